@@ -0,0 +1,106 @@
+//! Throughput of saving the entries of a classified chunk one by one compared
+//! to saving them in one batch, for the memory, SQLite and Postgres backends.
+//! Postgres is benchmarked if `TEST_POSTGRES_URL` is set.
+
+use std::{
+	env,
+	sync::atomic::{AtomicU64, Ordering},
+	time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use time::OffsetDateTime;
+use tokio::runtime::Runtime;
+use twitter_sentiment::{ConflictPolicy, EntryBatch, RetentionTarget, SentimentDB, TweetSentiment};
+
+/// Numbers of entries per chunk
+const CHUNK_SIZES: [u64; 2] = [16, 256];
+
+/// Chunks of entries of new tweets, so every entry is inserted
+#[derive(Debug)]
+struct Chunks {
+	/// Keyword unique to the run, as the Postgres database is kept between runs
+	keyword: String,
+	next_id: AtomicU64,
+}
+
+impl Chunks {
+	/// Entries of the next chunk
+	fn next(&self, size: u64) -> Vec<TweetSentiment> {
+		let first = self.next_id.fetch_add(size, Ordering::Relaxed);
+		(first..first + size)
+			.map(|id| {
+				let keyword = self.keyword.clone();
+				TweetSentiment::new(id, keyword, "twitter".to_owned(), 1_666_000_000, 0.5)
+			})
+			.collect()
+	}
+
+	/// Remove the saved entries, so the storage does not grow with the number
+	/// of iterations. Not included in the time.
+	async fn clear(&self, db: &SentimentDB) {
+		db.expire(&self.keyword, RetentionTarget::Raw, i64::MAX, false)
+			.await
+			.expect("Removing the entries failed!");
+	}
+
+	/// Time saving `iters` chunks entry by entry
+	async fn insert_single(&self, db: &SentimentDB, size: u64, iters: u64) -> Duration {
+		let mut elapsed = Duration::ZERO;
+		for _ in 0..iters {
+			let entries = self.next(size);
+			let start = Instant::now();
+			for entry in entries {
+				db.insert(entry, ConflictPolicy::Ignore).await.expect("Insert failed!");
+			}
+			elapsed += start.elapsed();
+			self.clear(db).await;
+		}
+		elapsed
+	}
+
+	/// Time saving `iters` chunks in one batch each
+	async fn insert_batch(&self, db: &SentimentDB, size: u64, iters: u64) -> Duration {
+		let mut elapsed = Duration::ZERO;
+		for _ in 0..iters {
+			let entries =
+				self.next(size).into_iter().map(|entry| (entry, ConflictPolicy::Ignore)).collect();
+			let batch = EntryBatch { entries, ..EntryBatch::default() };
+			let start = Instant::now();
+			db.insert_batch(batch).await.expect("Insert failed!");
+			elapsed += start.elapsed();
+			self.clear(db).await;
+		}
+		elapsed
+	}
+}
+
+fn insert_batch(c: &mut Criterion) {
+	let runtime = Runtime::new().expect("Creating the runtime failed!");
+	let dir = tempfile::tempdir().expect("Creating the directory failed!");
+	let sqlite_url = format!("sqlite://{}", dir.path().join("bench.db").display());
+	let mut backends = vec![("memory", "memory:".to_owned()), ("sqlite", sqlite_url)];
+	if let Ok(postgres_url) = env::var("TEST_POSTGRES_URL") {
+		backends.push(("postgres", postgres_url));
+	}
+
+	let keyword = format!("bench-{}", OffsetDateTime::now_utc().unix_timestamp_nanos());
+	let chunks = Chunks { keyword, next_id: AtomicU64::new(0) };
+	for (name, url) in backends {
+		let db = runtime.block_on(SentimentDB::connect(&url)).expect("Connecting failed!");
+		let mut group = c.benchmark_group(format!("insert_{}", name));
+		for size in CHUNK_SIZES {
+			group.throughput(Throughput::Elements(size));
+			group.bench_with_input(BenchmarkId::new("single", size), &size, |b, &size| {
+				b.to_async(&runtime).iter_custom(|iters| chunks.insert_single(&db, size, iters))
+			});
+			group.bench_with_input(BenchmarkId::new("batch", size), &size, |b, &size| {
+				b.to_async(&runtime).iter_custom(|iters| chunks.insert_batch(&db, size, iters))
+			});
+		}
+		group.finish();
+	}
+}
+
+criterion_group!(benches, insert_batch);
+criterion_main!(benches);
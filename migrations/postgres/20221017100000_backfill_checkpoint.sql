@@ -0,0 +1,7 @@
+CREATE TABLE backfill_checkpoint (
+	keyword VARCHAR(101) PRIMARY KEY,
+	oldest_id BIGINT,
+	newest_id BIGINT,
+	complete BOOLEAN NOT NULL DEFAULT FALSE,
+	updated BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE tweet_sentiment ADD COLUMN source VARCHAR(16) NOT NULL DEFAULT 'twitter';
+ALTER TABLE tweet_sentiment DROP CONSTRAINT tweet_sentiment_pkey;
+ALTER TABLE tweet_sentiment ADD PRIMARY KEY (keyword, source, id);
@@ -0,0 +1,6 @@
+CREATE TABLE tracked_keywords (
+	keyword VARCHAR(101) PRIMARY KEY,
+	rule TEXT NOT NULL,
+	paused BOOLEAN NOT NULL DEFAULT FALSE,
+	created BIGINT NOT NULL
+);
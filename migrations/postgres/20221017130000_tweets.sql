@@ -0,0 +1,16 @@
+CREATE TABLE tweets (
+	source VARCHAR(16) NOT NULL,
+	id BIGINT NOT NULL,
+	PRIMARY KEY (source, id),
+	created BIGINT NOT NULL,
+	text TEXT,
+	lang VARCHAR(16),
+	author_id BIGINT,
+	author_followers INTEGER,
+	retweet_count INTEGER,
+	like_count INTEGER,
+	reply_to BIGINT,
+	quote_of BIGINT,
+	retweet_of BIGINT,
+	client VARCHAR(255)
+);
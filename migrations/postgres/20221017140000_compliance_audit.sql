@@ -0,0 +1,12 @@
+CREATE TABLE compliance_audit (
+	id BIGSERIAL PRIMARY KEY,
+	source VARCHAR(16) NOT NULL,
+	kind VARCHAR(16) NOT NULL,
+	tweet_id BIGINT,
+	user_id BIGINT,
+	countries TEXT,
+	rows_affected BIGINT NOT NULL,
+	processed BIGINT NOT NULL
+);
+
+CREATE INDEX tweets_author ON tweets (source, author_id);
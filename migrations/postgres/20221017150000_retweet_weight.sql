@@ -0,0 +1,2 @@
+ALTER TABLE tweet_sentiment ADD COLUMN retweet BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE tweet_sentiment ADD COLUMN weight INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,7 @@
+ALTER TABLE tweet_sentiment ADD COLUMN lang VARCHAR(16);
+
+UPDATE tweet_sentiment SET lang = tweets.lang
+	FROM tweets
+	WHERE tweets.source = tweet_sentiment.source AND tweets.id = tweet_sentiment.id;
+
+UPDATE tweet_sentiment SET lang = 'en' WHERE lang IS NULL AND source = 'twitter';
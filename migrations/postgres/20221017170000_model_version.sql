@@ -0,0 +1 @@
+ALTER TABLE tweet_sentiment ADD COLUMN model_version INTEGER NOT NULL DEFAULT 1;
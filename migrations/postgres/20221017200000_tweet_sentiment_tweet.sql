@@ -0,0 +1 @@
+CREATE INDEX tweet_sentiment_tweet ON tweet_sentiment (source, id);
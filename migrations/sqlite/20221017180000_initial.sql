@@ -0,0 +1,59 @@
+CREATE TABLE tweet_sentiment (
+	id INTEGER NOT NULL,
+	keyword VARCHAR(101) NOT NULL,
+	source VARCHAR(16) NOT NULL DEFAULT 'twitter',
+	created INTEGER NOT NULL,
+	sentiment DOUBLE PRECISION NOT NULL,
+	retweet BOOLEAN NOT NULL DEFAULT FALSE,
+	weight INTEGER NOT NULL DEFAULT 1,
+	lang VARCHAR(16),
+	model_version INTEGER NOT NULL DEFAULT 1,
+	PRIMARY KEY (keyword, source, id)
+);
+
+CREATE INDEX tweet_sentiment_tweet ON tweet_sentiment (source, id);
+
+CREATE TABLE backfill_checkpoint (
+	keyword VARCHAR(101) PRIMARY KEY,
+	oldest_id INTEGER,
+	newest_id INTEGER,
+	complete BOOLEAN NOT NULL DEFAULT FALSE,
+	updated INTEGER NOT NULL
+);
+
+CREATE TABLE tracked_keywords (
+	keyword VARCHAR(101) PRIMARY KEY,
+	rule TEXT NOT NULL,
+	paused BOOLEAN NOT NULL DEFAULT FALSE,
+	created INTEGER NOT NULL
+);
+
+CREATE TABLE tweets (
+	source VARCHAR(16) NOT NULL,
+	id INTEGER NOT NULL,
+	created INTEGER NOT NULL,
+	text TEXT,
+	lang VARCHAR(16),
+	author_id INTEGER,
+	author_followers INTEGER,
+	retweet_count INTEGER,
+	like_count INTEGER,
+	reply_to INTEGER,
+	quote_of INTEGER,
+	retweet_of INTEGER,
+	client VARCHAR(255),
+	PRIMARY KEY (source, id)
+);
+
+CREATE INDEX tweets_author ON tweets (source, author_id);
+
+CREATE TABLE compliance_audit (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	source VARCHAR(16) NOT NULL,
+	kind VARCHAR(16) NOT NULL,
+	tweet_id INTEGER,
+	user_id INTEGER,
+	countries TEXT,
+	rows_affected INTEGER NOT NULL,
+	processed INTEGER NOT NULL
+);
@@ -0,0 +1,15 @@
+CREATE TABLE sentiment_rollup (
+	keyword VARCHAR(101) NOT NULL,
+	resolution VARCHAR(8) NOT NULL,
+	bucket INTEGER NOT NULL,
+	count INTEGER NOT NULL,
+	sum DOUBLE PRECISION NOT NULL,
+	sum_squares DOUBLE PRECISION NOT NULL,
+	min DOUBLE PRECISION NOT NULL,
+	max DOUBLE PRECISION NOT NULL,
+	positive INTEGER NOT NULL,
+	negative INTEGER NOT NULL,
+	PRIMARY KEY (keyword, resolution, bucket)
+);
+
+CREATE INDEX tweet_sentiment_created ON tweet_sentiment (created);
@@ -0,0 +1,26 @@
+CREATE TABLE keywords (
+	keyword VARCHAR(101) PRIMARY KEY,
+	display_name TEXT,
+	description TEXT,
+	rule TEXT,
+	status VARCHAR(8) NOT NULL DEFAULT 'active',
+	tags TEXT NOT NULL DEFAULT '',
+	created INTEGER NOT NULL,
+	first_seen INTEGER,
+	last_seen INTEGER
+);
+
+INSERT INTO keywords (keyword, rule, status, created)
+	SELECT keyword, rule, CASE WHEN paused THEN 'paused' ELSE 'active' END, created
+	FROM tracked_keywords;
+
+INSERT INTO keywords (keyword, created, first_seen, last_seen)
+	SELECT keyword, MIN(created), MIN(created), MAX(created)
+	FROM tweet_sentiment
+	WHERE TRUE
+	GROUP BY keyword
+	ON CONFLICT (keyword) DO UPDATE SET
+		first_seen = EXCLUDED.first_seen,
+		last_seen = EXCLUDED.last_seen;
+
+DROP TABLE tracked_keywords;
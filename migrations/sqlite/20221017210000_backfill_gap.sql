@@ -0,0 +1 @@
+ALTER TABLE backfill_checkpoint ADD COLUMN gap_since_id INTEGER;
@@ -0,0 +1 @@
+CREATE INDEX tweet_sentiment_keyword_created ON tweet_sentiment (keyword, created);
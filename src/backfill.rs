@@ -0,0 +1,456 @@
+//! Backfill of historical tweets for keywords via the search API
+
+use std::sync::Arc;
+
+use color_eyre::Result;
+use derive_builder::Builder;
+use egg_mode::{
+	raw::{self, ParamList},
+	search::SearchResult,
+	Token,
+};
+use time::OffsetDateTime;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+	classifier::sentiment_to_float,
+	database::{BackfillCheckpoint, EntryBatch, SentimentDB, StoredTweet, TweetSentiment},
+	matcher::{CompiledRule, KeywordMatcher},
+	settings::{BackfillSettings, ConflictPolicy},
+	source::SourceTweet,
+	SentimentClassifier,
+};
+
+/// Twitter's epoch of tweet ID snowflakes in milliseconds
+const TWITTER_EPOCH_MS: i128 = 1_288_834_974_657;
+/// Path of the v1.1 search API
+const SEARCH_PATH: &str = "/1.1/search/tweets.json";
+
+/// Backfills keywords with historical tweets from the search API. Pages through
+/// the results from new to old until the configured date is reached and keeps
+/// a checkpoint per keyword in the database to resume after restarts. On each
+/// start, the tweets after the newest backfilled or streamed tweet are searched
+/// to fill the gap of the time the app was not running.
+#[derive(Debug, Clone, Builder)]
+pub struct Backfiller {
+	config: BackfillSettings,
+	/// Whether to store the texts of the tweets
+	store_text: bool,
+	/// How entries of tweets that are stored already are handled
+	on_conflict: ConflictPolicy,
+	token: Token,
+	sentiment_classifier: SentimentClassifier,
+	db: Arc<SentimentDB>,
+}
+
+impl Backfiller {
+	/// Get a builder to create an instance.
+	pub fn builder() -> BackfillerBuilder {
+		BackfillerBuilder::default()
+	}
+
+	/// Backfill all keywords of the matcher one after another. Errors are
+	/// logged and the next keyword is backfilled, the failed one resumes on
+	/// the next run.
+	#[tracing::instrument(level = "debug", skip_all)]
+	pub async fn run(&self, matcher: &KeywordMatcher) {
+		// Only search tweets from before the start, the stream gets the newer ones.
+		let start_id = tweet_id_at(OffsetDateTime::now_utc());
+		for rule in matcher.rules() {
+			if let Err(err) = self.backfill_keyword(rule, start_id).await {
+				error!("Backfill of keyword `{}` failed: {}", rule.keyword(), err);
+			}
+		}
+	}
+
+	/// Fill the gap since the newest retrieved tweet of the keyword of a rule,
+	/// then backfill it starting at its checkpoint.
+	#[tracing::instrument(level = "debug", err, skip(self, rule), fields(keyword = rule.keyword()))]
+	async fn backfill_keyword(&self, rule: &CompiledRule, start_id: u64) -> Result<()> {
+		let keyword = rule.keyword();
+		let mut checkpoint =
+			self.db.checkpoint(keyword).await?.unwrap_or_else(|| BackfillCheckpoint::new(keyword));
+		let since = self.config.since.midnight().assume_utc().unix_timestamp();
+
+		// An interrupted gap is filled again from its start, as the stream might
+		// have saved newer IDs meanwhile.
+		let gap_since_id = checkpoint.gap_since_id.or(checkpoint.newest_id);
+		if let Some(gap_since_id) = gap_since_id.filter(|id| (*id as u64) < start_id) {
+			checkpoint.gap_since_id = Some(gap_since_id);
+			self.db.save_checkpoint(&checkpoint).await?;
+			let mut count = 0;
+			let mut max_id = start_id;
+			loop {
+				let page = self
+					.search_page(&rule.search_query(), Some(gap_since_id as u64), max_id)
+					.await?;
+				max_id = match page.iter().map(|tweet| tweet.id).min() {
+					Some(oldest) => oldest - 1,
+					None => break,
+				};
+				checkpoint.update(&page);
+				count += self.save_page(rule, page, since).await?;
+				self.db.save_checkpoint(&checkpoint).await?;
+			}
+			checkpoint.gap_since_id = None;
+			self.db.save_checkpoint(&checkpoint).await?;
+			info!("Filled gap of keyword `{}` with {} tweets.", keyword, count);
+		}
+
+		if checkpoint.complete {
+			return Ok(());
+		}
+		info!("Backfilling keyword `{}`.", keyword);
+
+		let mut count = 0;
+		while !checkpoint.complete {
+			let max_id = checkpoint.oldest_id.map_or(start_id, |id| id as u64 - 1);
+			let page = self.search_page(&rule.search_query(), None, max_id).await?;
+
+			let reached_end = page.is_empty() || page.iter().any(|tweet| tweet.created_at < since);
+			checkpoint.update(&page);
+			count += self.save_page(rule, page, since).await?;
+			checkpoint.complete = reached_end;
+			self.db.save_checkpoint(&checkpoint).await?;
+		}
+
+		info!("Backfill of keyword `{}` complete with {} tweets.", keyword, count);
+		Ok(())
+	}
+
+	/// Save the tweets of a page of search results that match the rule and are
+	/// not older than `since`. Returns the number of saved tweets.
+	async fn save_page(
+		&self,
+		rule: &CompiledRule,
+		page: Vec<SourceTweet>,
+		since: i64,
+	) -> Result<usize> {
+		let tweets: Vec<SourceTweet> = page
+			.into_iter()
+			.filter(|tweet| {
+				tweet.created_at >= since
+					&& rule.tracks_lang(tweet.lang.as_deref())
+					&& rule.matches(&tweet.text)
+			})
+			.collect();
+		self.save_tweets(rule.keyword(), &tweets).await?;
+		Ok(tweets.len())
+	}
+
+	/// Retrieve a page of search results with tweets up to the given ID and
+	/// after `since_id` if given. Waits for the rate limit window to reset when
+	/// the limit is reached.
+	async fn search_page(
+		&self,
+		query: &str,
+		since_id: Option<u64>,
+		max_id: u64,
+	) -> Result<Vec<SourceTweet>> {
+		let url = format!("{}{}", self.config.base_url, SEARCH_PATH);
+		loop {
+			let params = ParamList::new()
+				.extended_tweets()
+				.add_param("q", query.to_owned())
+				.add_param("result_type", "recent")
+				.add_param("count", self.config.page_size.to_string())
+				.add_param("max_id", max_id.to_string())
+				.add_opt_param("since_id", since_id.map(|id| id.to_string()));
+			let request = raw::request_get(&url, &self.token, Some(&params));
+			let result = raw::response_json::<SearchResult>(request).await;
+
+			match result {
+				Ok(response) => {
+					if response.rate_limit_status.remaining <= 0 {
+						wait_for_reset(response.rate_limit_status.reset).await;
+					}
+					let tweets = response.response.statuses.into_iter().map(Into::into).collect();
+					return Ok(tweets);
+				}
+				Err(egg_mode::error::Error::RateLimit(reset)) => wait_for_reset(reset).await,
+				Err(err) => return Err(err.into()),
+			}
+		}
+	}
+
+	/// Classify the tweets and save them for the keyword
+	async fn save_tweets(&self, keyword: &str, tweets: &[SourceTweet]) -> Result<()> {
+		if tweets.is_empty() {
+			return Ok(());
+		}
+
+		let texts = tweets.iter().map(|tweet| (tweet.text.clone(), tweet.lang.clone())).collect();
+		let sentiments = self.sentiment_classifier.predict_lang(texts).await?;
+		let mut batch = EntryBatch::default();
+		for (tweet, sentiment) in tweets.iter().zip(sentiments) {
+			batch.tweets.push(StoredTweet::new("twitter".to_owned(), tweet, self.store_text));
+
+			let mut entry = TweetSentiment::new(
+				tweet.id,
+				keyword.to_owned(),
+				"twitter".to_owned(),
+				tweet.created_at,
+				sentiment_to_float(&sentiment),
+			);
+			entry.lang = tweet.lang.clone();
+			entry.model_version = self.sentiment_classifier.model_version();
+			batch.entries.push((entry, self.on_conflict));
+		}
+		let duplicates = self.db.insert_batch(batch).await?.duplicates();
+		if duplicates > 0 {
+			debug!("{} backfilled entries were stored already.", duplicates);
+		}
+		Ok(())
+	}
+}
+
+/// Sleep until the rate limit window resets at the given UNIX timestamp.
+async fn wait_for_reset(reset: i32) {
+	let now = OffsetDateTime::now_utc().unix_timestamp();
+	let secs = (i64::from(reset) - now).max(1) as u64;
+	warn!("Search rate limit reached, waiting {} seconds.", secs);
+	tokio::time::sleep(std::time::Duration::from_secs(secs)).await;
+}
+
+/// Compute the tweet ID (snowflake) of the first tweet created at the given
+/// time.
+fn tweet_id_at(time: OffsetDateTime) -> u64 {
+	let millis = time.unix_timestamp_nanos() / 1_000_000;
+	((millis - TWITTER_EPOCH_MS).max(0) as u64) << 22
+}
+
+#[cfg(test)]
+mod tests {
+	use egg_mode::KeyPair;
+	use serde_json::{json, Value};
+	use time::{macros::format_description, Duration};
+	use wiremock::{
+		matchers::{method, path, query_param, query_param_is_missing},
+		Mock, MockServer, ResponseTemplate,
+	};
+
+	use super::*;
+	use crate::{database::EntryFilter, settings::KeywordRule};
+
+	/// Start of the day to backfill to
+	const SINCE: i64 = 1_665_964_800;
+
+	fn backfiller(server: &MockServer, db: Arc<SentimentDB>) -> Result<Backfiller> {
+		let config = serde_json::from_value(json!({
+			"since": "2022-10-17",
+			"page_size": 2,
+			"base_url": server.uri(),
+		}))?;
+		let token = Token::Access {
+			consumer: KeyPair::new("key", "secret"),
+			access: KeyPair::new("token", "token-secret"),
+		};
+		let backfiller = Backfiller::builder()
+			.config(config)
+			.store_text(false)
+			.on_conflict(ConflictPolicy::Ignore)
+			.token(token)
+			.sentiment_classifier(SentimentClassifier::fake())
+			.db(db)
+			.build()?;
+		Ok(backfiller)
+	}
+
+	fn matcher() -> Result<KeywordMatcher> {
+		KeywordMatcher::new(&[KeywordRule::new("rust".to_owned())])
+	}
+
+	/// Search result tweet in the v1.1 format
+	fn search_tweet(id: u64, created: i64) -> Result<Value> {
+		let format = format_description!(
+			"[weekday repr:short] [month repr:short] [day] [hour]:[minute]:[second] +0000 [year]"
+		);
+		let created_at = OffsetDateTime::from_unix_timestamp(created)?.format(format)?;
+		Ok(json!({
+			"created_at": created_at,
+			"id": id,
+			"full_text": "Rust is good",
+			"lang": "en",
+			"entities": { "hashtags": [], "symbols": [], "urls": [], "user_mentions": [] },
+			"favorite_count": 0,
+			"retweet_count": 0,
+			"source": "<a href=\"https://twitter.com\" rel=\"nofollow\">Twitter Web App</a>",
+			"truncated": false,
+		}))
+	}
+
+	/// Page of search results with the tweets' IDs and creation times
+	fn search_page(tweets: &[(u64, i64)]) -> Result<ResponseTemplate> {
+		let statuses = tweets
+			.iter()
+			.map(|(id, created)| search_tweet(*id, *created))
+			.collect::<Result<Vec<_>>>()?;
+		let body = json!({
+			"statuses": statuses,
+			"search_metadata": {
+				"completed_in": 0.01,
+				"max_id": tweets.iter().map(|(id, _)| *id).max().unwrap_or_default(),
+				"query": "rust",
+				"count": tweets.len(),
+				"since_id": 0,
+			},
+		});
+		let reset = OffsetDateTime::now_utc().unix_timestamp() + 900;
+		Ok(ResponseTemplate::new(200)
+			.insert_header("x-rate-limit-limit", "180")
+			.insert_header("x-rate-limit-remaining", "179")
+			.insert_header("x-rate-limit-reset", reset.to_string().as_str())
+			.set_body_json(body))
+	}
+
+	/// IDs of the saved entries
+	async fn saved_ids(db: &SentimentDB) -> Result<Vec<i64>> {
+		let entries = db.get("rust", &EntryFilter::default()).await?;
+		Ok(entries.iter().map(|entry| entry.id).collect())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn fills_the_gap_down_to_the_newest_id() -> Result<()> {
+		let server = MockServer::start().await;
+		let db = Arc::new(SentimentDB::in_memory());
+		let checkpoint = BackfillCheckpoint {
+			newest_id: Some(100),
+			complete: true,
+			..BackfillCheckpoint::new("rust")
+		};
+		db.save_checkpoint(&checkpoint).await?;
+
+		// Pages from new to old after the newest ID, until a page is empty
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("since_id", "100"))
+			.and(query_param("max_id", "149"))
+			.respond_with(search_page(&[])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("since_id", "100"))
+			.and(query_param("q", "rust lang:en"))
+			.respond_with(search_page(&[(200, SINCE + 20), (150, SINCE + 10)])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+		// The backfill itself is complete already.
+		Mock::given(method("GET"))
+			.and(query_param_is_missing("since_id"))
+			.respond_with(search_page(&[])?)
+			.expect(0)
+			.mount(&server)
+			.await;
+
+		backfiller(&server, db.clone())?.run(&matcher()?).await;
+		assert_eq!(saved_ids(&db).await?, vec![150, 200]);
+		let checkpoint = db.checkpoint("rust").await?.expect("Checkpoint missing");
+		assert_eq!((checkpoint.newest_id, checkpoint.gap_since_id), (Some(200), None));
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn resumes_from_the_oldest_id() -> Result<()> {
+		let server = MockServer::start().await;
+		let db = Arc::new(SentimentDB::in_memory());
+		let checkpoint =
+			BackfillCheckpoint { oldest_id: Some(500), ..BackfillCheckpoint::new("rust") };
+		db.save_checkpoint(&checkpoint).await?;
+
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("max_id", "499"))
+			.and(query_param_is_missing("since_id"))
+			.respond_with(search_page(&[(450, SINCE + 50), (420, SINCE + 40)])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("max_id", "419"))
+			.respond_with(search_page(&[])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+		// Nothing newer than the checkpoint is searched again.
+		Mock::given(method("GET")).respond_with(search_page(&[])?).expect(0).mount(&server).await;
+
+		backfiller(&server, db.clone())?.run(&matcher()?).await;
+		assert_eq!(saved_ids(&db).await?, vec![420, 450]);
+		let checkpoint = db.checkpoint("rust").await?.expect("Checkpoint missing");
+		assert_eq!((checkpoint.oldest_id, checkpoint.complete), (Some(420), true));
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn completes_when_since_is_crossed() -> Result<()> {
+		let server = MockServer::start().await;
+		let db = Arc::new(SentimentDB::in_memory());
+
+		// The second page reaches tweets from before `since`, which are not
+		// saved, and no further page is requested.
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("max_id", "299"))
+			.respond_with(search_page(&[(290, SINCE + 10), (280, SINCE - 10)])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param_is_missing("since_id"))
+			.respond_with(search_page(&[(400, SINCE + 40), (300, SINCE + 30)])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+
+		let backfiller = backfiller(&server, db.clone())?;
+		backfiller.run(&matcher()?).await;
+		assert_eq!(saved_ids(&db).await?, vec![290, 300, 400]);
+		let checkpoint = db.checkpoint("rust").await?.expect("Checkpoint missing");
+		assert!(checkpoint.complete);
+		assert_eq!((checkpoint.oldest_id, checkpoint.newest_id), (Some(280), Some(400)));
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn waits_for_the_rate_limit_reset() -> Result<()> {
+		let server = MockServer::start().await;
+		let db = Arc::new(SentimentDB::in_memory());
+
+		let reset = OffsetDateTime::now_utc().unix_timestamp();
+		let rate_limited = ResponseTemplate::new(429)
+			.insert_header("x-rate-limit-reset", reset.to_string().as_str())
+			.set_body_json(json!({ "errors": [{ "code": 88, "message": "Rate limit exceeded" }] }));
+		Mock::given(method("GET"))
+			.respond_with(rate_limited)
+			.up_to_n_times(1)
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.respond_with(search_page(&[(400, SINCE + 40)])?)
+			.up_to_n_times(1)
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("GET"))
+			.and(path(SEARCH_PATH))
+			.and(query_param("max_id", "399"))
+			.respond_with(search_page(&[])?)
+			.expect(1)
+			.mount(&server)
+			.await;
+
+		let start = OffsetDateTime::now_utc();
+		backfiller(&server, db.clone())?.run(&matcher()?).await;
+		assert!(OffsetDateTime::now_utc() - start >= Duration::seconds(1));
+		assert_eq!(saved_ids(&db).await?, vec![400]);
+		assert!(db.checkpoint("rust").await?.expect("Checkpoint missing").complete);
+		Ok(())
+	}
+}
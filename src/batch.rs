@@ -0,0 +1,155 @@
+//! Time-bounded batching of streams
+
+use std::mem;
+
+use futures::{stream, Stream, StreamExt};
+use tokio::time::{self, Duration, Instant};
+
+/// State of a batching stream
+struct BatchState<S, T, E> {
+	stream: S,
+	batch: Vec<T>,
+	/// Time the current batch has to be flushed at, set with its first item
+	deadline: Option<Instant>,
+	/// Error to return after flushing the current batch
+	error: Option<E>,
+	done: bool,
+}
+
+/// Group the items of a stream into batches. A batch is flushed when it has
+/// `size` items or when `max_latency` passed since its first item, so items
+/// of quiet streams do not wait for the batch to fill. An error is returned
+/// after flushing the items before it and ends the stream.
+pub fn batches<S, T, E>(
+	stream: S,
+	size: usize,
+	max_latency: Duration,
+) -> impl Stream<Item = Result<Vec<T>, E>>
+where
+	S: Stream<Item = Result<T, E>> + Unpin,
+{
+	let size = size.max(1);
+	let state = BatchState { stream, batch: Vec::new(), deadline: None, error: None, done: false };
+	stream::unfold(state, move |mut state| async move {
+		if let Some(err) = state.error.take() {
+			state.done = true;
+			return Some((Err(err), state));
+		}
+
+		while !state.done && state.batch.len() < size {
+			let next = match state.deadline {
+				Some(deadline) => match time::timeout_at(deadline, state.stream.next()).await {
+					Ok(next) => next,
+					Err(_elapsed) => break,
+				},
+				None => state.stream.next().await,
+			};
+			match next {
+				Some(Ok(item)) => {
+					if state.batch.is_empty() {
+						state.deadline = Some(Instant::now() + max_latency);
+					}
+					state.batch.push(item);
+				}
+				Some(Err(err)) => {
+					state.error = Some(err);
+					break;
+				}
+				None => state.done = true,
+			}
+		}
+
+		state.deadline = None;
+		if state.batch.is_empty() {
+			return state.error.take().map(|err| {
+				state.done = true;
+				(Err(err), state)
+			});
+		}
+		let batch = mem::take(&mut state.batch);
+		Some((Ok(batch), state))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::channel::mpsc;
+
+	use super::*;
+
+	type Item = Result<u32, String>;
+
+	/// Batches of a channel's items, with up to 2 items per batch and a latency
+	/// of one second
+	fn channel_batches(
+	) -> (mpsc::UnboundedSender<Item>, impl Stream<Item = Result<Vec<u32>, String>>) {
+		let (sender, receiver) = mpsc::unbounded();
+		(sender, Box::pin(batches(receiver, 2, Duration::from_secs(1))))
+	}
+
+	#[tokio::test]
+	async fn flushes_full_batches_immediately() {
+		time::pause();
+		let start = Instant::now();
+		let (sender, batches) = channel_batches();
+		futures::pin_mut!(batches);
+		for item in 1..=4 {
+			sender.unbounded_send(Ok(item)).expect("Sending failed");
+		}
+
+		assert_eq!(batches.next().await, Some(Ok(vec![1, 2])));
+		assert_eq!(batches.next().await, Some(Ok(vec![3, 4])));
+		assert_eq!(start.elapsed(), Duration::ZERO);
+	}
+
+	#[tokio::test]
+	async fn flushes_partial_batches_after_the_latency() {
+		time::pause();
+		let start = Instant::now();
+		let (sender, batches) = channel_batches();
+		futures::pin_mut!(batches);
+		sender.unbounded_send(Ok(1)).expect("Sending failed");
+		let late_sender = sender.clone();
+		tokio::spawn(async move {
+			time::sleep(Duration::from_millis(1500)).await;
+			late_sender.unbounded_send(Ok(2)).expect("Sending failed");
+		});
+
+		// The latency counts from the first item of each batch. Timers have a
+		// resolution of a millisecond.
+		assert_eq!(batches.next().await, Some(Ok(vec![1])));
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_millis(1010));
+		assert_eq!(batches.next().await, Some(Ok(vec![2])));
+		let elapsed = start.elapsed();
+		assert!(elapsed >= Duration::from_millis(2500) && elapsed < Duration::from_millis(2510));
+	}
+
+	#[tokio::test]
+	async fn flushes_the_last_batch_when_the_stream_ends() {
+		time::pause();
+		let start = Instant::now();
+		let (sender, batches) = channel_batches();
+		futures::pin_mut!(batches);
+		sender.unbounded_send(Ok(1)).expect("Sending failed");
+		drop(sender);
+
+		assert_eq!(batches.next().await, Some(Ok(vec![1])));
+		assert_eq!(batches.next().await, None);
+		assert_eq!(start.elapsed(), Duration::ZERO);
+	}
+
+	#[tokio::test]
+	async fn flushes_the_items_before_an_error() {
+		time::pause();
+		let (sender, batches) = channel_batches();
+		futures::pin_mut!(batches);
+		sender.unbounded_send(Ok(1)).expect("Sending failed");
+		sender.unbounded_send(Err("broken".to_owned())).expect("Sending failed");
+		sender.unbounded_send(Ok(2)).expect("Sending failed");
+
+		assert_eq!(batches.next().await, Some(Ok(vec![1])));
+		assert_eq!(batches.next().await, Some(Err("broken".to_owned())));
+		assert_eq!(batches.next().await, None);
+	}
+}
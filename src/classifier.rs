@@ -1,45 +1,120 @@
 //! Sentiment classification module
 
 use std::{
+	path::PathBuf,
 	sync::mpsc,
 	thread::{self, JoinHandle},
 };
 
-use color_eyre::{eyre::eyre, Result};
-use rust_bert::pipelines::sentiment::{Sentiment, SentimentConfig, SentimentModel};
+use color_eyre::{
+	eyre::{bail, eyre},
+	Result,
+};
+use rust_bert::{
+	pipelines::{
+		common::ModelType,
+		sentiment::{Sentiment, SentimentConfig, SentimentModel, SentimentPolarity},
+	},
+	resources::{LocalResource, RemoteResource, Resource},
+};
 use tokio::{sync::oneshot, task};
 use tracing::{info, warn};
 
-/// Message type for internal channel, passing around texts and return value
-/// senders
-type Message = (Vec<String>, oneshot::Sender<Vec<Sentiment>>);
+use crate::settings::{ClassifierSettings, ModelSettings};
+
+/// Message type for internal channel, passing around texts with their
+/// language and return value senders
+type Message = (Vec<(String, Option<String>)>, oneshot::Sender<Vec<Sentiment>>);
+
+/// Convert a sentiment to a float between -1 (negative) and 1 (positive)
+pub fn sentiment_to_float(sentiment: &Sentiment) -> f64 {
+	match sentiment.polarity {
+		SentimentPolarity::Positive => sentiment.score,
+		SentimentPolarity::Negative => -sentiment.score,
+	}
+}
 
 /// Runner for sentiment classification
 #[derive(Debug, Clone)]
 pub struct SentimentClassifier {
 	sender: mpsc::SyncSender<Message>,
+	model_version: i32,
 }
 
 impl SentimentClassifier {
 	/// Spawn a classifier on a separate thread and return a classifier instance
 	/// to interact with it
-	pub fn spawn() -> (JoinHandle<Result<()>>, SentimentClassifier) {
+	pub fn spawn(settings: ClassifierSettings) -> (JoinHandle<Result<()>>, SentimentClassifier) {
 		let (sender, receiver) = mpsc::sync_channel(10);
-		let handle = thread::spawn(move || Self::runner(receiver));
-		(handle, SentimentClassifier { sender })
+		let model_version = settings.model_version;
+		let handle = thread::spawn(move || Self::runner(settings, receiver));
+		(handle, SentimentClassifier { sender, model_version })
+	}
+
+	/// Classifier without models for tests: texts containing "good" are
+	/// positive, all others negative. Predictions including a text containing
+	/// "unclassifiable" fail.
+	#[cfg(test)]
+	pub(crate) fn fake() -> SentimentClassifier {
+		let (sender, receiver) = mpsc::sync_channel::<Message>(10);
+		thread::spawn(move || {
+			while let Ok((texts, sender)) = receiver.recv() {
+				if texts.iter().any(|(text, _)| text.contains("unclassifiable")) {
+					continue;
+				}
+				let sentiments = texts
+					.iter()
+					.map(|(text, _)| {
+						let polarity = match text.contains("good") {
+							true => SentimentPolarity::Positive,
+							false => SentimentPolarity::Negative,
+						};
+						Sentiment { polarity, score: 0.9 }
+					})
+					.collect();
+				let _ = sender.send(sentiments);
+			}
+		});
+		SentimentClassifier { sender, model_version: 1 }
+	}
+
+	/// Version of the configured models, to store with the entries
+	pub fn model_version(&self) -> i32 {
+		self.model_version
 	}
 
 	/// The classification runner itself
 	#[tracing::instrument(level = "debug", err, skip_all)]
-	fn runner(receiver: mpsc::Receiver<Message>) -> Result<()> {
+	fn runner(settings: ClassifierSettings, receiver: mpsc::Receiver<Message>) -> Result<()> {
 		info!("Sentiment classifier runner starting.");
 		// Needs to be in sync runtime, async doesn't work
-		let model = SentimentModel::new(SentimentConfig::default())?;
+		let default_model = SentimentModel::new(SentimentConfig::default())?;
+		let mut models = Vec::with_capacity(settings.models.len());
+		for model in &settings.models {
+			info!("Loading sentiment model for languages {:?}.", model.languages);
+			models.push((&model.languages, SentimentModel::new(model_config(model)?)?));
+		}
 
 		while let Ok((texts, sender)) = receiver.recv() {
-			let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
-			let sentiments = model.predict(texts);
-			let res = sender.send(sentiments);
+			// Group the texts by model and put the results back in order
+			let mut sentiments: Vec<Option<Sentiment>> = texts.iter().map(|_| None).collect();
+			for index in 0..=models.len() {
+				let (positions, group): (Vec<usize>, Vec<&str>) = texts
+					.iter()
+					.enumerate()
+					.filter(|(_, (_, lang))| model_index(&models, lang.as_deref()) == index)
+					.map(|(position, (text, _))| (position, text.as_str()))
+					.unzip();
+				if group.is_empty() {
+					continue;
+				}
+				let model = models.get(index).map_or(&default_model, |(_, model)| model);
+				for (position, sentiment) in positions.into_iter().zip(model.predict(group)) {
+					sentiments[position] = Some(sentiment);
+				}
+			}
+
+			let res = sender.send(sentiments.into_iter().flatten().collect());
 			if let Err(_lost) = res {
 				warn!("Sending sentiments results failed, receiver was closed!");
 			}
@@ -49,12 +124,63 @@ impl SentimentClassifier {
 		Ok(())
 	}
 
-	/// Make the runner predict a sample and return the result
+	/// Make the runner predict a sample with the default model and return the
+	/// result
 	#[tracing::instrument(level = "debug", err, skip_all)]
 	pub async fn predict(&self, texts: Vec<String>) -> Result<Vec<Sentiment>> {
+		self.predict_lang(texts.into_iter().map(|text| (text, None)).collect()).await
+	}
+
+	/// Make the runner predict a sample of texts with their languages, using
+	/// the model configured for each language, and return the result
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn predict_lang(
+		&self,
+		texts: Vec<(String, Option<String>)>,
+	) -> Result<Vec<Sentiment>> {
 		let (sender, receiver) = oneshot::channel();
 		task::block_in_place(|| self.sender.send((texts, sender)))
 			.map_err(|err| eyre!("Sending mpsc message failed: {}", err))?;
 		Ok(receiver.await?)
 	}
 }
+
+/// Index of the model for a language. The default model has the index after
+/// the configured models.
+fn model_index(models: &[(&Vec<String>, SentimentModel)], lang: Option<&str>) -> usize {
+	lang.and_then(|lang| {
+		models.iter().position(|(languages, _)| {
+			languages.iter().any(|language| language.eq_ignore_ascii_case(lang))
+		})
+	})
+	.unwrap_or(models.len())
+}
+
+/// Sentiment model configuration from the settings
+fn model_config(settings: &ModelSettings) -> Result<SentimentConfig> {
+	let model_type = match settings.model_type.to_lowercase().as_str() {
+		"bert" => ModelType::Bert,
+		"distilbert" => ModelType::DistilBert,
+		"roberta" => ModelType::Roberta,
+		"xlmroberta" => ModelType::XLMRoberta,
+		other => bail!("Unsupported sentiment model type `{}`", other),
+	};
+	let cache_dir = format!("sentiment-{}", settings.languages.join("-"));
+	let resource = |location: &str| {
+		if location.starts_with("http://") || location.starts_with("https://") {
+			Resource::Remote(RemoteResource::new(location, &cache_dir))
+		} else {
+			Resource::Local(LocalResource { local_path: PathBuf::from(location) })
+		}
+	};
+	Ok(SentimentConfig::new(
+		model_type,
+		resource(&settings.model),
+		resource(&settings.config),
+		resource(&settings.vocab),
+		settings.merges.as_deref().map(resource),
+		settings.lower_case,
+		None,
+		None,
+	))
+}
@@ -0,0 +1,164 @@
+//! Command line interface: commands to run instead of the app
+
+use std::{path::PathBuf, sync::Arc};
+
+use clap::{Args, Parser, Subcommand};
+use color_eyre::{eyre::eyre, Result};
+use tokio::{fs::File, io};
+use tracing::info;
+
+use crate::{
+	data,
+	database::Resolution,
+	export::{self, ExportFormat, ExportRequest},
+	import::{ImportFormat, Importer},
+	settings::ConflictPolicy,
+	SentimentClassifier, SentimentDB, Settings,
+};
+
+/// Sentiment analysis of tweets. Runs the app if no command is given.
+#[derive(Debug, Parser)]
+#[clap(version, about)]
+pub struct Cli {
+	/// Command to run instead of the app
+	#[clap(subcommand)]
+	pub command: Option<Command>,
+}
+
+/// Command to run instead of the app
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// Export the raw entries or the rollups of a keyword
+	Export(ExportArgs),
+	/// Import historical entries of a keyword from a CSV or JSON Lines file
+	Import(ImportArgs),
+}
+
+impl Command {
+	/// Run the command
+	pub async fn run(self, db: Arc<SentimentDB>, config: &Settings) -> Result<()> {
+		match self {
+			Command::Export(args) => args.run(db).await,
+			Command::Import(args) => args.run(db, config).await,
+		}
+	}
+}
+
+/// Arguments of the export command
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+	/// Keyword to export the data of
+	keyword: String,
+	/// File format: csv, jsonl or parquet
+	#[clap(long, default_value = "csv")]
+	format: ExportFormat,
+	/// Export the rollups of this resolution (minute, hour or day) instead of
+	/// the raw entries
+	#[clap(long)]
+	resolution: Option<Resolution>,
+	/// Only data since this time, UNIX timestamp or RFC 3339
+	#[clap(long, parse(try_from_str = parse_time))]
+	from: Option<i64>,
+	/// Only data before this time, UNIX timestamp or RFC 3339
+	#[clap(long, parse(try_from_str = parse_time))]
+	to: Option<i64>,
+	/// File to write to, stdout if not given
+	#[clap(short, long)]
+	output: Option<PathBuf>,
+}
+
+impl ExportArgs {
+	/// Export the data to the output
+	async fn run(self, db: Arc<SentimentDB>) -> Result<()> {
+		let keyword = self.keyword.to_lowercase();
+		let keyword = db
+			.keyword(&keyword)
+			.await?
+			.ok_or_else(|| eyre!("Keyword `{}` does not exist!", keyword))?;
+		let request = ExportRequest {
+			format: self.format,
+			resolution: self.resolution,
+			from: self.from,
+			to: self.to,
+		};
+		let chunks = export::export(db, &keyword, &request)?;
+		let written = match &self.output {
+			Some(path) => export::write_to(chunks, &mut File::create(path).await?).await?,
+			None => export::write_to(chunks, &mut io::stdout()).await?,
+		};
+		info!("Exported {} bytes of keyword `{}`.", written, keyword.keyword);
+		Ok(())
+	}
+}
+
+/// Arguments of the import command
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+	/// Keyword to store the entries under
+	keyword: String,
+	/// CSV or JSON Lines file with the fields `id`, `created` (UNIX timestamp
+	/// or RFC 3339) and `sentiment` or `text`, optionally `lang`
+	file: PathBuf,
+	/// File format: csv or jsonl, by default from the file extension
+	#[clap(long)]
+	format: Option<ImportFormat>,
+	/// Classify the texts of records without sentiment with the configured
+	/// models
+	#[clap(long)]
+	classify: bool,
+	/// Source name stored with the entries
+	#[clap(long, default_value = "import")]
+	source: String,
+	/// Handling of entries that are stored already: ignore, overwrite or
+	/// keep_newest_model, by default as configured for the streams
+	#[clap(long)]
+	on_conflict: Option<ConflictPolicy>,
+	/// Number of records saved in one transaction
+	#[clap(long, default_value = "1000")]
+	batch_size: usize,
+	/// Start from the beginning instead of resuming a previous import
+	#[clap(long)]
+	restart: bool,
+}
+
+impl ImportArgs {
+	/// Import the file
+	async fn run(self, db: Arc<SentimentDB>, config: &Settings) -> Result<()> {
+		let format = match self.format.or_else(|| ImportFormat::of_path(&self.file)) {
+			Some(format) => format,
+			None => return Err(eyre!("Unknown format of {}, use --format", self.file.display())),
+		};
+		let mut importer = Importer::builder();
+		if self.classify {
+			let (_runner, sentiment_classifier) =
+				SentimentClassifier::spawn(config.classifier.clone());
+			importer.sentiment_classifier(sentiment_classifier);
+		}
+		let progress = importer
+			.path(self.file)
+			.format(format)
+			.keyword(self.keyword)
+			.source(self.source)
+			.on_conflict(self.on_conflict.unwrap_or(config.twitter.on_conflict))
+			.batch_size(self.batch_size.max(1))
+			.restart(self.restart)
+			.db(db)
+			.build()?
+			.run()
+			.await?;
+		info!(
+			"Imported {} records: {} inserted, {} updated, {} skipped, {} invalid.",
+			progress.records,
+			progress.inserted,
+			progress.updated,
+			progress.skipped,
+			progress.invalid
+		);
+		Ok(())
+	}
+}
+
+/// Parse a time argument
+fn parse_time(time: &str) -> Result<i64, String> {
+	data::parse_timestamp(time).ok_or_else(|| format!("Invalid time: {}", time))
+}
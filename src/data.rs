@@ -1,26 +1,63 @@
 //! Data retrieval and transformation helpers
 
-use std::fmt::Formatter;
-
-use crate::database::TweetSentiment;
-
-/// Make a plot of data points. Returns a string with a SVG.
-pub fn plot(
-	title: &str,
-	line_name: &str,
-	points: &[(f64, f64)],
-) -> Result<String, std::fmt::Error> {
-	let plot = poloto::plot(title, "Days (/ Timestamp)", "Sentiment")
-		.ymarker(-1.0)
-		.ymarker(1.0)
-		.line(line_name, points)
-		.xinterval_fmt(timestamp_fmt)
-		.move_into();
+use std::{collections::BTreeMap, fmt::Formatter};
+
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+use crate::database::{RollupBucket, TweetSentiment};
+
+/// Make a plot of lines of data points. Returns a string with a SVG.
+pub fn plot(title: &str, lines: &[(String, Vec<(f64, f64)>)]) -> Result<String, std::fmt::Error> {
+	let mut plot = poloto::plot(title, "Days (/ Timestamp)", "Sentiment");
+	plot.ymarker(-1.0).ymarker(1.0);
+	for (line_name, points) in lines {
+		plot.line(line_name, points);
+	}
+	plot.xinterval_fmt(timestamp_fmt);
 	let mut svg = String::new();
 	poloto::simple_theme_dark(&mut svg, plot)?;
 	Ok(svg)
 }
 
+/// Split entries into groups by the given key, e.g. the source, keeping the
+/// order of the entries.
+pub fn split_by<F>(entries: Vec<TweetSentiment>, key: F) -> BTreeMap<String, Vec<TweetSentiment>>
+where
+	F: Fn(&TweetSentiment) -> String,
+{
+	let mut groups: BTreeMap<String, Vec<TweetSentiment>> = BTreeMap::new();
+	for entry in entries {
+		groups.entry(key(&entry)).or_default().push(entry);
+	}
+	groups
+}
+
+/// Parse a duration with a unit, e.g. `90s`, `30m`, `6h`, `7d` or `2w`.
+/// Returns `None` for invalid and overflowing durations.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+	let text = text.trim();
+	let unit_start = text.find(|c: char| !c.is_ascii_digit())?;
+	let (amount, unit) = text.split_at(unit_start);
+	let amount: i64 = amount.parse().ok()?;
+	let unit_seconds = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		"w" => 7 * 24 * 60 * 60,
+		_ => return None,
+	};
+	amount.checked_mul(unit_seconds).map(Duration::seconds)
+}
+
+/// Parse a point in time as UNIX timestamp or RFC 3339 date and time, e.g.
+/// `2022-10-17T12:00:00Z`. Returns the UNIX timestamp.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+	text.parse()
+		.ok()
+		.or_else(|| OffsetDateTime::parse(text, &Rfc3339).ok().map(OffsetDateTime::unix_timestamp))
+}
+
 /// Format the timestamp steps in the graph.
 pub fn timestamp_fmt(
 	f: &mut Formatter<'_>,
@@ -35,18 +72,49 @@ pub fn timestamp_fmt(
 ///
 /// Alpha defines the influence of the previous vs the new value:
 /// $$ ema_{i+1} = alpha * ema_i + (1-alpha) * next $$
+/// Entries with a weight above 1 are applied as often as their weight.
 pub fn exp_moving_avg(entries: &[TweetSentiment], alpha: f64) -> Vec<(f64, f64)> {
 	entries
 		.iter()
 		.scan(0.0, |ema, item| {
-			*ema = alpha * *ema + (1.0 - alpha) * item.sentiment;
+			let keep = alpha.powi(item.weight.max(1));
+			*ema = keep * *ema + (1.0 - keep) * item.sentiment;
 			Some((item.created as f64, *ema))
 		})
 		.collect()
 }
 
+/// Transform a vector of entries to exponential moving average values that
+/// decay by the elapsed time instead of per entry, so the time horizon does not
+/// depend on the number of entries.
+///
+/// The influence of previous entries halves every `half_life`:
+/// $$ decay_{i+1} = 0.5^{(t_{i+1} - t_i) / half\_life} $$
+/// Values and weights are accumulated separately and the average is their
+/// ratio, which corrects the bias of the start towards 0 and handles gaps and
+/// irregular timestamps. Entries are weighted by their weight.
+pub fn exp_moving_avg_half_life(
+	entries: &[TweetSentiment],
+	half_life: Duration,
+) -> Vec<(f64, f64)> {
+	let half_life = half_life.as_seconds_f64().max(1.0);
+	let mut last_time = entries.first().map_or(0, |entry| entry.created);
+	entries
+		.iter()
+		.scan((0.0, 0.0), |(sum_val, sum_weight), item| {
+			let elapsed = (item.created - last_time).max(0) as f64;
+			last_time = last_time.max(item.created);
+			let decay = 0.5_f64.powf(elapsed / half_life);
+			let item_weight = f64::from(item.weight.max(1));
+			*sum_val = decay * *sum_val + item_weight * item.sentiment;
+			*sum_weight = decay * *sum_weight + item_weight;
+			Some((item.created as f64, *sum_val / *sum_weight))
+		})
+		.collect()
+}
+
 /// Transform a vector of entries to moving average values with variable window
-/// size.
+/// size. The sentiment is weighted by the entries' weights.
 pub fn moving_avg(entries: &[TweetSentiment], mut window: usize) -> Vec<(f64, f64)> {
 	if entries.len() < 5 * window && window > 2 {
 		// adjust window size for too small set, but must be at least 1
@@ -55,10 +123,102 @@ pub fn moving_avg(entries: &[TweetSentiment], mut window: usize) -> Vec<(f64, f6
 	entries
 		.windows(window)
 		.map(|values| {
-			let (sum_time, sum_val) = values.iter().fold((0, 0.0), |(time, value), item| {
-				(time + item.created, value + item.sentiment)
-			});
-			(sum_time as f64 / window as f64, sum_val / window as f64)
+			let (sum_time, sum_val, sum_weight) =
+				values.iter().fold((0, 0.0, 0.0), |(time, value, weight), item| {
+					let item_weight = f64::from(item.weight.max(1));
+					(
+						time + item.created,
+						value + item_weight * item.sentiment,
+						weight + item_weight,
+					)
+				});
+			(sum_time as f64 / window as f64, sum_val / sum_weight)
 		})
 		.collect()
 }
+
+/// Transform rollup buckets to their average values, at the start of the
+/// buckets.
+pub fn rollup_avg(buckets: &[RollupBucket]) -> Vec<(f64, f64)> {
+	buckets.iter().map(|bucket| (bucket.bucket as f64, bucket.mean())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Entry with the creation time, sentiment and weight
+	fn entry(created: i64, sentiment: f64, weight: i32) -> TweetSentiment {
+		let entry =
+			TweetSentiment::new(0, "rust".to_owned(), "twitter".to_owned(), created, sentiment);
+		TweetSentiment { weight, ..entry }
+	}
+
+	fn assert_points(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+		assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+		for (actual, expected) in actual.iter().zip(expected) {
+			assert_eq!(actual.0, expected.0, "{:?}", actual);
+			assert!((actual.1 - expected.1).abs() < 1e-12, "{:?} != {:?}", actual, expected);
+		}
+	}
+
+	#[test]
+	fn exp_moving_avg_half_life_decays_by_time() {
+		let entries = [
+			// Not biased towards 0 at the start
+			entry(0, 1.0, 1),
+			// One half-life later: (0.5 * 1) / (0.5 * 1 + 1)
+			entry(60, 0.0, 1),
+			// At the same time, counted twice: (0.5 - 2) / (1.5 + 2)
+			entry(60, -1.0, 2),
+			// After a gap of ten half-lives: (-1.5 / 1024 + 0.5) / (3.5 / 1024 + 1)
+			entry(660, 0.5, 1),
+		];
+		let points = exp_moving_avg_half_life(&entries, Duration::minutes(1));
+		assert_points(
+			&points,
+			&[(0.0, 1.0), (60.0, 1.0 / 3.0), (60.0, -3.0 / 7.0), (660.0, 510.5 / 1027.5)],
+		);
+	}
+
+	#[test]
+	fn exp_moving_avg_half_life_ignores_earlier_timestamps() {
+		// An entry earlier than the previous one decays nothing, and the next
+		// one decays from the latest time: (0.5 * 1 + 1) / (0.5 * 2 + 1)
+		let entries = [entry(60, 1.0, 1), entry(0, 0.0, 1), entry(120, 1.0, 1)];
+		let points = exp_moving_avg_half_life(&entries, Duration::minutes(1));
+		assert_points(&points, &[(60.0, 1.0), (0.0, 0.5), (120.0, 0.75)]);
+		assert!(exp_moving_avg_half_life(&[], Duration::minutes(1)).is_empty());
+	}
+
+	#[test]
+	fn parses_durations() {
+		assert_eq!(parse_duration("90s"), Some(Duration::seconds(90)));
+		assert_eq!(parse_duration(" 30m "), Some(Duration::minutes(30)));
+		assert_eq!(parse_duration("6h"), Some(Duration::hours(6)));
+		assert_eq!(parse_duration("7d"), Some(Duration::days(7)));
+		assert_eq!(parse_duration("2w"), Some(Duration::weeks(2)));
+		assert_eq!(parse_duration("9223372036854775807s"), Some(Duration::seconds(i64::MAX)));
+
+		for text in ["", "7", "d", "7y", "-7d", "1.5h", "7 d"] {
+			assert_eq!(parse_duration(text), None, "{}", text);
+		}
+		// Overflowing amounts and durations
+		assert_eq!(parse_duration("9223372036854775808s"), None);
+		assert_eq!(parse_duration("9223372036854775807m"), None);
+		assert_eq!(parse_duration("9999999999999999w"), None);
+	}
+
+	#[test]
+	fn parses_timestamps() {
+		assert_eq!(parse_timestamp("1666008000"), Some(1_666_008_000));
+		assert_eq!(parse_timestamp("-60"), Some(-60));
+		assert_eq!(parse_timestamp("2022-10-17T12:00:00Z"), Some(1_666_008_000));
+		assert_eq!(parse_timestamp("2022-10-17T14:00:00+02:00"), Some(1_666_008_000));
+		assert_eq!(parse_timestamp("2022-10-17T12:00:00.5Z"), Some(1_666_008_000));
+
+		for text in ["", "yesterday", "2022-10-17", "2022-10-17 12:00:00"] {
+			assert_eq!(parse_timestamp(text), None, "{}", text);
+		}
+	}
+}
@@ -0,0 +1,478 @@
+//! Storage in memory, for tests and demos. The data is lost when the app
+//! stops.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use sqlx::Result;
+use time::OffsetDateTime;
+
+use super::{
+	BackfillCheckpoint, ComplianceRecord, EntryBatch, EntryFilter, InsertOutcome, Keyword,
+	KeywordStatus, KeywordUpdate, Resolution, RetentionTarget, RollupBucket, RollupRange, Storage,
+	StoredTweet, TweetSentiment,
+};
+use crate::settings::ConflictPolicy;
+
+/// Storage in memory
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+	data: Mutex<MemoryData>,
+}
+
+/// Stored data, the tables of the SQL backends
+#[derive(Debug, Default)]
+struct MemoryData {
+	/// Entries per keyword
+	keywords: BTreeMap<String, KeywordEntries>,
+	/// Full data of the tweets per source and ID
+	tweets: HashMap<(String, i64), StoredTweet>,
+	compliance: Vec<ComplianceRecord>,
+	checkpoints: HashMap<String, BackfillCheckpoint>,
+	/// Keywords and their metadata
+	metadata: BTreeMap<String, Keyword>,
+	/// Rollups by resolution, keyword and start of the bucket
+	rollups: BTreeMap<(Resolution, String, i64), RollupBucket>,
+}
+
+/// Entries of a keyword, ordered by time
+#[derive(Debug, Default)]
+struct KeywordEntries {
+	/// Entries by creation time, source and ID
+	by_time: BTreeMap<(i64, String, i64), TweetSentiment>,
+	/// Creation time of the entries by source and ID
+	created: HashMap<(String, i64), i64>,
+}
+
+/// How an entry for a tweet that is stored already is handled
+#[derive(Debug, Clone, Copy)]
+enum OnConflict {
+	Policy(ConflictPolicy),
+	AddWeight,
+}
+
+impl MemoryStorage {
+	/// Create new, empty storage
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Lock the data
+	fn data(&self) -> MutexGuard<'_, MemoryData> {
+		self.data.lock().expect("Memory storage lock poisoned")
+	}
+}
+
+impl KeywordEntries {
+	/// Get the entry of a tweet
+	fn get_mut(&mut self, source: &str, id: i64) -> Option<&mut TweetSentiment> {
+		let created = *self.created.get(&(source.to_owned(), id))?;
+		self.by_time.get_mut(&(created, source.to_owned(), id))
+	}
+
+	/// Insert an entry, replacing the entry of the same tweet
+	fn insert(&mut self, entry: TweetSentiment) {
+		self.remove(&entry.source, entry.id);
+		self.created.insert((entry.source.clone(), entry.id), entry.created);
+		self.by_time.insert((entry.created, entry.source.clone(), entry.id), entry);
+	}
+
+	/// Remove the entry of a tweet
+	fn remove(&mut self, source: &str, id: i64) -> Option<TweetSentiment> {
+		let created = self.created.remove(&(source.to_owned(), id))?;
+		self.by_time.remove(&(created, source.to_owned(), id))
+	}
+}
+
+impl MemoryData {
+	/// Save an entry, resolving a conflict like the conflict clauses of the
+	/// SQL backends
+	fn insert(&mut self, entry: TweetSentiment, on_conflict: OnConflict) -> InsertOutcome {
+		self.mark_seen(&entry);
+		let entries = self.keywords.entry(entry.keyword.clone()).or_default();
+		let existing = match entries.get_mut(&entry.source, entry.id) {
+			Some(existing) => existing,
+			None => {
+				entries.insert(entry);
+				return InsertOutcome::Inserted;
+			}
+		};
+
+		match on_conflict {
+			OnConflict::Policy(ConflictPolicy::Ignore) => InsertOutcome::Skipped,
+			OnConflict::Policy(ConflictPolicy::KeepNewestModel)
+				if existing.model_version >= entry.model_version =>
+			{
+				InsertOutcome::Skipped
+			}
+			OnConflict::Policy(_) => {
+				let weight = existing.weight;
+				entries.insert(TweetSentiment { weight, ..entry });
+				InsertOutcome::Updated
+			}
+			OnConflict::AddWeight => {
+				existing.weight += entry.weight;
+				InsertOutcome::Updated
+			}
+		}
+	}
+
+	/// Extend the first and last seen times of the keyword of an entry, adding
+	/// the keyword if missing
+	fn mark_seen(&mut self, entry: &TweetSentiment) {
+		let keyword = self
+			.metadata
+			.entry(entry.keyword.clone())
+			.or_insert_with(|| Keyword::new(&entry.keyword));
+		keyword.first_seen =
+			Some(keyword.first_seen.map_or(entry.created, |first| first.min(entry.created)));
+		keyword.last_seen =
+			Some(keyword.last_seen.map_or(entry.created, |last| last.max(entry.created)));
+	}
+
+	/// Save the full data of a tweet, updating the counts if it exists
+	fn upsert_tweet(&mut self, tweet: &StoredTweet) {
+		let key = (tweet.source.clone(), tweet.id);
+		match self.tweets.get_mut(&key) {
+			Some(existing) => {
+				existing.author_followers = tweet.author_followers.or(existing.author_followers);
+				existing.retweet_count = tweet.retweet_count.or(existing.retweet_count);
+				existing.like_count = tweet.like_count.or(existing.like_count);
+			}
+			None => {
+				self.tweets.insert(key, tweet.clone());
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+	async fn insert(&self, entry: TweetSentiment, policy: ConflictPolicy) -> Result<InsertOutcome> {
+		Ok(self.data().insert(entry, OnConflict::Policy(policy)))
+	}
+
+	async fn insert_or_add_weight(&self, entry: TweetSentiment) -> Result<InsertOutcome> {
+		Ok(self.data().insert(entry, OnConflict::AddWeight))
+	}
+
+	async fn add_weight(&self, source: &str, id: u64) -> Result<Vec<(String, i64)>> {
+		let mut data = self.data();
+		let mut updated = Vec::new();
+		for entries in data.keywords.values_mut() {
+			if let Some(entry) = entries.get_mut(source, id as i64) {
+				entry.weight += 1;
+				updated.push((entry.keyword.clone(), entry.created));
+			}
+		}
+		Ok(updated)
+	}
+
+	async fn insert_batch(&self, batch: EntryBatch) -> Result<Vec<InsertOutcome>> {
+		let mut data = self.data();
+		for tweet in &batch.tweets {
+			data.upsert_tweet(tweet);
+		}
+		let outcomes = batch
+			.entries
+			.into_iter()
+			.map(|(entry, policy)| data.insert(entry, OnConflict::Policy(policy)))
+			.collect();
+		for entry in batch.weighted {
+			data.insert(entry, OnConflict::AddWeight);
+		}
+		Ok(outcomes)
+	}
+
+	async fn insert_tweet(&self, tweet: &StoredTweet) -> Result<()> {
+		self.data().upsert_tweet(tweet);
+		Ok(())
+	}
+
+	async fn delete_tweet(&self, source: &str, id: u64) -> Result<(Vec<(String, i64)>, u64)> {
+		let mut data = self.data();
+		let id = id as i64;
+		let removed = data
+			.keywords
+			.values_mut()
+			.filter_map(|entries| entries.remove(source, id))
+			.map(|entry| (entry.keyword, entry.created))
+			.collect();
+		let tweets = data.tweets.remove(&(source.to_owned(), id)).map_or(0, |_| 1);
+		Ok((removed, tweets))
+	}
+
+	async fn anonymise_tweets(&self, source: &str, id: Option<u64>, user_id: u64) -> Result<u64> {
+		let mut data = self.data();
+		let mut anonymised = 0;
+		for tweet in data.tweets.values_mut().filter(|tweet| tweet.source == source) {
+			let affected = match id {
+				Some(id) => tweet.id == id as i64,
+				None => tweet.author_id == Some(user_id as i64),
+			};
+			if affected {
+				tweet.text = None;
+				tweet.author_id = None;
+				tweet.author_followers = None;
+				anonymised += 1;
+			}
+		}
+		Ok(anonymised)
+	}
+
+	async fn record_compliance(&self, record: &ComplianceRecord) -> Result<()> {
+		self.data().compliance.push(record.clone());
+		Ok(())
+	}
+
+	async fn compliance_counts(&self) -> Result<Vec<(String, i64)>> {
+		let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+		for record in &self.data().compliance {
+			*counts.entry(record.kind.clone()).or_default() += 1;
+		}
+		Ok(counts.into_iter().collect())
+	}
+
+	async fn entries(&self, keyword: &str, filter: &EntryFilter) -> Result<Vec<TweetSentiment>> {
+		let data = self.data();
+		let entries = match data.keywords.get(keyword) {
+			Some(entries) => entries,
+			None => return Ok(Vec::new()),
+		};
+		let after = filter.after.as_ref().map(|cursor| (cursor.created, &cursor.source, cursor.id));
+		Ok(entries
+			.by_time
+			.iter()
+			.filter(|((created, source, id), _)| {
+				after.map_or(true, |after| (*created, source, *id) > after)
+			})
+			.map(|(_, entry)| entry)
+			.skip_while(|entry| filter.from.map_or(false, |from| entry.created < from))
+			.take_while(|entry| filter.to.map_or(true, |to| entry.created < to))
+			.filter(|entry| filter.source.as_ref().map_or(true, |source| entry.source == *source))
+			.filter(|entry| filter.lang.is_none() || entry.lang == filter.lang)
+			.take(filter.limit.map_or(usize::MAX, |limit| limit as usize))
+			.cloned()
+			.collect())
+	}
+
+	async fn exists(&self, keyword: &str) -> Result<bool> {
+		Ok(self.data().keywords.get(keyword).map_or(false, |entries| !entries.by_time.is_empty()))
+	}
+
+	async fn checkpoint(&self, keyword: &str) -> Result<Option<BackfillCheckpoint>> {
+		Ok(self.data().checkpoints.get(keyword).cloned())
+	}
+
+	async fn save_checkpoint(&self, checkpoint: &BackfillCheckpoint) -> Result<()> {
+		let mut data = self.data();
+		let mut checkpoint = checkpoint.clone();
+		if let Some(saved) = data.checkpoints.get(&checkpoint.keyword) {
+			checkpoint.newest_id = checkpoint.newest_id.max(saved.newest_id);
+		}
+		data.checkpoints.insert(checkpoint.keyword.clone(), checkpoint);
+		Ok(())
+	}
+
+	async fn save_newest_id(&self, keyword: &str, newest_id: i64) -> Result<()> {
+		let mut data = self.data();
+		let checkpoint = data
+			.checkpoints
+			.entry(keyword.to_owned())
+			.or_insert_with(|| BackfillCheckpoint::new(keyword));
+		checkpoint.newest_id = checkpoint.newest_id.max(Some(newest_id));
+		checkpoint.updated = OffsetDateTime::now_utc().unix_timestamp();
+		Ok(())
+	}
+
+	async fn keywords(&self) -> Result<Vec<Keyword>> {
+		Ok(self.data().metadata.values().cloned().collect())
+	}
+
+	async fn keyword(&self, keyword: &str) -> Result<Option<Keyword>> {
+		Ok(self.data().metadata.get(keyword).cloned())
+	}
+
+	async fn track_keyword(&self, entry: &Keyword, replace: bool) -> Result<()> {
+		let mut data = self.data();
+		match data.metadata.get_mut(&entry.keyword) {
+			Some(existing) => {
+				let untracked =
+					existing.rule.is_none() || existing.status() == KeywordStatus::Archived;
+				if replace || untracked {
+					existing.rule = entry.rule.clone();
+				}
+				if untracked {
+					existing.status = entry.status.clone();
+				}
+			}
+			None => {
+				data.metadata.insert(entry.keyword.clone(), entry.clone());
+			}
+		}
+		Ok(())
+	}
+
+	async fn set_keyword_status(&self, keyword: &str, status: KeywordStatus) -> Result<bool> {
+		Ok(match self.data().metadata.get_mut(keyword) {
+			Some(entry) => {
+				entry.status = status.as_str().to_owned();
+				true
+			}
+			None => false,
+		})
+	}
+
+	async fn update_keyword(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		let mut data = self.data();
+		let entry = match data.metadata.get_mut(keyword) {
+			Some(entry) => entry,
+			None => return Ok(false),
+		};
+		if let Some(display_name) = &update.display_name {
+			entry.display_name = Some(display_name.clone()).filter(|name| !name.is_empty());
+		}
+		if let Some(description) = &update.description {
+			entry.description = Some(description.clone()).filter(|text| !text.is_empty());
+		}
+		if let Some(tags) = update.tags_column() {
+			entry.tags = tags;
+		}
+		Ok(true)
+	}
+
+	async fn refresh_rollups(&self, range: &RollupRange) -> Result<u64> {
+		let mut data = self.data();
+		let data = &mut *data;
+		// Buckets before the day of the first entry of a keyword are kept, like
+		// in the SQL backends.
+		let first_days: HashMap<String, i64> = data
+			.keywords
+			.iter()
+			.filter_map(|(keyword, entries)| {
+				let ((created, _, _), _) = entries.by_time.iter().next()?;
+				Some((keyword.clone(), Resolution::Day.bucket(*created)))
+			})
+			.collect();
+		let in_range = |keyword: &str, bucket: i64, start: i64, end: Option<i64>| {
+			bucket >= start
+				&& end.map_or(true, |end| bucket < end)
+				&& range.keyword.as_deref().map_or(true, |range_keyword| range_keyword == keyword)
+				&& first_days.get(keyword).map_or(false, |first_day| bucket >= *first_day)
+		};
+
+		let mut written = 0;
+		for resolution in Resolution::ALL {
+			let start = resolution.bucket(range.from);
+			let end = range.to.map(|to| resolution.bucket(to));
+			data.rollups.retain(|(res, keyword, bucket), _| {
+				*res != resolution || !in_range(keyword, *bucket, start, end)
+			});
+			for (keyword, entries) in &data.keywords {
+				let entries = entries.by_time.range((start, String::new(), i64::MIN)..);
+				for (_, entry) in entries {
+					let bucket = resolution.bucket(entry.created);
+					if !in_range(keyword, bucket, start, end) {
+						continue;
+					}
+					let weight = i64::from(entry.weight);
+					let rollup = data
+						.rollups
+						.entry((resolution, keyword.clone(), bucket))
+						.or_insert_with(|| {
+							written += 1;
+							RollupBucket {
+								keyword: keyword.clone(),
+								resolution: resolution.as_str().to_owned(),
+								bucket,
+								count: 0,
+								sum: 0.0,
+								sum_squares: 0.0,
+								min: entry.sentiment,
+								max: entry.sentiment,
+								positive: 0,
+								negative: 0,
+							}
+						});
+					rollup.count += weight;
+					rollup.sum += weight as f64 * entry.sentiment;
+					rollup.sum_squares += weight as f64 * entry.sentiment * entry.sentiment;
+					rollup.min = rollup.min.min(entry.sentiment);
+					rollup.max = rollup.max.max(entry.sentiment);
+					if entry.sentiment > 0.0 {
+						rollup.positive += weight;
+					} else if entry.sentiment < 0.0 {
+						rollup.negative += weight;
+					}
+				}
+			}
+		}
+		Ok(written)
+	}
+
+	async fn expire(
+		&self,
+		keyword: &str,
+		target: RetentionTarget,
+		cutoff: i64,
+		dry_run: bool,
+	) -> Result<u64> {
+		let mut data = self.data();
+		let expired: Vec<_> = match target {
+			RetentionTarget::Raw => match data.keywords.get(keyword) {
+				Some(entries) => entries
+					.by_time
+					.keys()
+					.take_while(|(created, _, _)| *created < cutoff)
+					.map(|(_, source, id)| (source.clone(), *id))
+					.collect(),
+				None => Vec::new(),
+			},
+			RetentionTarget::Rollup(resolution) => data
+				.rollups
+				.range((resolution, keyword.to_owned(), i64::MIN)..)
+				.take_while(|((res, kw, bucket), _)| {
+					*res == resolution && kw == keyword && *bucket < cutoff
+				})
+				.map(|((_, _, bucket), _)| (String::new(), *bucket))
+				.collect(),
+		};
+		if !dry_run {
+			for (source, key) in &expired {
+				match target {
+					RetentionTarget::Raw => {
+						if let Some(entries) = data.keywords.get_mut(keyword) {
+							entries.remove(source, *key);
+						}
+					}
+					RetentionTarget::Rollup(resolution) => {
+						data.rollups.remove(&(resolution, keyword.to_owned(), *key));
+					}
+				}
+			}
+		}
+		Ok(expired.len() as u64)
+	}
+
+	async fn rollups(
+		&self,
+		keyword: &str,
+		resolution: Resolution,
+		from: Option<i64>,
+		to: Option<i64>,
+	) -> Result<Vec<RollupBucket>> {
+		let start = (resolution, keyword.to_owned(), from.unwrap_or(i64::MIN));
+		Ok(self
+			.data()
+			.rollups
+			.range(start..)
+			.take_while(|((res, kw, bucket), _)| {
+				*res == resolution && kw == keyword && to.map_or(true, |to| *bucket < to)
+			})
+			.map(|(_, rollup)| rollup.clone())
+			.collect())
+	}
+}
@@ -0,0 +1,1209 @@
+//! Module for data persistence handling. The data is stored by a storage
+//! backend, selected by the scheme of the database URL.
+//!
+//! The SQL backends use runtime queries instead of the `sqlx::query!` macros:
+//! the macros check every query against the one database of `DATABASE_URL`
+//! at compile time, which cannot cover Postgres and SQLite queries in the same
+//! build. The tests of this module run the queries against the migrated
+//! schemas of all backends instead, Postgres if `TEST_POSTGRES_URL` is set.
+
+mod memory;
+mod postgres;
+mod sqlite;
+
+use std::{collections::BTreeMap, fmt::Debug, str::FromStr, sync::Mutex, time::Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Result};
+use time::OffsetDateTime;
+use tracing::debug;
+
+pub use self::{memory::MemoryStorage, postgres::PostgresStorage, sqlite::SqliteStorage};
+use crate::{
+	settings::{ConflictPolicy, KeywordRule},
+	source::SourceTweet,
+};
+
+/// Database entry for tweet sentiment.
+#[derive(Debug, Clone, PartialEq, FromRow, Serialize)]
+pub struct TweetSentiment {
+	/// ID of the tweet in its source
+	pub id: i64,
+	/// Keyword the tweet matched, lowercase
+	pub keyword: String,
+	/// Name of the source the tweet came from, e.g. `twitter`
+	pub source: String,
+	/// Creation time of the tweet as UNIX timestamp
+	pub created: i64,
+	/// Sentiment between -1 (negative) and 1 (positive)
+	pub sentiment: f64,
+	/// Whether the entry is a retweet, stored separately
+	pub retweet: bool,
+	/// Number of times the tweet counts, increased by its retweets
+	pub weight: i32,
+	/// Language of the tweet, if known
+	pub lang: Option<String>,
+	/// Version of the sentiment models the entry was classified with
+	pub model_version: i32,
+}
+
+/// Outcome of saving an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+	/// The entry was new and inserted
+	Inserted,
+	/// An entry for the tweet existed and was updated
+	Updated,
+	/// An entry for the tweet existed and was kept
+	Skipped,
+}
+
+impl InsertOutcome {
+	/// Whether an entry for the tweet existed already
+	pub fn is_duplicate(self) -> bool {
+		self != InsertOutcome::Inserted
+	}
+}
+
+/// Counts of the outcomes of saving entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InsertCounts {
+	pub inserted: u64,
+	pub updated: u64,
+	pub skipped: u64,
+}
+
+impl InsertCounts {
+	/// Number of entries that existed already
+	pub fn duplicates(&self) -> u64 {
+		self.updated + self.skipped
+	}
+}
+
+impl FromIterator<InsertOutcome> for InsertCounts {
+	fn from_iter<I: IntoIterator<Item = InsertOutcome>>(outcomes: I) -> Self {
+		let mut counts = InsertCounts::default();
+		for outcome in outcomes {
+			match outcome {
+				InsertOutcome::Inserted => counts.inserted += 1,
+				InsertOutcome::Updated => counts.updated += 1,
+				InsertOutcome::Skipped => counts.skipped += 1,
+			}
+		}
+		counts
+	}
+}
+
+impl std::ops::AddAssign for InsertCounts {
+	fn add_assign(&mut self, other: Self) {
+		self.inserted += other.inserted;
+		self.updated += other.updated;
+		self.skipped += other.skipped;
+	}
+}
+
+/// Entries of a classified chunk, saved together in one transaction
+#[derive(Debug, Clone, Default)]
+pub struct EntryBatch {
+	/// Full data of the tweets
+	pub tweets: Vec<StoredTweet>,
+	/// Entries with the policy for conflicts with existing entries
+	pub entries: Vec<(TweetSentiment, ConflictPolicy)>,
+	/// Entries adding their weight to existing entries of the tweet
+	pub weighted: Vec<TweetSentiment>,
+}
+
+/// Filter for the entries of a keyword. Entries are ordered by time, then
+/// source and ID.
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+	/// Only entries from this source
+	pub source: Option<String>,
+	/// Only entries in this language
+	pub lang: Option<String>,
+	/// Only entries created at or after this timestamp
+	pub from: Option<i64>,
+	/// Only entries created before this timestamp
+	pub to: Option<i64>,
+	/// Only entries after this position, to get the next page
+	pub after: Option<EntryCursor>,
+	/// Maximum number of entries
+	pub limit: Option<u32>,
+}
+
+/// Position of an entry in the order of the entries of a keyword, to
+/// continue paging after it. Formatted as `created:source:id`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntryCursor {
+	pub created: i64,
+	pub source: String,
+	pub id: i64,
+}
+
+impl EntryCursor {
+	/// Position of the entry
+	pub fn of(entry: &TweetSentiment) -> Self {
+		EntryCursor { created: entry.created, source: entry.source.clone(), id: entry.id }
+	}
+
+	/// Parse a formatted cursor
+	pub fn parse(cursor: &str) -> Option<Self> {
+		let mut parts = cursor.splitn(3, ':');
+		let created = parts.next()?.parse().ok()?;
+		let source = parts.next()?.to_owned();
+		let id = parts.next()?.parse().ok()?;
+		Some(EntryCursor { created, source, id })
+	}
+}
+
+impl std::fmt::Display for EntryCursor {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}:{}", self.created, self.source, self.id)
+	}
+}
+
+/// Page of the entries of a keyword
+#[derive(Debug, Clone)]
+pub struct EntryPage {
+	pub entries: Vec<TweetSentiment>,
+	/// Cursor to get the next page, if the page is full
+	pub next: Option<EntryCursor>,
+}
+
+impl TweetSentiment {
+	/// Create new entry.
+	pub fn new(id: u64, keyword: String, source: String, timestamp: i64, sentiment: f64) -> Self {
+		TweetSentiment {
+			id: id as i64,
+			keyword,
+			source,
+			created: timestamp,
+			sentiment,
+			retweet: false,
+			weight: 1,
+			lang: None,
+			model_version: 1,
+		}
+	}
+}
+
+/// SQL conflict clause for a tweet sentiment entry adding its weight to the
+/// existing entry. Conflict clauses are valid for all SQL backends.
+const ADD_WEIGHT_CLAUSE: &str = "ON CONFLICT (keyword, source, id) DO UPDATE SET
+	weight = tweet_sentiment.weight + EXCLUDED.weight";
+
+/// SQL conflict clause for a tweet sentiment entry implementing the policy.
+/// The weight of an existing entry is always kept.
+fn conflict_clause(policy: ConflictPolicy) -> &'static str {
+	match policy {
+		ConflictPolicy::Ignore => "ON CONFLICT (keyword, source, id) DO NOTHING",
+		ConflictPolicy::Overwrite => {
+			"ON CONFLICT (keyword, source, id) DO UPDATE SET
+				created = EXCLUDED.created,
+				sentiment = EXCLUDED.sentiment,
+				retweet = EXCLUDED.retweet,
+				lang = EXCLUDED.lang,
+				model_version = EXCLUDED.model_version"
+		}
+		ConflictPolicy::KeepNewestModel => {
+			"ON CONFLICT (keyword, source, id) DO UPDATE SET
+				created = EXCLUDED.created,
+				sentiment = EXCLUDED.sentiment,
+				retweet = EXCLUDED.retweet,
+				lang = EXCLUDED.lang,
+				model_version = EXCLUDED.model_version
+				WHERE tweet_sentiment.model_version < EXCLUDED.model_version"
+		}
+	}
+}
+
+/// Time resolution of the rollups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+	Minute,
+	Hour,
+	Day,
+}
+
+impl Resolution {
+	/// All resolutions rollups are maintained for
+	pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+	/// Width of a bucket in seconds
+	pub fn seconds(self) -> i64 {
+		match self {
+			Resolution::Minute => 60,
+			Resolution::Hour => 60 * 60,
+			Resolution::Day => 24 * 60 * 60,
+		}
+	}
+
+	/// Name of the resolution as stored in the database
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Resolution::Minute => "minute",
+			Resolution::Hour => "hour",
+			Resolution::Day => "day",
+		}
+	}
+
+	/// Start of the bucket containing the timestamp
+	pub fn bucket(self, timestamp: i64) -> i64 {
+		timestamp - timestamp.rem_euclid(self.seconds())
+	}
+}
+
+impl FromStr for Resolution {
+	type Err = String;
+
+	fn from_str(resolution: &str) -> std::result::Result<Self, Self::Err> {
+		match resolution {
+			"minute" => Ok(Resolution::Minute),
+			"hour" => Ok(Resolution::Hour),
+			"day" => Ok(Resolution::Day),
+			_ => Err(format!("Unknown resolution: {}", resolution)),
+		}
+	}
+}
+
+/// Range of the rollups to recompute
+#[derive(Debug, Clone, Default)]
+pub struct RollupRange {
+	/// Only rollups of this keyword
+	pub keyword: Option<String>,
+	/// Buckets containing this timestamp or later
+	pub from: i64,
+	/// Only buckets before the bucket containing this timestamp
+	pub to: Option<i64>,
+}
+
+impl RollupRange {
+	/// Range of the buckets of all resolutions containing the timestamps from
+	/// `first` to `last` of a keyword
+	pub fn covering(keyword: &str, first: i64, last: i64) -> Self {
+		let day = Resolution::Day;
+		RollupRange {
+			keyword: Some(keyword.to_owned()),
+			from: first,
+			to: Some(day.bucket(last) + day.seconds()),
+		}
+	}
+}
+
+/// Data removed by the retention policies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionTarget {
+	/// Sentiment entries of the tweets
+	Raw,
+	/// Rollups of a resolution
+	Rollup(Resolution),
+}
+
+impl std::fmt::Display for RetentionTarget {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RetentionTarget::Raw => f.write_str("raw"),
+			RetentionTarget::Rollup(resolution) => f.write_str(resolution.as_str()),
+		}
+	}
+}
+
+/// Database entry for the aggregated sentiment of a keyword in a time bucket.
+/// Entries are weighted by their weights.
+#[derive(Debug, Clone, PartialEq, FromRow, Serialize)]
+pub struct RollupBucket {
+	pub keyword: String,
+	/// Name of the resolution, see `Resolution`
+	pub resolution: String,
+	/// Timestamp of the start of the bucket
+	pub bucket: i64,
+	/// Sum of the weights of the entries
+	pub count: i64,
+	/// Weighted sum of the sentiments
+	pub sum: f64,
+	/// Weighted sum of the squared sentiments
+	pub sum_squares: f64,
+	pub min: f64,
+	pub max: f64,
+	/// Weighted number of positive entries
+	pub positive: i64,
+	/// Weighted number of negative entries
+	pub negative: i64,
+}
+
+impl RollupBucket {
+	/// Weighted average sentiment in the bucket
+	pub fn mean(&self) -> f64 {
+		self.sum / self.count.max(1) as f64
+	}
+
+	/// Weighted standard deviation of the sentiment in the bucket
+	pub fn std_dev(&self) -> f64 {
+		let mean = self.mean();
+		(self.sum_squares / self.count.max(1) as f64 - mean * mean).max(0.0).sqrt()
+	}
+}
+
+/// Database entry for the full data of a tweet. Belongs to the tweet
+/// sentiment entries with the same source and ID.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct StoredTweet {
+	pub source: String,
+	pub id: i64,
+	pub created: i64,
+	/// Text of the tweet, not stored if disabled
+	pub text: Option<String>,
+	pub lang: Option<String>,
+	pub author_id: Option<i64>,
+	pub author_followers: Option<i32>,
+	pub retweet_count: Option<i32>,
+	pub like_count: Option<i32>,
+	pub reply_to: Option<i64>,
+	pub quote_of: Option<i64>,
+	pub retweet_of: Option<i64>,
+	pub client: Option<String>,
+}
+
+impl StoredTweet {
+	/// Create new entry from a received tweet, optionally without its text.
+	pub fn new(source: String, tweet: &SourceTweet, store_text: bool) -> Self {
+		let meta = &tweet.meta;
+		StoredTweet {
+			source,
+			id: tweet.id as i64,
+			created: tweet.created_at,
+			text: store_text.then(|| tweet.text.clone()),
+			lang: tweet.lang.clone(),
+			author_id: meta.author_id.map(|id| id as i64),
+			author_followers: meta.author_followers,
+			retweet_count: meta.retweet_count,
+			like_count: meta.like_count,
+			reply_to: meta.reply_to.map(|id| id as i64),
+			quote_of: meta.quote_of.map(|id| id as i64),
+			retweet_of: meta.retweet_of.map(|id| id as i64),
+			client: meta.client.clone(),
+		}
+	}
+}
+
+/// Database entry for the audit log of processed compliance messages.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct ComplianceRecord {
+	/// Name of the source the message came from
+	pub source: String,
+	/// Kind of the message: `delete`, `scrub_geo` or `withheld`
+	pub kind: String,
+	pub tweet_id: Option<i64>,
+	pub user_id: Option<i64>,
+	/// Comma-separated country codes of withheld content
+	pub countries: Option<String>,
+	/// Number of rows deleted or anonymised
+	pub rows_affected: i64,
+	/// Timestamp of when the message was processed
+	pub processed: i64,
+}
+
+impl ComplianceRecord {
+	/// Create new audit entry, processed now.
+	pub fn new(source: String, kind: &str, tweet_id: Option<u64>, user_id: Option<u64>) -> Self {
+		ComplianceRecord {
+			source,
+			kind: kind.to_owned(),
+			tweet_id: tweet_id.map(|id| id as i64),
+			user_id: user_id.map(|id| id as i64),
+			countries: None,
+			rows_affected: 0,
+			processed: OffsetDateTime::now_utc().unix_timestamp(),
+		}
+	}
+}
+
+/// Database entry for the backfill progress of a keyword.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct BackfillCheckpoint {
+	pub keyword: String,
+	/// Oldest tweet ID retrieved so far
+	pub oldest_id: Option<i64>,
+	/// Newest tweet ID retrieved so far, by the backfill or the stream
+	pub newest_id: Option<i64>,
+	/// Whether the backfill reached the configured date
+	pub complete: bool,
+	/// Timestamp of the last update
+	pub updated: i64,
+	/// Newest tweet ID before a gap that is being filled, e.g. of the time the
+	/// app was not running. Kept until the gap is filled, as the stream saves
+	/// newer IDs meanwhile.
+	pub gap_since_id: Option<i64>,
+}
+
+impl BackfillCheckpoint {
+	/// Create new, empty checkpoint.
+	pub fn new(keyword: &str) -> Self {
+		BackfillCheckpoint {
+			keyword: keyword.to_owned(),
+			oldest_id: None,
+			newest_id: None,
+			complete: false,
+			updated: OffsetDateTime::now_utc().unix_timestamp(),
+			gap_since_id: None,
+		}
+	}
+
+	/// Extend the checkpoint's ID range by the given tweets.
+	pub fn update(&mut self, tweets: &[SourceTweet]) {
+		for id in tweets.iter().map(|tweet| tweet.id as i64) {
+			self.oldest_id = Some(self.oldest_id.map_or(id, |oldest| oldest.min(id)));
+			self.newest_id = Some(self.newest_id.map_or(id, |newest| newest.max(id)));
+		}
+		self.updated = OffsetDateTime::now_utc().unix_timestamp();
+	}
+}
+
+/// Tracking status of a keyword
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeywordStatus {
+	/// Tracked from the sources if it has a rule
+	Active,
+	/// Tracking is paused
+	Paused,
+	/// Not tracked anymore, its data is kept
+	Archived,
+}
+
+impl KeywordStatus {
+	/// Name of the status as stored in the database
+	pub fn as_str(self) -> &'static str {
+		match self {
+			KeywordStatus::Active => "active",
+			KeywordStatus::Paused => "paused",
+			KeywordStatus::Archived => "archived",
+		}
+	}
+
+	/// Parse the name of a status
+	pub fn parse(status: &str) -> Option<Self> {
+		match status {
+			"active" => Some(KeywordStatus::Active),
+			"paused" => Some(KeywordStatus::Paused),
+			"archived" => Some(KeywordStatus::Archived),
+			_ => None,
+		}
+	}
+}
+
+/// Database entry for a keyword and its metadata. Keywords with a rule are
+/// tracked from the sources, others only have pushed entries.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Keyword {
+	pub keyword: String,
+	/// Name to show instead of the keyword
+	pub display_name: Option<String>,
+	pub description: Option<String>,
+	/// Keyword rule as JSON, if the keyword is tracked
+	pub rule: Option<String>,
+	/// Name of the tracking status, see `KeywordStatus`
+	pub status: String,
+	/// Comma-separated tags
+	pub tags: String,
+	/// Timestamp of when the keyword was added
+	pub created: i64,
+	/// Creation time of the first entry
+	pub first_seen: Option<i64>,
+	/// Creation time of the last entry
+	pub last_seen: Option<i64>,
+}
+
+impl Keyword {
+	/// Create new, active entry without rule.
+	pub fn new(keyword: &str) -> Self {
+		Keyword {
+			keyword: keyword.to_lowercase(),
+			display_name: None,
+			description: None,
+			rule: None,
+			status: KeywordStatus::Active.as_str().to_owned(),
+			tags: String::new(),
+			created: OffsetDateTime::now_utc().unix_timestamp(),
+			first_seen: None,
+			last_seen: None,
+		}
+	}
+
+	/// Create new, active entry for a keyword rule.
+	pub fn tracked(rule: &KeywordRule) -> serde_json::Result<Self> {
+		Ok(Keyword { rule: Some(serde_json::to_string(rule)?), ..Self::new(&rule.keyword) })
+	}
+
+	/// Parse the keyword rule, if the keyword is tracked.
+	pub fn rule(&self) -> Option<serde_json::Result<KeywordRule>> {
+		self.rule.as_deref().map(serde_json::from_str)
+	}
+
+	/// Tracking status, unknown names are treated as archived.
+	pub fn status(&self) -> KeywordStatus {
+		KeywordStatus::parse(&self.status).unwrap_or(KeywordStatus::Archived)
+	}
+
+	/// Tags of the keyword
+	pub fn tags(&self) -> Vec<String> {
+		self.tags.split(',').filter(|tag| !tag.is_empty()).map(str::to_owned).collect()
+	}
+
+	/// Name to show: the display name or the keyword
+	pub fn name(&self) -> &str {
+		self.display_name.as_deref().unwrap_or(&self.keyword)
+	}
+}
+
+/// Changes of the metadata of a keyword, missing fields are kept. Empty
+/// display names and descriptions are removed.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct KeywordUpdate {
+	pub display_name: Option<String>,
+	pub description: Option<String>,
+	/// Tags, must not contain commas
+	pub tags: Option<Vec<String>>,
+}
+
+impl KeywordUpdate {
+	/// Tags as stored in the database, trimmed and comma-separated
+	fn tags_column(&self) -> Option<String> {
+		self.tags.as_ref().map(|tags| {
+			let tags: Vec<_> =
+				tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()).collect();
+			tags.join(",")
+		})
+	}
+}
+
+/// Creation time of the first and last of the entries per keyword, to update
+/// when the keywords were seen
+fn seen_ranges<'a>(
+	entries: impl IntoIterator<Item = &'a TweetSentiment>,
+) -> BTreeMap<String, (i64, i64)> {
+	time_ranges(entries.into_iter().map(|entry| (entry.keyword.as_str(), entry.created)))
+}
+
+/// Creation time of the first and last entry per keyword, of entries given by
+/// keyword and creation time
+fn time_ranges<'a>(
+	entries: impl IntoIterator<Item = (&'a str, i64)>,
+) -> BTreeMap<String, (i64, i64)> {
+	let mut ranges = BTreeMap::new();
+	for (keyword, created) in entries {
+		let range = ranges.entry(keyword.to_owned()).or_insert((created, created));
+		range.0 = range.0.min(created);
+		range.1 = range.1.max(created);
+	}
+	ranges
+}
+
+/// Backend storing the data. All methods of `SentimentDB` are delegated to
+/// the backend, see there for the documentation.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+	/// Save an entry, resolving a conflict by the policy
+	async fn insert(&self, entry: TweetSentiment, policy: ConflictPolicy) -> Result<InsertOutcome>;
+	/// Save an entry, adding its weight to an existing entry
+	async fn insert_or_add_weight(&self, entry: TweetSentiment) -> Result<InsertOutcome>;
+	/// Add one to the weight of the entries of a tweet. Returns the keywords
+	/// and creation times of the updated entries.
+	async fn add_weight(&self, source: &str, id: u64) -> Result<Vec<(String, i64)>>;
+	/// Save the tweets and entries of a chunk in one transaction. Returns the
+	/// outcomes of the entries with conflict policy in their order.
+	async fn insert_batch(&self, batch: EntryBatch) -> Result<Vec<InsertOutcome>>;
+	/// Save the full data of a tweet
+	async fn insert_tweet(&self, tweet: &StoredTweet) -> Result<()>;
+	/// Remove a deleted tweet and its entries. Returns the keywords and
+	/// creation times of the removed entries and the number of removed rows of
+	/// stored tweet data.
+	async fn delete_tweet(&self, source: &str, id: u64) -> Result<(Vec<(String, i64)>, u64)>;
+	/// Anonymise the stored data of a tweet or a user's tweets
+	async fn anonymise_tweets(&self, source: &str, id: Option<u64>, user_id: u64) -> Result<u64>;
+	/// Save an audit entry of a compliance message
+	async fn record_compliance(&self, record: &ComplianceRecord) -> Result<()>;
+	/// Count the compliance messages per kind
+	async fn compliance_counts(&self) -> Result<Vec<(String, i64)>>;
+	/// Get the entries of a keyword matching the filter, ordered by time,
+	/// source and ID
+	async fn entries(&self, keyword: &str, filter: &EntryFilter) -> Result<Vec<TweetSentiment>>;
+	/// Check if there are entries for a keyword
+	async fn exists(&self, keyword: &str) -> Result<bool>;
+	/// Get the backfill checkpoint of a keyword
+	async fn checkpoint(&self, keyword: &str) -> Result<Option<BackfillCheckpoint>>;
+	/// Save a backfill checkpoint, keeping a newer newest ID
+	async fn save_checkpoint(&self, checkpoint: &BackfillCheckpoint) -> Result<()>;
+	/// Raise the newest tweet ID of a backfill checkpoint
+	async fn save_newest_id(&self, keyword: &str, newest_id: i64) -> Result<()>;
+	/// List all keywords
+	async fn keywords(&self) -> Result<Vec<Keyword>>;
+	/// Get a keyword
+	async fn keyword(&self, keyword: &str) -> Result<Option<Keyword>>;
+	/// Save a keyword rule
+	async fn track_keyword(&self, entry: &Keyword, replace: bool) -> Result<()>;
+	/// Set the tracking status of a keyword
+	async fn set_keyword_status(&self, keyword: &str, status: KeywordStatus) -> Result<bool>;
+	/// Update the metadata of a keyword
+	async fn update_keyword(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool>;
+	/// Recompute the rollups in the range
+	async fn refresh_rollups(&self, range: &RollupRange) -> Result<u64>;
+	/// Remove or count the data of a keyword before the cutoff
+	async fn expire(
+		&self,
+		keyword: &str,
+		target: RetentionTarget,
+		cutoff: i64,
+		dry_run: bool,
+	) -> Result<u64>;
+	/// Get the rollups of a keyword in the time range, ordered by time
+	async fn rollups(
+		&self,
+		keyword: &str,
+		resolution: Resolution,
+		from: Option<i64>,
+		to: Option<i64>,
+	) -> Result<Vec<RollupBucket>>;
+}
+
+/// Database handler to share
+#[derive(Debug)]
+pub struct SentimentDB {
+	storage: Box<dyn Storage>,
+	/// First and last creation time of the entries per keyword saved since
+	/// their rollups were refreshed
+	stale_rollups: Mutex<BTreeMap<String, (i64, i64)>>,
+}
+
+impl SentimentDB {
+	/// Create new DB interface for a storage backend
+	pub fn new(storage: impl Storage + 'static) -> Self {
+		SentimentDB { storage: Box::new(storage), stale_rollups: Mutex::default() }
+	}
+
+	/// Create new DB interface storing the data in memory, e.g. for tests
+	pub fn in_memory() -> Self {
+		Self::new(MemoryStorage::new())
+	}
+
+	/// Connect to the database at the URL and run the migrations. The backend
+	/// is selected by the scheme: `postgres://` or `postgresql://` for
+	/// Postgres, `sqlite:` for SQLite and `memory:` for storage in memory.
+	pub async fn connect(url: &str) -> Result<Self> {
+		let scheme = url.split(':').next().unwrap_or_default();
+		match scheme {
+			"memory" => Ok(Self::in_memory()),
+			"postgres" | "postgresql" => Ok(Self::new(PostgresStorage::connect(url).await?)),
+			"sqlite" => Ok(Self::new(SqliteStorage::connect(url).await?)),
+			_ => Err(sqlx::Error::Configuration(
+				format!("Unsupported database URL scheme: {}", scheme).into(),
+			)),
+		}
+	}
+
+	/// Save an entry to the database. An existing entry for the same tweet is
+	/// handled by the conflict policy, which is reported in the outcome.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn insert(
+		&self,
+		entry: TweetSentiment,
+		policy: ConflictPolicy,
+	) -> Result<InsertOutcome> {
+		let seen = seen_ranges([&entry]);
+		let outcome = self.storage.insert(entry, policy).await?;
+		self.mark_rollups_stale(seen);
+		Ok(outcome)
+	}
+
+	/// Save an entry to the database, adding its weight to the existing entry
+	/// of the tweet if there is one
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn insert_or_add_weight(&self, entry: TweetSentiment) -> Result<InsertOutcome> {
+		let seen = seen_ranges([&entry]);
+		let outcome = self.storage.insert_or_add_weight(entry).await?;
+		self.mark_rollups_stale(seen);
+		Ok(outcome)
+	}
+
+	/// Add one to the weight of the entries of a tweet, e.g. for a retweet.
+	/// Returns the number of entries updated, 0 if the tweet is not stored.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn add_weight(&self, source: &str, id: u64) -> Result<u64> {
+		let updated = self.storage.add_weight(source, id).await?;
+		self.mark_rollups_stale(time_ranges(
+			updated.iter().map(|(keyword, created)| (keyword.as_str(), *created)),
+		));
+		Ok(updated.len() as u64)
+	}
+
+	/// Save the tweets and entries of a classified chunk in one transaction.
+	/// Returns the counts of the entries with conflict policy.
+	pub async fn insert_batch(&self, batch: EntryBatch) -> Result<InsertCounts> {
+		Ok(self.insert_batch_outcomes(batch).await?.into_iter().collect())
+	}
+
+	/// Save the tweets and entries of a classified chunk in one transaction.
+	/// Returns the outcome of each entry with conflict policy, in the order of
+	/// the entries.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn insert_batch_outcomes(&self, batch: EntryBatch) -> Result<Vec<InsertOutcome>> {
+		let start = Instant::now();
+		let rows = batch.tweets.len() + batch.entries.len() + batch.weighted.len();
+		let seen = seen_ranges(batch.entries.iter().map(|(entry, _)| entry).chain(&batch.weighted));
+		let outcomes = self.storage.insert_batch(batch).await?;
+		self.mark_rollups_stale(seen);
+
+		let elapsed = start.elapsed();
+		debug!(
+			"Saved {} rows in {:.1}ms ({:.0} rows/s).",
+			rows,
+			elapsed.as_secs_f64() * 1000.0,
+			rows as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+		);
+		Ok(outcomes)
+	}
+
+	/// Remember the time ranges of saved entries per keyword, so their rollups
+	/// are refreshed even if they are older than the periodic refresh covers.
+	pub fn mark_rollups_stale(&self, ranges: BTreeMap<String, (i64, i64)>) {
+		let mut stale = self.stale_rollups.lock().expect("Stale rollups lock poisoned!");
+		for (keyword, (first, last)) in ranges {
+			let range = stale.entry(keyword).or_insert((first, last));
+			range.0 = range.0.min(first);
+			range.1 = range.1.max(last);
+		}
+	}
+
+	/// Take the ranges of the rollups of the entries saved since the last call,
+	/// see `mark_rollups_stale`.
+	pub fn take_stale_rollups(&self) -> BTreeMap<String, (i64, i64)> {
+		std::mem::take(&mut *self.stale_rollups.lock().expect("Stale rollups lock poisoned!"))
+	}
+
+	/// Save the full data of a tweet
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn insert_tweet(&self, tweet: &StoredTweet) -> Result<()> {
+		self.storage.insert_tweet(tweet).await
+	}
+
+	/// Remove a deleted tweet: its sentiment entries and its stored data.
+	/// Returns the number of removed rows.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn delete_tweet(&self, source: &str, id: u64) -> Result<u64> {
+		let (removed, tweets) = self.storage.delete_tweet(source, id).await?;
+		self.mark_rollups_stale(time_ranges(
+			removed.iter().map(|(keyword, created)| (keyword.as_str(), *created)),
+		));
+		Ok(removed.len() as u64 + tweets)
+	}
+
+	/// Anonymise the stored data of a withheld tweet, or of all tweets of the
+	/// user if no tweet is given: the text and author are removed, the
+	/// sentiment entries are kept. Returns the number of anonymised rows.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn anonymise_tweets(
+		&self,
+		source: &str,
+		id: Option<u64>,
+		user_id: u64,
+	) -> Result<u64> {
+		self.storage.anonymise_tweets(source, id, user_id).await
+	}
+
+	/// Save an audit entry of a processed compliance message
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn record_compliance(&self, record: &ComplianceRecord) -> Result<()> {
+		self.storage.record_compliance(record).await
+	}
+
+	/// Count the processed compliance messages per kind
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn compliance_counts(&self) -> Result<Vec<(String, i64)>> {
+		self.storage.compliance_counts().await
+	}
+
+	/// Get the entries for a given keyword matching the filter
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn get(&self, keyword: &str, filter: &EntryFilter) -> Result<Vec<TweetSentiment>> {
+		self.storage.entries(keyword, filter).await
+	}
+
+	/// Get a page of the entries for a given keyword matching the filter, with
+	/// at most `limit` entries. The next page is requested with the returned
+	/// cursor as `after`.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn page(&self, keyword: &str, filter: &EntryFilter, limit: u32) -> Result<EntryPage> {
+		let filter = EntryFilter { limit: Some(limit), ..filter.clone() };
+		let entries = self.storage.entries(keyword, &filter).await?;
+		let next = match entries.last() {
+			Some(last) if entries.len() >= limit as usize => Some(EntryCursor::of(last)),
+			_ => None,
+		};
+		Ok(EntryPage { entries, next })
+	}
+
+	/// Checks if a given keyword exists in the database
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn exists(&self, keyword: &str) -> Result<bool> {
+		self.storage.exists(keyword).await
+	}
+
+	/// Get the backfill checkpoint for a given keyword
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn checkpoint(&self, keyword: &str) -> Result<Option<BackfillCheckpoint>> {
+		self.storage.checkpoint(keyword).await
+	}
+
+	/// Save a backfill checkpoint. A newer newest ID saved by the stream
+	/// meanwhile is kept.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn save_checkpoint(&self, checkpoint: &BackfillCheckpoint) -> Result<()> {
+		self.storage.save_checkpoint(checkpoint).await
+	}
+
+	/// Save the newest tweet ID of a keyword received by the stream, if newer
+	/// than the one of the backfill checkpoint. The backfill searches the
+	/// tweets after it on the next start.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn save_newest_id(&self, keyword: &str, newest_id: i64) -> Result<()> {
+		self.storage.save_newest_id(keyword, newest_id).await
+	}
+
+	/// List all keywords with their metadata, ordered by keyword
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn keywords(&self) -> Result<Vec<Keyword>> {
+		self.storage.keywords().await
+	}
+
+	/// Get a keyword with its metadata
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn keyword(&self, keyword: &str) -> Result<Option<Keyword>> {
+		self.storage.keyword(keyword).await
+	}
+
+	/// Save the rule of a tracked keyword, adding the keyword if missing. An
+	/// archived keyword or a keyword without rule becomes active with the rule.
+	/// The rule of other existing keywords is only replaced if `replace` is
+	/// true.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn track_keyword(&self, entry: &Keyword, replace: bool) -> Result<()> {
+		self.storage.track_keyword(entry, replace).await
+	}
+
+	/// Set the tracking status of a keyword. Returns whether the keyword
+	/// exists.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn set_keyword_status(&self, keyword: &str, status: KeywordStatus) -> Result<bool> {
+		self.storage.set_keyword_status(keyword, status).await
+	}
+
+	/// Update the display name, description or tags of a keyword. Returns
+	/// whether the keyword exists.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn update_keyword(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		self.storage.update_keyword(keyword, update).await
+	}
+
+	/// Recompute the rollups of all resolutions in the range. Buckets before
+	/// the day of the first entry of a keyword are kept, as their entries might
+	/// have been removed by the retention policy. Returns the number of buckets
+	/// written.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn refresh_rollups(&self, range: &RollupRange) -> Result<u64> {
+		self.storage.refresh_rollups(range).await
+	}
+
+	/// Remove the data of a keyword before the cutoff timestamp, or only count
+	/// it in a dry run. Returns the number of removed rows.
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn expire(
+		&self,
+		keyword: &str,
+		target: RetentionTarget,
+		cutoff: i64,
+		dry_run: bool,
+	) -> Result<u64> {
+		self.storage.expire(keyword, target, cutoff, dry_run).await
+	}
+
+	/// Get the rollups of a keyword in the given resolution with buckets
+	/// starting in the time range
+	#[tracing::instrument(level = "debug", err, skip(self))]
+	pub async fn rollups(
+		&self,
+		keyword: &str,
+		resolution: Resolution,
+		from: Option<i64>,
+		to: Option<i64>,
+	) -> Result<Vec<RollupBucket>> {
+		self.storage.rollups(keyword, resolution, from, to).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+
+	use color_eyre::Result;
+	use tempfile::TempDir;
+
+	use super::*;
+
+	/// Storage backends to run the tests against: in memory, SQLite in the
+	/// directory and Postgres if `TEST_POSTGRES_URL` is set.
+	async fn backends(dir: &TempDir) -> Result<Vec<SentimentDB>> {
+		let sqlite_url = format!("sqlite://{}", dir.path().join("test.db").display());
+		let mut backends = vec![SentimentDB::in_memory(), SentimentDB::connect(&sqlite_url).await?];
+		if let Ok(postgres_url) = env::var("TEST_POSTGRES_URL") {
+			backends.push(SentimentDB::connect(&postgres_url).await?);
+		}
+		Ok(backends)
+	}
+
+	/// Keyword unique to the test run, as the Postgres database is kept between
+	/// runs
+	fn unique(name: &str) -> String {
+		format!("{}-{}", name, OffsetDateTime::now_utc().unix_timestamp_nanos())
+	}
+
+	#[tokio::test]
+	async fn pages_through_entries_in_order() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("paging");
+		for db in backends(&dir).await? {
+			// Entries with equal times and sources, so pages have to continue by
+			// all parts of the cursor
+			let mut batch = EntryBatch::default();
+			for id in 0..25_u64 {
+				let source = if id % 2 == 0 { "twitter" } else { "mastodon" };
+				let created = 1_666_000_000 + id as i64 / 5;
+				let entry =
+					TweetSentiment::new(id, keyword.clone(), source.to_owned(), created, 0.5);
+				batch.entries.push((entry, ConflictPolicy::Ignore));
+			}
+			db.insert_batch(batch).await?;
+
+			let all = db.get(&keyword, &EntryFilter::default()).await?;
+			assert_eq!(all.len(), 25, "{:?}", db);
+			assert!(all
+				.windows(2)
+				.all(|pair| EntryCursor::of(&pair[0]) < EntryCursor::of(&pair[1])));
+
+			let mut paged = Vec::new();
+			let mut filter = EntryFilter::default();
+			loop {
+				let page = db.page(&keyword, &filter, 10).await?;
+				assert!(page.entries.len() <= 10);
+				paged.extend(page.entries);
+				match page.next {
+					Some(next) => filter.after = Some(next),
+					None => break,
+				}
+			}
+			assert_eq!(paged, all, "{:?}", db);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn resolves_conflicts_by_policy() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("conflicts");
+		// Retweets add weight to all entries of a tweet, so the tweet is unique too
+		let id = OffsetDateTime::now_utc().unix_timestamp_nanos() as u64;
+		let entry = |sentiment, model_version| TweetSentiment {
+			model_version,
+			..TweetSentiment::new(
+				id,
+				keyword.clone(),
+				"twitter".to_owned(),
+				1_666_000_000,
+				sentiment,
+			)
+		};
+		for db in backends(&dir).await? {
+			let outcomes = vec![
+				db.insert(entry(0.5, 1), ConflictPolicy::Ignore).await?,
+				db.insert(entry(0.1, 1), ConflictPolicy::Ignore).await?,
+				db.insert(entry(0.2, 1), ConflictPolicy::KeepNewestModel).await?,
+				db.insert(entry(0.3, 2), ConflictPolicy::KeepNewestModel).await?,
+				db.insert(entry(-0.4, 1), ConflictPolicy::Overwrite).await?,
+				db.insert_or_add_weight(entry(0.9, 1)).await?,
+			];
+			use InsertOutcome::*;
+			assert_eq!(outcomes, vec![Inserted, Skipped, Skipped, Updated, Updated, Updated]);
+			assert_eq!(db.add_weight("twitter", id).await?, 1);
+
+			let entries = db.get(&keyword, &EntryFilter::default()).await?;
+			assert_eq!(entries, vec![TweetSentiment { weight: 3, ..entry(-0.4, 1) }], "{:?}", db);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn counts_duplicates_within_a_batch() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("duplicates");
+		let entry = |id, sentiment| {
+			TweetSentiment::new(id, keyword.clone(), "twitter".to_owned(), 1, sentiment)
+		};
+		for db in backends(&dir).await? {
+			let batch = EntryBatch {
+				tweets: Vec::new(),
+				entries: vec![
+					(entry(1, 0.5), ConflictPolicy::Ignore),
+					(entry(1, 0.1), ConflictPolicy::Ignore),
+					(entry(1, -0.4), ConflictPolicy::Overwrite),
+					(entry(2, 0.3), ConflictPolicy::Ignore),
+				],
+				weighted: vec![entry(2, 0.9), entry(3, 0.2), entry(3, 0.8)],
+			};
+			let outcomes = db.insert_batch_outcomes(batch).await?;
+			use InsertOutcome::*;
+			assert_eq!(outcomes, vec![Inserted, Skipped, Updated, Inserted], "{:?}", db);
+			let counts: InsertCounts = outcomes.into_iter().collect();
+			assert_eq!(counts, InsertCounts { inserted: 2, updated: 1, skipped: 1 });
+
+			let entries = db.get(&keyword, &EntryFilter::default()).await?;
+			let expected = vec![
+				entry(1, -0.4),
+				TweetSentiment { weight: 2, ..entry(2, 0.3) },
+				TweetSentiment { weight: 2, ..entry(3, 0.2) },
+			];
+			assert_eq!(entries, expected, "{:?}", db);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn refreshes_and_expires_rollups() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("rollups");
+		let start = Resolution::Day.bucket(1_666_000_000);
+		for db in backends(&dir).await? {
+			let mut batch = EntryBatch::default();
+			for (id, offset, sentiment) in [(1, 0, 0.5), (2, 30, -0.5), (3, 2 * 60 * 60, 1.0)] {
+				let created = start + offset;
+				let entry = TweetSentiment::new(
+					id,
+					keyword.clone(),
+					"twitter".to_owned(),
+					created,
+					sentiment,
+				);
+				batch.entries.push((entry, ConflictPolicy::Ignore));
+			}
+			db.insert_batch(batch).await?;
+			db.refresh_rollups(&RollupRange::covering(&keyword, start, start + 2 * 60 * 60))
+				.await?;
+
+			let hours = db.rollups(&keyword, Resolution::Hour, None, None).await?;
+			let hours: Vec<_> =
+				hours.iter().map(|bucket| (bucket.bucket, bucket.count, bucket.sum)).collect();
+			assert_eq!(hours, vec![(start, 2, 0.0), (start + 2 * 60 * 60, 1, 1.0)], "{:?}", db);
+			let days = db.rollups(&keyword, Resolution::Day, None, None).await?;
+			let days: Vec<_> = days
+				.iter()
+				.map(|bucket| (bucket.count, bucket.positive, bucket.negative))
+				.collect();
+			assert_eq!(days, vec![(3, 2, 1)], "{:?}", db);
+
+			let cutoff = start + 60 * 60;
+			assert_eq!(db.expire(&keyword, RetentionTarget::Raw, cutoff, true).await?, 2);
+			assert_eq!(db.expire(&keyword, RetentionTarget::Raw, cutoff, false).await?, 2);
+			assert_eq!(db.get(&keyword, &EntryFilter::default()).await?.len(), 1);
+			let hour = RetentionTarget::Rollup(Resolution::Hour);
+			assert_eq!(db.expire(&keyword, hour, cutoff, false).await?, 1);
+			assert_eq!(db.rollups(&keyword, Resolution::Hour, None, None).await?.len(), 1);
+
+			db.refresh_rollups(&RollupRange::covering(&keyword, start, start + 2 * 60 * 60))
+				.await?;
+			let days = db.rollups(&keyword, Resolution::Day, None, None).await?;
+			assert_eq!(days.iter().map(|bucket| bucket.count).collect::<Vec<_>>(), vec![1]);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn refreshes_rollups_after_deletions_and_retweets() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("stale");
+		let start = Resolution::Day.bucket(1_666_000_000);
+		// Deletions and retweets apply to all entries of a tweet, so the tweets
+		// are unique too
+		let id = OffsetDateTime::now_utc().unix_timestamp_nanos() as u64;
+		for db in backends(&dir).await? {
+			let mut batch = EntryBatch::default();
+			for (id, offset, sentiment) in [(id, 0, 0.5), (id + 1, 30, -0.5)] {
+				let entry = TweetSentiment::new(
+					id,
+					keyword.clone(),
+					"twitter".to_owned(),
+					start + offset,
+					sentiment,
+				);
+				batch.entries.push((entry, ConflictPolicy::Ignore));
+			}
+			db.insert_batch(batch).await?;
+			db.refresh_rollups(&RollupRange::covering(&keyword, start, start)).await?;
+			db.take_stale_rollups();
+
+			assert_eq!(db.add_weight("twitter", id).await?, 1);
+			assert_eq!(db.delete_tweet("twitter", id + 1).await?, 1);
+			let stale = db.take_stale_rollups();
+			assert_eq!(stale.get(&keyword), Some(&(start, start + 30)), "{:?}", db);
+			for (keyword, (first, last)) in stale {
+				db.refresh_rollups(&RollupRange::covering(&keyword, first, last)).await?;
+			}
+
+			let hours = db.rollups(&keyword, Resolution::Hour, None, None).await?;
+			let hours: Vec<_> = hours
+				.iter()
+				.map(|bucket| (bucket.count, bucket.sum, bucket.positive, bucket.negative))
+				.collect();
+			assert_eq!(hours, vec![(2, 1.0, 2, 0)], "{:?}", db);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn saves_checkpoints_and_keywords() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let keyword = unique("checkpoints");
+		for db in backends(&dir).await? {
+			let mut checkpoint = BackfillCheckpoint::new(&keyword);
+			checkpoint.oldest_id = Some(10);
+			checkpoint.newest_id = Some(20);
+			db.save_checkpoint(&checkpoint).await?;
+			// The stream saves newer IDs meanwhile, which are kept
+			db.save_newest_id(&keyword, 30).await?;
+			db.save_newest_id(&keyword, 25).await?;
+			checkpoint.complete = true;
+			db.save_checkpoint(&checkpoint).await?;
+			let saved = db.checkpoint(&keyword).await?.expect("Checkpoint is missing");
+			assert_eq!(
+				(saved.oldest_id, saved.newest_id, saved.complete),
+				(Some(10), Some(30), true)
+			);
+
+			db.track_keyword(&Keyword::tracked(&KeywordRule::new(keyword.clone()))?, false).await?;
+			let update = KeywordUpdate {
+				display_name: Some("Checkpoints".to_owned()),
+				description: None,
+				tags: Some(vec![" test ".to_owned(), String::new()]),
+			};
+			assert!(db.update_keyword(&keyword, &update).await?);
+			assert!(db.set_keyword_status(&keyword, KeywordStatus::Paused).await?);
+			assert!(!db.set_keyword_status("missing", KeywordStatus::Paused).await?);
+
+			let saved = db.keyword(&keyword).await?.expect("Keyword is missing");
+			assert_eq!(saved.name(), "Checkpoints");
+			assert_eq!(saved.tags(), vec!["test"]);
+			assert_eq!(saved.status(), KeywordStatus::Paused);
+			assert_eq!(saved.rule().transpose()?.map(|rule| rule.keyword), Some(keyword.clone()));
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,772 @@
+//! Storage in a Postgres database.
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	hash::Hash,
+};
+
+use async_trait::async_trait;
+use sqlx::{PgPool, Postgres, Result, Transaction};
+use time::OffsetDateTime;
+
+use super::{
+	conflict_clause, seen_ranges, BackfillCheckpoint, ComplianceRecord, EntryBatch, EntryFilter,
+	InsertOutcome, Keyword, KeywordStatus, KeywordUpdate, Resolution, RetentionTarget,
+	RollupBucket, RollupRange, Storage, StoredTweet, TweetSentiment, ADD_WEIGHT_CLAUSE,
+};
+use crate::settings::ConflictPolicy;
+
+/// Storage in a Postgres database
+#[derive(Debug)]
+pub struct PostgresStorage {
+	pool: PgPool,
+}
+
+impl PostgresStorage {
+	/// Create storage for the DB pool
+	pub fn new(pool: PgPool) -> Self {
+		PostgresStorage { pool }
+	}
+
+	/// Connect to the database at the URL and run the migrations
+	pub async fn connect(url: &str) -> Result<Self> {
+		let pool = PgPool::connect(url).await?;
+		sqlx::migrate!("migrations/postgres").run(&pool).await?;
+		Ok(Self::new(pool))
+	}
+}
+
+impl TweetSentiment {
+	/// Save an entry to the database, resolving a conflict with an existing
+	/// entry for the tweet by the policy
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn insert(self, db: &PgPool, policy: ConflictPolicy) -> Result<InsertOutcome> {
+		self.insert_with(db, conflict_clause(policy)).await
+	}
+
+	/// Save an entry to the database. If an entry for the tweet exists
+	/// already, the weight of this entry is added to it.
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn insert_or_add_weight(self, db: &PgPool) -> Result<InsertOutcome> {
+		self.insert_with(db, ADD_WEIGHT_CLAUSE).await
+	}
+
+	/// Save many entries with one statement in the transaction, using the
+	/// given conflict clause. The entries must be unique per tweet and
+	/// keyword. Returns the outcomes in the order of the entries.
+	async fn insert_many(
+		entries: &[Self],
+		tx: &mut Transaction<'_, Postgres>,
+		on_conflict: &str,
+	) -> Result<Vec<InsertOutcome>> {
+		if entries.is_empty() {
+			return Ok(Vec::new());
+		}
+		let query = format!(
+			r#"INSERT INTO tweet_sentiment
+				(id, keyword, source, created, sentiment, retweet, weight, lang, model_version)
+				SELECT * FROM UNNEST($1::BIGINT[], $2::VARCHAR[], $3::VARCHAR[], $4::BIGINT[],
+					$5::DOUBLE PRECISION[], $6::BOOLEAN[], $7::INTEGER[], $8::VARCHAR[],
+					$9::INTEGER[])
+				{}
+				RETURNING keyword, source, id, (xmax = 0) AS inserted
+			"#,
+			on_conflict
+		);
+		let saved: Vec<(String, String, i64, bool)> = sqlx::query_as(&query)
+			.bind(entries.iter().map(|entry| entry.id).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.keyword.clone()).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.source.clone()).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.created).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.sentiment).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.retweet).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.weight).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.lang.clone()).collect::<Vec<_>>())
+			.bind(entries.iter().map(|entry| entry.model_version).collect::<Vec<_>>())
+			.fetch_all(&mut *tx)
+			.await?;
+
+		// Entries with ignored conflicts return no row.
+		let inserted: HashMap<(String, String, i64), bool> = saved
+			.into_iter()
+			.map(|(keyword, source, id, inserted)| ((keyword, source, id), inserted))
+			.collect();
+		Ok(entries
+			.iter()
+			.map(|entry| match inserted.get(&entry_key(entry)) {
+				Some(true) => InsertOutcome::Inserted,
+				Some(false) => InsertOutcome::Updated,
+				None => InsertOutcome::Skipped,
+			})
+			.collect())
+	}
+
+	/// Save an entry to the database with the given conflict clause
+	async fn insert_with(self, db: &PgPool, on_conflict: &str) -> Result<InsertOutcome> {
+		let seen = seen_ranges([&self]);
+		let mut tx = db.begin().await?;
+		// `xmax` is 0 for inserted rows, no row is returned if the conflict
+		// was ignored.
+		let query = format!(
+			r#"INSERT INTO tweet_sentiment
+				(id, keyword, source, created, sentiment, retweet, weight, lang, model_version)
+				VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+				{}
+				RETURNING (xmax = 0) AS inserted
+			"#,
+			on_conflict
+		);
+		let inserted: Option<bool> = sqlx::query_scalar(&query)
+			.bind(self.id)
+			.bind(self.keyword)
+			.bind(self.source)
+			.bind(self.created)
+			.bind(self.sentiment)
+			.bind(self.retweet)
+			.bind(self.weight)
+			.bind(self.lang)
+			.bind(self.model_version)
+			.fetch_optional(&mut tx)
+			.await?;
+		Keyword::mark_seen(&seen, &mut tx).await?;
+		tx.commit().await?;
+		Ok(match inserted {
+			Some(true) => InsertOutcome::Inserted,
+			Some(false) => InsertOutcome::Updated,
+			None => InsertOutcome::Skipped,
+		})
+	}
+
+	/// Add weight to the entries of a tweet. Returns the keywords and creation
+	/// times of the updated entries.
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn add_weight(
+		db: &PgPool,
+		source: &str,
+		id: i64,
+		weight: i32,
+	) -> Result<Vec<(String, i64)>> {
+		sqlx::query_as(
+			r#"UPDATE tweet_sentiment SET weight = weight + $3 WHERE source = $1 AND id = $2
+				RETURNING keyword, created
+			"#,
+		)
+		.bind(source)
+		.bind(id)
+		.bind(weight)
+		.fetch_all(db)
+		.await
+	}
+
+	/// Get the entries for a given keyword matching the filter
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn with_keyword(db: &PgPool, keyword: &str, filter: &EntryFilter) -> Result<Vec<Self>> {
+		let entries = sqlx::query_as(
+			r#"SELECT id, keyword, source, created, sentiment, retweet, weight, lang, model_version
+				FROM tweet_sentiment
+				WHERE keyword = $1
+					AND ($2::VARCHAR IS NULL OR source = $2)
+					AND ($3::VARCHAR IS NULL OR lang = $3)
+					AND ($4::BIGINT IS NULL OR created >= $4)
+					AND ($5::BIGINT IS NULL OR created < $5)
+					AND ($6::BIGINT IS NULL OR (created, source, id) > ($6, $7::VARCHAR, $8::BIGINT))
+				ORDER BY created ASC, source ASC, id ASC
+				LIMIT $9
+			"#,
+		)
+		.bind(keyword)
+		.bind(&filter.source)
+		.bind(&filter.lang)
+		.bind(filter.from)
+		.bind(filter.to)
+		.bind(filter.after.as_ref().map(|cursor| cursor.created))
+		.bind(filter.after.as_ref().map(|cursor| cursor.source.clone()))
+		.bind(filter.after.as_ref().map(|cursor| cursor.id))
+		.bind(filter.limit.map(i64::from))
+		.fetch_all(db)
+		.await?;
+		Ok(entries)
+	}
+}
+
+impl StoredTweet {
+	/// Save the tweet to the database. If it exists already, the counts are
+	/// updated.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	async fn upsert(&self, db: &PgPool) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO tweets
+				(source, id, created, text, lang, author_id, author_followers, retweet_count,
+					like_count, reply_to, quote_of, retweet_of, client)
+				VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+				ON CONFLICT (source, id) DO UPDATE SET
+					author_followers = COALESCE(EXCLUDED.author_followers, tweets.author_followers),
+					retweet_count = COALESCE(EXCLUDED.retweet_count, tweets.retweet_count),
+					like_count = COALESCE(EXCLUDED.like_count, tweets.like_count)
+			"#,
+		)
+		.bind(&self.source)
+		.bind(self.id)
+		.bind(self.created)
+		.bind(&self.text)
+		.bind(&self.lang)
+		.bind(self.author_id)
+		.bind(self.author_followers)
+		.bind(self.retweet_count)
+		.bind(self.like_count)
+		.bind(self.reply_to)
+		.bind(self.quote_of)
+		.bind(self.retweet_of)
+		.bind(&self.client)
+		.execute(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Save many tweets with one statement in the transaction, like `upsert`.
+	/// The tweets must be unique per source and ID.
+	async fn upsert_many(tweets: &[Self], tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+		if tweets.is_empty() {
+			return Ok(());
+		}
+		sqlx::query(
+			r#"INSERT INTO tweets
+				(source, id, created, text, lang, author_id, author_followers, retweet_count,
+					like_count, reply_to, quote_of, retweet_of, client)
+				SELECT * FROM UNNEST($1::VARCHAR[], $2::BIGINT[], $3::BIGINT[], $4::TEXT[],
+					$5::VARCHAR[], $6::BIGINT[], $7::INTEGER[], $8::INTEGER[], $9::INTEGER[],
+					$10::BIGINT[], $11::BIGINT[], $12::BIGINT[], $13::VARCHAR[])
+				ON CONFLICT (source, id) DO UPDATE SET
+					author_followers = COALESCE(EXCLUDED.author_followers, tweets.author_followers),
+					retweet_count = COALESCE(EXCLUDED.retweet_count, tweets.retweet_count),
+					like_count = COALESCE(EXCLUDED.like_count, tweets.like_count)
+			"#,
+		)
+		.bind(tweets.iter().map(|tweet| tweet.source.clone()).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.id).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.created).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.text.clone()).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.lang.clone()).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.author_id).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.author_followers).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.retweet_count).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.like_count).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.reply_to).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.quote_of).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.retweet_of).collect::<Vec<_>>())
+		.bind(tweets.iter().map(|tweet| tweet.client.clone()).collect::<Vec<_>>())
+		.execute(&mut *tx)
+		.await?;
+		Ok(())
+	}
+}
+
+impl ComplianceRecord {
+	/// Save the entry to the database
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn insert(&self, db: &PgPool) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO compliance_audit
+				(source, kind, tweet_id, user_id, countries, rows_affected, processed)
+				VALUES ($1, $2, $3, $4, $5, $6, $7)
+			"#,
+		)
+		.bind(&self.source)
+		.bind(&self.kind)
+		.bind(self.tweet_id)
+		.bind(self.user_id)
+		.bind(&self.countries)
+		.bind(self.rows_affected)
+		.bind(self.processed)
+		.execute(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Count the processed messages per kind
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn counts(db: &PgPool) -> Result<Vec<(String, i64)>> {
+		let counts = sqlx::query_as(
+			r#"SELECT kind, COUNT(*) FROM compliance_audit GROUP BY kind ORDER BY kind ASC"#,
+		)
+		.fetch_all(db)
+		.await?;
+		Ok(counts)
+	}
+}
+
+impl BackfillCheckpoint {
+	/// Save the checkpoint to the database, replacing the previous one but
+	/// keeping a newer newest ID
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn upsert(&self, db: &PgPool) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO backfill_checkpoint
+				(keyword, oldest_id, newest_id, complete, updated, gap_since_id)
+				VALUES ($1, $2, $3, $4, $5, $6)
+				ON CONFLICT (keyword) DO UPDATE SET
+					oldest_id = EXCLUDED.oldest_id,
+					newest_id = GREATEST(backfill_checkpoint.newest_id, EXCLUDED.newest_id),
+					complete = EXCLUDED.complete,
+					updated = EXCLUDED.updated,
+					gap_since_id = EXCLUDED.gap_since_id
+			"#,
+		)
+		.bind(&self.keyword)
+		.bind(self.oldest_id)
+		.bind(self.newest_id)
+		.bind(self.complete)
+		.bind(self.updated)
+		.bind(self.gap_since_id)
+		.execute(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Raise the newest ID of the checkpoint of a keyword, adding the
+	/// checkpoint if missing
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn raise_newest_id(db: &PgPool, keyword: &str, newest_id: i64) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO backfill_checkpoint (keyword, newest_id, updated)
+				VALUES ($1, $2, $3)
+				ON CONFLICT (keyword) DO UPDATE SET
+					newest_id = GREATEST(backfill_checkpoint.newest_id, EXCLUDED.newest_id),
+					updated = EXCLUDED.updated
+			"#,
+		)
+		.bind(keyword)
+		.bind(newest_id)
+		.bind(OffsetDateTime::now_utc().unix_timestamp())
+		.execute(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Get the checkpoint for a given keyword
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn with_keyword(db: &PgPool, keyword: &str) -> Result<Option<Self>> {
+		let checkpoint = sqlx::query_as(
+			r#"SELECT keyword, oldest_id, newest_id, complete, updated, gap_since_id
+				FROM backfill_checkpoint
+				WHERE keyword = $1
+			"#,
+		)
+		.bind(keyword)
+		.fetch_optional(db)
+		.await?;
+		Ok(checkpoint)
+	}
+}
+
+impl Keyword {
+	/// Save the rule of the keyword, adding the keyword if missing. An
+	/// existing rule is only replaced if `replace` is true or the keyword is
+	/// not tracked.
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn upsert_rule(&self, db: &PgPool, replace: bool) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO keywords
+				(keyword, display_name, description, rule, status, tags, created)
+				VALUES ($1, $2, $3, $4, $5, $6, $7)
+				ON CONFLICT (keyword) DO UPDATE SET
+					rule = CASE WHEN $8::BOOLEAN OR keywords.rule IS NULL OR keywords.status = 'archived'
+						THEN EXCLUDED.rule ELSE keywords.rule END,
+					status = CASE WHEN keywords.rule IS NULL OR keywords.status = 'archived'
+						THEN EXCLUDED.status ELSE keywords.status END
+			"#,
+		)
+		.bind(&self.keyword)
+		.bind(&self.display_name)
+		.bind(&self.description)
+		.bind(&self.rule)
+		.bind(&self.status)
+		.bind(&self.tags)
+		.bind(self.created)
+		.bind(replace)
+		.execute(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Extend the first and last seen times of the keywords in the
+	/// transaction, adding missing keywords
+	async fn mark_seen(
+		seen: &BTreeMap<String, (i64, i64)>,
+		tx: &mut Transaction<'_, Postgres>,
+	) -> Result<()> {
+		if seen.is_empty() {
+			return Ok(());
+		}
+		sqlx::query(
+			r#"INSERT INTO keywords (keyword, created, first_seen, last_seen)
+				SELECT keyword, $4, first_seen, last_seen
+				FROM UNNEST($1::VARCHAR[], $2::BIGINT[], $3::BIGINT[])
+					AS seen (keyword, first_seen, last_seen)
+				ON CONFLICT (keyword) DO UPDATE SET
+					first_seen = LEAST(keywords.first_seen, EXCLUDED.first_seen),
+					last_seen = GREATEST(keywords.last_seen, EXCLUDED.last_seen)
+			"#,
+		)
+		.bind(seen.keys().cloned().collect::<Vec<_>>())
+		.bind(seen.values().map(|range| range.0).collect::<Vec<_>>())
+		.bind(seen.values().map(|range| range.1).collect::<Vec<_>>())
+		.bind(OffsetDateTime::now_utc().unix_timestamp())
+		.execute(&mut *tx)
+		.await?;
+		Ok(())
+	}
+
+	/// Get all keywords
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn all(db: &PgPool) -> Result<Vec<Self>> {
+		let keywords = sqlx::query_as(
+			r#"SELECT keyword, display_name, description, rule, status, tags, created, first_seen,
+					last_seen
+				FROM keywords
+				ORDER BY keyword ASC
+			"#,
+		)
+		.fetch_all(db)
+		.await?;
+		Ok(keywords)
+	}
+
+	/// Get a keyword
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn with_keyword(db: &PgPool, keyword: &str) -> Result<Option<Self>> {
+		let keyword = sqlx::query_as(
+			r#"SELECT keyword, display_name, description, rule, status, tags, created, first_seen,
+					last_seen
+				FROM keywords
+				WHERE keyword = $1
+			"#,
+		)
+		.bind(keyword)
+		.fetch_optional(db)
+		.await?;
+		Ok(keyword)
+	}
+
+	/// Set the tracking status of a keyword. Returns whether the keyword
+	/// exists.
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn set_status(db: &PgPool, keyword: &str, status: KeywordStatus) -> Result<bool> {
+		let result = sqlx::query(r#"UPDATE keywords SET status = $2 WHERE keyword = $1"#)
+			.bind(keyword)
+			.bind(status.as_str())
+			.execute(db)
+			.await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	/// Update the metadata of a keyword. Returns whether the keyword exists.
+	#[tracing::instrument(level = "debug", err, skip(db))]
+	async fn update(db: &PgPool, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		let result = sqlx::query(
+			r#"UPDATE keywords SET
+					display_name = NULLIF(COALESCE($2, display_name), ''),
+					description = NULLIF(COALESCE($3, description), ''),
+					tags = COALESCE($4, tags)
+				WHERE keyword = $1
+			"#,
+		)
+		.bind(keyword)
+		.bind(&update.display_name)
+		.bind(&update.description)
+		.bind(update.tags_column())
+		.execute(db)
+		.await?;
+		Ok(result.rows_affected() > 0)
+	}
+}
+
+/// Key of an entry in the `tweet_sentiment` table
+fn entry_key(entry: &TweetSentiment) -> (String, String, i64) {
+	(entry.keyword.clone(), entry.source.clone(), entry.id)
+}
+
+/// Split rows into rounds with unique keys, as rows of one statement have to
+/// be unique for the conflict clauses. The n-th row of a key goes into the
+/// n-th round, so rows of the same key are saved in order, like row by row.
+fn unique_rounds<T, K: Hash + Eq>(rows: Vec<T>, key: impl Fn(&T) -> K) -> Vec<Vec<T>> {
+	let mut occurrences = HashMap::new();
+	let mut rounds: Vec<Vec<T>> = Vec::new();
+	for row in rows {
+		let round = occurrences.entry(key(&row)).or_insert(0_usize);
+		if *round == rounds.len() {
+			rounds.push(Vec::new());
+		}
+		rounds[*round].push(row);
+		*round += 1;
+	}
+	rounds
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+	async fn insert(&self, entry: TweetSentiment, policy: ConflictPolicy) -> Result<InsertOutcome> {
+		entry.insert(&self.pool, policy).await
+	}
+
+	async fn insert_or_add_weight(&self, entry: TweetSentiment) -> Result<InsertOutcome> {
+		entry.insert_or_add_weight(&self.pool).await
+	}
+
+	async fn add_weight(&self, source: &str, id: u64) -> Result<Vec<(String, i64)>> {
+		TweetSentiment::add_weight(&self.pool, source, id as i64, 1).await
+	}
+
+	async fn insert_batch(&self, batch: EntryBatch) -> Result<Vec<InsertOutcome>> {
+		let seen = seen_ranges(batch.entries.iter().map(|(entry, _)| entry).chain(&batch.weighted));
+		let mut tx = self.pool.begin().await?;
+		for tweets in unique_rounds(batch.tweets, |tweet| (tweet.source.clone(), tweet.id)) {
+			StoredTweet::upsert_many(&tweets, &mut tx).await?;
+		}
+		// Outcomes are put back at the positions of the entries.
+		let mut outcomes = vec![InsertOutcome::Skipped; batch.entries.len()];
+		let entries = batch.entries.into_iter().enumerate().collect();
+		for entries in unique_rounds(entries, |(_, (entry, _))| entry_key(entry)) {
+			let mut by_policy: HashMap<ConflictPolicy, (Vec<usize>, Vec<TweetSentiment>)> =
+				HashMap::new();
+			for (index, (entry, policy)) in entries {
+				let (indices, entries) = by_policy.entry(policy).or_default();
+				indices.push(index);
+				entries.push(entry);
+			}
+			for (policy, (indices, entries)) in by_policy {
+				let saved =
+					TweetSentiment::insert_many(&entries, &mut tx, conflict_clause(policy)).await?;
+				for (index, outcome) in indices.into_iter().zip(saved) {
+					outcomes[index] = outcome;
+				}
+			}
+		}
+		for weighted in unique_rounds(batch.weighted, entry_key) {
+			TweetSentiment::insert_many(&weighted, &mut tx, ADD_WEIGHT_CLAUSE).await?;
+		}
+		Keyword::mark_seen(&seen, &mut tx).await?;
+		tx.commit().await?;
+		Ok(outcomes)
+	}
+
+	async fn insert_tweet(&self, tweet: &StoredTweet) -> Result<()> {
+		tweet.upsert(&self.pool).await
+	}
+
+	async fn delete_tweet(&self, source: &str, id: u64) -> Result<(Vec<(String, i64)>, u64)> {
+		let mut tx = self.pool.begin().await?;
+		let removed = sqlx::query_as(
+			r#"DELETE FROM tweet_sentiment WHERE source = $1 AND id = $2 RETURNING keyword, created"#,
+		)
+		.bind(source)
+		.bind(id as i64)
+		.fetch_all(&mut tx)
+		.await?;
+		let tweets = sqlx::query(r#"DELETE FROM tweets WHERE source = $1 AND id = $2"#)
+			.bind(source)
+			.bind(id as i64)
+			.execute(&mut tx)
+			.await?;
+		tx.commit().await?;
+		Ok((removed, tweets.rows_affected()))
+	}
+
+	async fn anonymise_tweets(&self, source: &str, id: Option<u64>, user_id: u64) -> Result<u64> {
+		let result = sqlx::query(
+			r#"UPDATE tweets SET text = NULL, author_id = NULL, author_followers = NULL
+				WHERE source = $1 AND CASE WHEN $2::BIGINT IS NULL THEN author_id = $3 ELSE id = $2 END
+			"#,
+		)
+		.bind(source)
+		.bind(id.map(|id| id as i64))
+		.bind(user_id as i64)
+		.execute(&self.pool)
+		.await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn record_compliance(&self, record: &ComplianceRecord) -> Result<()> {
+		record.insert(&self.pool).await
+	}
+
+	async fn compliance_counts(&self) -> Result<Vec<(String, i64)>> {
+		ComplianceRecord::counts(&self.pool).await
+	}
+
+	async fn entries(&self, keyword: &str, filter: &EntryFilter) -> Result<Vec<TweetSentiment>> {
+		TweetSentiment::with_keyword(&self.pool, keyword, filter).await
+	}
+
+	async fn exists(&self, keyword: &str) -> Result<bool> {
+		let exists = sqlx::query_scalar(
+			r#"SELECT EXISTS (
+				SELECT 1 FROM tweet_sentiment WHERE keyword = $1
+			)"#,
+		)
+		.bind(keyword)
+		.fetch_one(&self.pool)
+		.await?;
+		Ok(exists)
+	}
+
+	async fn checkpoint(&self, keyword: &str) -> Result<Option<BackfillCheckpoint>> {
+		BackfillCheckpoint::with_keyword(&self.pool, keyword).await
+	}
+
+	async fn save_checkpoint(&self, checkpoint: &BackfillCheckpoint) -> Result<()> {
+		checkpoint.upsert(&self.pool).await
+	}
+
+	async fn save_newest_id(&self, keyword: &str, newest_id: i64) -> Result<()> {
+		BackfillCheckpoint::raise_newest_id(&self.pool, keyword, newest_id).await
+	}
+
+	async fn keywords(&self) -> Result<Vec<Keyword>> {
+		Keyword::all(&self.pool).await
+	}
+
+	async fn keyword(&self, keyword: &str) -> Result<Option<Keyword>> {
+		Keyword::with_keyword(&self.pool, keyword).await
+	}
+
+	async fn track_keyword(&self, entry: &Keyword, replace: bool) -> Result<()> {
+		entry.upsert_rule(&self.pool, replace).await
+	}
+
+	async fn set_keyword_status(&self, keyword: &str, status: KeywordStatus) -> Result<bool> {
+		Keyword::set_status(&self.pool, keyword, status).await
+	}
+
+	async fn update_keyword(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		Keyword::update(&self.pool, keyword, update).await
+	}
+
+	async fn refresh_rollups(&self, range: &RollupRange) -> Result<u64> {
+		let mut tx = self.pool.begin().await?;
+		// Buckets before the day of the first entry of a keyword are kept, their
+		// entries might have been removed by the retention policy.
+		let (keywords, first_days): (Vec<String>, Vec<i64>) =
+			sqlx::query_as::<_, (String, Option<i64>)>(
+				r#"SELECT keyword, (
+						SELECT MIN(created) FROM tweet_sentiment
+						WHERE tweet_sentiment.keyword = keywords.keyword
+					)
+					FROM keywords
+					WHERE $1::VARCHAR IS NULL OR keyword = $1
+				"#,
+			)
+			.bind(&range.keyword)
+			.fetch_all(&mut tx)
+			.await?
+			.into_iter()
+			.filter_map(|(keyword, first)| Some((keyword, Resolution::Day.bucket(first?))))
+			.unzip();
+		let mut written = 0;
+		for resolution in Resolution::ALL {
+			let start = resolution.bucket(range.from);
+			let end = range.to.map(|to| resolution.bucket(to));
+			sqlx::query(
+				r#"DELETE FROM sentiment_rollup
+					USING UNNEST($4::VARCHAR[], $5::BIGINT[]) AS first_days (keyword, first_day)
+					WHERE sentiment_rollup.keyword = first_days.keyword
+						AND resolution = $1 AND bucket >= $2
+						AND ($3::BIGINT IS NULL OR bucket < $3)
+						AND bucket >= first_days.first_day
+				"#,
+			)
+			.bind(resolution.as_str())
+			.bind(start)
+			.bind(end)
+			.bind(&keywords)
+			.bind(&first_days)
+			.execute(&mut tx)
+			.await?;
+			let result = sqlx::query(
+				r#"INSERT INTO sentiment_rollup
+					(keyword, resolution, bucket, count, sum, sum_squares, min, max, positive, negative)
+					SELECT keyword, $1::VARCHAR, created - created % $2::BIGINT, SUM(weight),
+						SUM(weight * sentiment), SUM(weight * sentiment * sentiment),
+						MIN(sentiment), MAX(sentiment),
+						SUM(CASE WHEN sentiment > 0 THEN weight ELSE 0 END),
+						SUM(CASE WHEN sentiment < 0 THEN weight ELSE 0 END)
+					FROM tweet_sentiment
+					WHERE created >= $3
+						AND ($4::BIGINT IS NULL OR created < $4)
+						AND ($5::VARCHAR IS NULL OR keyword = $5)
+					GROUP BY 1, 3
+				"#,
+			)
+			.bind(resolution.as_str())
+			.bind(resolution.seconds())
+			.bind(start)
+			.bind(end)
+			.bind(&range.keyword)
+			.execute(&mut tx)
+			.await?;
+			written += result.rows_affected();
+		}
+		tx.commit().await?;
+		Ok(written)
+	}
+
+	async fn expire(
+		&self,
+		keyword: &str,
+		target: RetentionTarget,
+		cutoff: i64,
+		dry_run: bool,
+	) -> Result<u64> {
+		let (table, condition) = match target {
+			RetentionTarget::Raw => ("tweet_sentiment", "created < $2"),
+			RetentionTarget::Rollup(_) => ("sentiment_rollup", "bucket < $2 AND resolution = $3"),
+		};
+		let resolution = match target {
+			RetentionTarget::Raw => None,
+			RetentionTarget::Rollup(resolution) => Some(resolution.as_str()),
+		};
+		if dry_run {
+			let query =
+				format!("SELECT COUNT(*) FROM {} WHERE keyword = $1 AND {}", table, condition);
+			let mut query = sqlx::query_scalar(&query).bind(keyword).bind(cutoff);
+			if let Some(resolution) = resolution {
+				query = query.bind(resolution);
+			}
+			let count: i64 = query.fetch_one(&self.pool).await?;
+			Ok(count as u64)
+		} else {
+			let query = format!("DELETE FROM {} WHERE keyword = $1 AND {}", table, condition);
+			let mut query = sqlx::query(&query).bind(keyword).bind(cutoff);
+			if let Some(resolution) = resolution {
+				query = query.bind(resolution);
+			}
+			Ok(query.execute(&self.pool).await?.rows_affected())
+		}
+	}
+
+	async fn rollups(
+		&self,
+		keyword: &str,
+		resolution: Resolution,
+		from: Option<i64>,
+		to: Option<i64>,
+	) -> Result<Vec<RollupBucket>> {
+		let buckets = sqlx::query_as(
+			r#"SELECT keyword, resolution, bucket, count, sum, sum_squares, min, max, positive,
+					negative
+				FROM sentiment_rollup
+				WHERE keyword = $1 AND resolution = $2
+					AND ($3::BIGINT IS NULL OR bucket >= $3)
+					AND ($4::BIGINT IS NULL OR bucket < $4)
+				ORDER BY bucket ASC
+			"#,
+		)
+		.bind(keyword)
+		.bind(resolution.as_str())
+		.bind(from)
+		.bind(to)
+		.fetch_all(&self.pool)
+		.await?;
+		Ok(buckets)
+	}
+}
@@ -0,0 +1,562 @@
+//! Storage in an SQLite database file, for deployments without a database
+//! server.
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use async_trait::async_trait;
+use sqlx::{
+	sqlite::{SqliteConnectOptions, SqliteConnection},
+	Result, SqlitePool,
+};
+use time::OffsetDateTime;
+
+use super::{
+	conflict_clause, seen_ranges, BackfillCheckpoint, ComplianceRecord, EntryBatch, EntryFilter,
+	InsertOutcome, Keyword, KeywordStatus, KeywordUpdate, Resolution, RetentionTarget,
+	RollupBucket, RollupRange, Storage, StoredTweet, TweetSentiment, ADD_WEIGHT_CLAUSE,
+};
+use crate::settings::ConflictPolicy;
+
+/// Storage in an SQLite database
+#[derive(Debug)]
+pub struct SqliteStorage {
+	pool: SqlitePool,
+}
+
+impl SqliteStorage {
+	/// Create storage for the DB pool
+	pub fn new(pool: SqlitePool) -> Self {
+		SqliteStorage { pool }
+	}
+
+	/// Open or create the database file at the URL and run the migrations
+	pub async fn connect(url: &str) -> Result<Self> {
+		let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+		let pool = SqlitePool::connect_with(options).await?;
+		sqlx::migrate!("migrations/sqlite").run(&pool).await?;
+		Ok(Self::new(pool))
+	}
+}
+
+/// Save an entry with the given conflict clause. SQLite does not tell whether
+/// a row was inserted or updated, so the existing entry is looked up first.
+/// Should run in a transaction.
+async fn insert_entry(
+	conn: &mut SqliteConnection,
+	entry: &TweetSentiment,
+	on_conflict: &str,
+) -> Result<InsertOutcome> {
+	let existed: bool = sqlx::query_scalar(
+		r#"SELECT EXISTS (
+			SELECT 1 FROM tweet_sentiment WHERE keyword = ?1 AND source = ?2 AND id = ?3
+		)"#,
+	)
+	.bind(&entry.keyword)
+	.bind(&entry.source)
+	.bind(entry.id)
+	.fetch_one(&mut *conn)
+	.await?;
+
+	let query = format!(
+		r#"INSERT INTO tweet_sentiment
+			(id, keyword, source, created, sentiment, retweet, weight, lang, model_version)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+			{}
+		"#,
+		on_conflict
+	);
+	let result = sqlx::query(&query)
+		.bind(entry.id)
+		.bind(&entry.keyword)
+		.bind(&entry.source)
+		.bind(entry.created)
+		.bind(entry.sentiment)
+		.bind(entry.retweet)
+		.bind(entry.weight)
+		.bind(&entry.lang)
+		.bind(entry.model_version)
+		.execute(&mut *conn)
+		.await?;
+
+	Ok(match (existed, result.rows_affected()) {
+		(false, _) => InsertOutcome::Inserted,
+		(true, 0) => InsertOutcome::Skipped,
+		(true, _) => InsertOutcome::Updated,
+	})
+}
+
+/// Save the full data of a tweet. If it exists already, the counts are
+/// updated.
+async fn upsert_tweet(conn: &mut SqliteConnection, tweet: &StoredTweet) -> Result<()> {
+	sqlx::query(
+		r#"INSERT INTO tweets
+			(source, id, created, text, lang, author_id, author_followers, retweet_count,
+				like_count, reply_to, quote_of, retweet_of, client)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+			ON CONFLICT (source, id) DO UPDATE SET
+				author_followers = COALESCE(EXCLUDED.author_followers, tweets.author_followers),
+				retweet_count = COALESCE(EXCLUDED.retweet_count, tweets.retweet_count),
+				like_count = COALESCE(EXCLUDED.like_count, tweets.like_count)
+		"#,
+	)
+	.bind(&tweet.source)
+	.bind(tweet.id)
+	.bind(tweet.created)
+	.bind(&tweet.text)
+	.bind(&tweet.lang)
+	.bind(tweet.author_id)
+	.bind(tweet.author_followers)
+	.bind(tweet.retweet_count)
+	.bind(tweet.like_count)
+	.bind(tweet.reply_to)
+	.bind(tweet.quote_of)
+	.bind(tweet.retweet_of)
+	.bind(&tweet.client)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Extend the first and last seen times of the keywords, adding missing
+/// keywords. Should run in a transaction.
+async fn mark_seen(conn: &mut SqliteConnection, seen: &BTreeMap<String, (i64, i64)>) -> Result<()> {
+	let now = OffsetDateTime::now_utc().unix_timestamp();
+	for (keyword, (first, last)) in seen {
+		sqlx::query(
+			r#"INSERT INTO keywords (keyword, created, first_seen, last_seen)
+				VALUES (?1, ?2, ?3, ?4)
+				ON CONFLICT (keyword) DO UPDATE SET
+					first_seen = MIN(COALESCE(keywords.first_seen, EXCLUDED.first_seen),
+						EXCLUDED.first_seen),
+					last_seen = MAX(COALESCE(keywords.last_seen, EXCLUDED.last_seen),
+						EXCLUDED.last_seen)
+			"#,
+		)
+		.bind(keyword)
+		.bind(now)
+		.bind(first)
+		.bind(last)
+		.execute(&mut *conn)
+		.await?;
+	}
+	Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+	async fn insert(&self, entry: TweetSentiment, policy: ConflictPolicy) -> Result<InsertOutcome> {
+		let mut tx = self.pool.begin().await?;
+		let outcome = insert_entry(&mut tx, &entry, conflict_clause(policy)).await?;
+		mark_seen(&mut tx, &seen_ranges([&entry])).await?;
+		tx.commit().await?;
+		Ok(outcome)
+	}
+
+	async fn insert_or_add_weight(&self, entry: TweetSentiment) -> Result<InsertOutcome> {
+		let mut tx = self.pool.begin().await?;
+		let outcome = insert_entry(&mut tx, &entry, ADD_WEIGHT_CLAUSE).await?;
+		mark_seen(&mut tx, &seen_ranges([&entry])).await?;
+		tx.commit().await?;
+		Ok(outcome)
+	}
+
+	async fn add_weight(&self, source: &str, id: u64) -> Result<Vec<(String, i64)>> {
+		sqlx::query_as(
+			r#"UPDATE tweet_sentiment SET weight = weight + 1 WHERE source = ?1 AND id = ?2
+				RETURNING keyword, created
+			"#,
+		)
+		.bind(source)
+		.bind(id as i64)
+		.fetch_all(&self.pool)
+		.await
+	}
+
+	async fn insert_batch(&self, batch: EntryBatch) -> Result<Vec<InsertOutcome>> {
+		// Statements in one transaction are cheap in SQLite, so no multi-row
+		// statements are needed.
+		let mut tx = self.pool.begin().await?;
+		for tweet in &batch.tweets {
+			upsert_tweet(&mut tx, tweet).await?;
+		}
+		let mut outcomes = Vec::with_capacity(batch.entries.len());
+		for (entry, policy) in &batch.entries {
+			outcomes.push(insert_entry(&mut tx, entry, conflict_clause(*policy)).await?);
+		}
+		for entry in &batch.weighted {
+			insert_entry(&mut tx, entry, ADD_WEIGHT_CLAUSE).await?;
+		}
+		let seen = seen_ranges(batch.entries.iter().map(|(entry, _)| entry).chain(&batch.weighted));
+		mark_seen(&mut tx, &seen).await?;
+		tx.commit().await?;
+		Ok(outcomes)
+	}
+
+	async fn insert_tweet(&self, tweet: &StoredTweet) -> Result<()> {
+		let mut conn = self.pool.acquire().await?;
+		upsert_tweet(&mut conn, tweet).await
+	}
+
+	async fn delete_tweet(&self, source: &str, id: u64) -> Result<(Vec<(String, i64)>, u64)> {
+		let mut tx = self.pool.begin().await?;
+		let removed = sqlx::query_as(
+			r#"DELETE FROM tweet_sentiment WHERE source = ?1 AND id = ?2 RETURNING keyword, created"#,
+		)
+		.bind(source)
+		.bind(id as i64)
+		.fetch_all(&mut tx)
+		.await?;
+		let tweets = sqlx::query(r#"DELETE FROM tweets WHERE source = ?1 AND id = ?2"#)
+			.bind(source)
+			.bind(id as i64)
+			.execute(&mut tx)
+			.await?;
+		tx.commit().await?;
+		Ok((removed, tweets.rows_affected()))
+	}
+
+	async fn anonymise_tweets(&self, source: &str, id: Option<u64>, user_id: u64) -> Result<u64> {
+		let result = sqlx::query(
+			r#"UPDATE tweets SET text = NULL, author_id = NULL, author_followers = NULL
+				WHERE source = ?1 AND CASE WHEN ?2 IS NULL THEN author_id = ?3 ELSE id = ?2 END
+			"#,
+		)
+		.bind(source)
+		.bind(id.map(|id| id as i64))
+		.bind(user_id as i64)
+		.execute(&self.pool)
+		.await?;
+		Ok(result.rows_affected())
+	}
+
+	async fn record_compliance(&self, record: &ComplianceRecord) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO compliance_audit
+				(source, kind, tweet_id, user_id, countries, rows_affected, processed)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+			"#,
+		)
+		.bind(&record.source)
+		.bind(&record.kind)
+		.bind(record.tweet_id)
+		.bind(record.user_id)
+		.bind(&record.countries)
+		.bind(record.rows_affected)
+		.bind(record.processed)
+		.execute(&self.pool)
+		.await?;
+		Ok(())
+	}
+
+	async fn compliance_counts(&self) -> Result<Vec<(String, i64)>> {
+		let counts = sqlx::query_as(
+			r#"SELECT kind, COUNT(*) FROM compliance_audit GROUP BY kind ORDER BY kind ASC"#,
+		)
+		.fetch_all(&self.pool)
+		.await?;
+		Ok(counts)
+	}
+
+	async fn entries(&self, keyword: &str, filter: &EntryFilter) -> Result<Vec<TweetSentiment>> {
+		let entries = sqlx::query_as(
+			r#"SELECT id, keyword, source, created, sentiment, retweet, weight, lang, model_version
+				FROM tweet_sentiment
+				WHERE keyword = ?1
+					AND (?2 IS NULL OR source = ?2)
+					AND (?3 IS NULL OR lang = ?3)
+					AND (?4 IS NULL OR created >= ?4)
+					AND (?5 IS NULL OR created < ?5)
+					AND (?6 IS NULL OR (created, source, id) > (?6, ?7, ?8))
+				ORDER BY created ASC, source ASC, id ASC
+				LIMIT COALESCE(?9, -1)
+			"#,
+		)
+		.bind(keyword)
+		.bind(&filter.source)
+		.bind(&filter.lang)
+		.bind(filter.from)
+		.bind(filter.to)
+		.bind(filter.after.as_ref().map(|cursor| cursor.created))
+		.bind(filter.after.as_ref().map(|cursor| cursor.source.clone()))
+		.bind(filter.after.as_ref().map(|cursor| cursor.id))
+		.bind(filter.limit.map(i64::from))
+		.fetch_all(&self.pool)
+		.await?;
+		Ok(entries)
+	}
+
+	async fn exists(&self, keyword: &str) -> Result<bool> {
+		let exists = sqlx::query_scalar(
+			r#"SELECT EXISTS (
+				SELECT 1 FROM tweet_sentiment WHERE keyword = ?1
+			)"#,
+		)
+		.bind(keyword)
+		.fetch_one(&self.pool)
+		.await?;
+		Ok(exists)
+	}
+
+	async fn checkpoint(&self, keyword: &str) -> Result<Option<BackfillCheckpoint>> {
+		let checkpoint = sqlx::query_as(
+			r#"SELECT keyword, oldest_id, newest_id, complete, updated, gap_since_id
+				FROM backfill_checkpoint
+				WHERE keyword = ?1
+			"#,
+		)
+		.bind(keyword)
+		.fetch_optional(&self.pool)
+		.await?;
+		Ok(checkpoint)
+	}
+
+	async fn save_checkpoint(&self, checkpoint: &BackfillCheckpoint) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO backfill_checkpoint
+				(keyword, oldest_id, newest_id, complete, updated, gap_since_id)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+				ON CONFLICT (keyword) DO UPDATE SET
+					oldest_id = EXCLUDED.oldest_id,
+					newest_id = MAX(
+						COALESCE(backfill_checkpoint.newest_id, EXCLUDED.newest_id),
+						COALESCE(EXCLUDED.newest_id, backfill_checkpoint.newest_id)
+					),
+					complete = EXCLUDED.complete,
+					updated = EXCLUDED.updated,
+					gap_since_id = EXCLUDED.gap_since_id
+			"#,
+		)
+		.bind(&checkpoint.keyword)
+		.bind(checkpoint.oldest_id)
+		.bind(checkpoint.newest_id)
+		.bind(checkpoint.complete)
+		.bind(checkpoint.updated)
+		.bind(checkpoint.gap_since_id)
+		.execute(&self.pool)
+		.await?;
+		Ok(())
+	}
+
+	async fn save_newest_id(&self, keyword: &str, newest_id: i64) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO backfill_checkpoint (keyword, newest_id, updated)
+				VALUES (?1, ?2, ?3)
+				ON CONFLICT (keyword) DO UPDATE SET
+					newest_id = MAX(COALESCE(backfill_checkpoint.newest_id, ?2), ?2),
+					updated = EXCLUDED.updated
+			"#,
+		)
+		.bind(keyword)
+		.bind(newest_id)
+		.bind(OffsetDateTime::now_utc().unix_timestamp())
+		.execute(&self.pool)
+		.await?;
+		Ok(())
+	}
+
+	async fn keywords(&self) -> Result<Vec<Keyword>> {
+		let keywords = sqlx::query_as(
+			r#"SELECT keyword, display_name, description, rule, status, tags, created, first_seen,
+					last_seen
+				FROM keywords
+				ORDER BY keyword ASC
+			"#,
+		)
+		.fetch_all(&self.pool)
+		.await?;
+		Ok(keywords)
+	}
+
+	async fn keyword(&self, keyword: &str) -> Result<Option<Keyword>> {
+		let keyword = sqlx::query_as(
+			r#"SELECT keyword, display_name, description, rule, status, tags, created, first_seen,
+					last_seen
+				FROM keywords
+				WHERE keyword = ?1
+			"#,
+		)
+		.bind(keyword)
+		.fetch_optional(&self.pool)
+		.await?;
+		Ok(keyword)
+	}
+
+	async fn track_keyword(&self, entry: &Keyword, replace: bool) -> Result<()> {
+		sqlx::query(
+			r#"INSERT INTO keywords
+				(keyword, display_name, description, rule, status, tags, created)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+				ON CONFLICT (keyword) DO UPDATE SET
+					rule = CASE WHEN ?8 OR keywords.rule IS NULL OR keywords.status = 'archived'
+						THEN EXCLUDED.rule ELSE keywords.rule END,
+					status = CASE WHEN keywords.rule IS NULL OR keywords.status = 'archived'
+						THEN EXCLUDED.status ELSE keywords.status END
+			"#,
+		)
+		.bind(&entry.keyword)
+		.bind(&entry.display_name)
+		.bind(&entry.description)
+		.bind(&entry.rule)
+		.bind(&entry.status)
+		.bind(&entry.tags)
+		.bind(entry.created)
+		.bind(replace)
+		.execute(&self.pool)
+		.await?;
+		Ok(())
+	}
+
+	async fn set_keyword_status(&self, keyword: &str, status: KeywordStatus) -> Result<bool> {
+		let result = sqlx::query(r#"UPDATE keywords SET status = ?2 WHERE keyword = ?1"#)
+			.bind(keyword)
+			.bind(status.as_str())
+			.execute(&self.pool)
+			.await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn update_keyword(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		let result = sqlx::query(
+			r#"UPDATE keywords SET
+					display_name = NULLIF(COALESCE(?2, display_name), ''),
+					description = NULLIF(COALESCE(?3, description), ''),
+					tags = COALESCE(?4, tags)
+				WHERE keyword = ?1
+			"#,
+		)
+		.bind(keyword)
+		.bind(&update.display_name)
+		.bind(&update.description)
+		.bind(update.tags_column())
+		.execute(&self.pool)
+		.await?;
+		Ok(result.rows_affected() > 0)
+	}
+
+	async fn refresh_rollups(&self, range: &RollupRange) -> Result<u64> {
+		let mut tx = self.pool.begin().await?;
+		// Buckets before the day of the first entry of a keyword are kept, their
+		// entries might have been removed by the retention policy.
+		let first_days: Vec<(String, Option<i64>)> = sqlx::query_as(
+			r#"SELECT keyword, (
+					SELECT MIN(created) FROM tweet_sentiment
+					WHERE tweet_sentiment.keyword = keywords.keyword
+				)
+				FROM keywords
+				WHERE ?1 IS NULL OR keyword = ?1
+			"#,
+		)
+		.bind(&range.keyword)
+		.fetch_all(&mut tx)
+		.await?;
+		let mut written = 0;
+		for resolution in Resolution::ALL {
+			let start = resolution.bucket(range.from);
+			let end = range.to.map(|to| resolution.bucket(to));
+			for (keyword, first) in &first_days {
+				let first_day = match first {
+					Some(first) => Resolution::Day.bucket(*first),
+					None => continue,
+				};
+				sqlx::query(
+					r#"DELETE FROM sentiment_rollup
+						WHERE keyword = ?1 AND resolution = ?2 AND bucket >= MAX(?3, ?4)
+							AND (?5 IS NULL OR bucket < ?5)
+					"#,
+				)
+				.bind(keyword)
+				.bind(resolution.as_str())
+				.bind(start)
+				.bind(first_day)
+				.bind(end)
+				.execute(&mut tx)
+				.await?;
+			}
+			let result = sqlx::query(
+				r#"INSERT INTO sentiment_rollup
+					(keyword, resolution, bucket, count, sum, sum_squares, min, max, positive, negative)
+					SELECT keyword, ?1, created - created % ?2, SUM(weight),
+						SUM(weight * sentiment), SUM(weight * sentiment * sentiment),
+						MIN(sentiment), MAX(sentiment),
+						SUM(CASE WHEN sentiment > 0 THEN weight ELSE 0 END),
+						SUM(CASE WHEN sentiment < 0 THEN weight ELSE 0 END)
+					FROM tweet_sentiment
+					WHERE created >= ?3
+						AND (?4 IS NULL OR created < ?4)
+						AND (?5 IS NULL OR keyword = ?5)
+					GROUP BY 1, 3
+				"#,
+			)
+			.bind(resolution.as_str())
+			.bind(resolution.seconds())
+			.bind(start)
+			.bind(end)
+			.bind(&range.keyword)
+			.execute(&mut tx)
+			.await?;
+			written += result.rows_affected();
+		}
+		tx.commit().await?;
+		Ok(written)
+	}
+
+	async fn expire(
+		&self,
+		keyword: &str,
+		target: RetentionTarget,
+		cutoff: i64,
+		dry_run: bool,
+	) -> Result<u64> {
+		let (table, condition) = match target {
+			RetentionTarget::Raw => ("tweet_sentiment", "created < ?2"),
+			RetentionTarget::Rollup(_) => ("sentiment_rollup", "bucket < ?2 AND resolution = ?3"),
+		};
+		let resolution = match target {
+			RetentionTarget::Raw => None,
+			RetentionTarget::Rollup(resolution) => Some(resolution.as_str()),
+		};
+		if dry_run {
+			let query =
+				format!("SELECT COUNT(*) FROM {} WHERE keyword = ?1 AND {}", table, condition);
+			let mut query = sqlx::query_scalar(&query).bind(keyword).bind(cutoff);
+			if let Some(resolution) = resolution {
+				query = query.bind(resolution);
+			}
+			let count: i64 = query.fetch_one(&self.pool).await?;
+			Ok(count as u64)
+		} else {
+			let query = format!("DELETE FROM {} WHERE keyword = ?1 AND {}", table, condition);
+			let mut query = sqlx::query(&query).bind(keyword).bind(cutoff);
+			if let Some(resolution) = resolution {
+				query = query.bind(resolution);
+			}
+			Ok(query.execute(&self.pool).await?.rows_affected())
+		}
+	}
+
+	async fn rollups(
+		&self,
+		keyword: &str,
+		resolution: Resolution,
+		from: Option<i64>,
+		to: Option<i64>,
+	) -> Result<Vec<RollupBucket>> {
+		let buckets = sqlx::query_as(
+			r#"SELECT keyword, resolution, bucket, count, sum, sum_squares, min, max, positive,
+					negative
+				FROM sentiment_rollup
+				WHERE keyword = ?1 AND resolution = ?2
+					AND (?3 IS NULL OR bucket >= ?3)
+					AND (?4 IS NULL OR bucket < ?4)
+				ORDER BY bucket ASC
+			"#,
+		)
+		.bind(keyword)
+		.bind(resolution.as_str())
+		.bind(from)
+		.bind(to)
+		.fetch_all(&self.pool)
+		.await?;
+		Ok(buckets)
+	}
+}
@@ -0,0 +1,674 @@
+//! Export of the stored data as CSV, JSON Lines or Parquet, streamed page by
+//! page
+
+use std::{
+	io::Write,
+	mem,
+	str::FromStr,
+	sync::{Arc, Mutex},
+};
+
+use color_eyre::{eyre::eyre, Result};
+use futures::{stream, Stream, TryStreamExt};
+use parquet::{
+	basic::Compression,
+	column::writer::ColumnWriter,
+	data_type::ByteArray,
+	file::{properties::WriterProperties, writer::SerializedFileWriter},
+	schema::parser::parse_message_type,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+	database::{EntryCursor, EntryFilter, Keyword, Resolution, RollupBucket, TweetSentiment},
+	SentimentDB,
+};
+
+/// Number of rows read from the database and encoded at once. Each page is a
+/// row group in Parquet files.
+const PAGE_SIZE: u32 = 10_000;
+
+/// File format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+	/// Comma-separated values with a header row
+	Csv,
+	/// One JSON object per line
+	Jsonl,
+	/// Apache Parquet file with one row group per page
+	Parquet,
+}
+
+impl Default for ExportFormat {
+	fn default() -> Self {
+		ExportFormat::Csv
+	}
+}
+
+impl ExportFormat {
+	/// MIME type of the format
+	pub fn content_type(self) -> &'static str {
+		match self {
+			ExportFormat::Csv => "text/csv",
+			ExportFormat::Jsonl => "application/x-ndjson",
+			ExportFormat::Parquet => "application/vnd.apache.parquet",
+		}
+	}
+
+	/// File extension of the format
+	pub fn extension(self) -> &'static str {
+		match self {
+			ExportFormat::Csv => "csv",
+			ExportFormat::Jsonl => "jsonl",
+			ExportFormat::Parquet => "parquet",
+		}
+	}
+}
+
+impl FromStr for ExportFormat {
+	type Err = String;
+
+	fn from_str(format: &str) -> Result<Self, Self::Err> {
+		match format {
+			"csv" => Ok(ExportFormat::Csv),
+			"jsonl" => Ok(ExportFormat::Jsonl),
+			"parquet" => Ok(ExportFormat::Parquet),
+			_ => Err(format!("Unknown export format: {}", format)),
+		}
+	}
+}
+
+/// Data of a keyword to export
+#[derive(Debug, Clone, Default)]
+pub struct ExportRequest {
+	/// File format, CSV by default
+	pub format: ExportFormat,
+	/// Export the rollups of this resolution instead of the raw entries
+	pub resolution: Option<Resolution>,
+	/// Only data at or after this timestamp
+	pub from: Option<i64>,
+	/// Only data before this timestamp
+	pub to: Option<i64>,
+}
+
+/// Stream the data of a keyword in the requested format. The data is read
+/// and encoded page by page, so it is never held in memory completely.
+pub fn export(
+	db: Arc<SentimentDB>,
+	keyword: &Keyword,
+	request: &ExportRequest,
+) -> Result<impl Stream<Item = Result<Vec<u8>>>> {
+	let (position, schema) = match request.resolution {
+		None => (Position::Raw(None), TweetSentiment::PARQUET_SCHEMA),
+		Some(resolution) => {
+			// Rollups are read in time windows, starting at the first entry at the
+			// earliest.
+			let start = request.from.or(keyword.first_seen).unwrap_or_default();
+			let end = request
+				.to
+				.or_else(|| keyword.last_seen.map(|last| last + 1))
+				.unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp());
+			(
+				Position::Rollup { resolution, start: resolution.bucket(start), end },
+				RollupBucket::PARQUET_SCHEMA,
+			)
+		}
+	};
+	let exporter = Exporter {
+		db,
+		keyword: keyword.keyword.clone(),
+		request: request.clone(),
+		encoder: Encoder::new(request.format, schema)?,
+		position,
+	};
+	Ok(stream::try_unfold(exporter, |mut exporter| async move {
+		Ok(exporter.next_chunk().await?.map(|chunk| (chunk, exporter)))
+	}))
+}
+
+/// Write an export stream to the writer, e.g. a file or stdout. Returns the
+/// number of bytes written.
+pub async fn write_to<W: AsyncWrite + Unpin>(
+	chunks: impl Stream<Item = Result<Vec<u8>>>,
+	out: &mut W,
+) -> Result<u64> {
+	let mut written = 0;
+	futures::pin_mut!(chunks);
+	while let Some(chunk) = chunks.try_next().await? {
+		out.write_all(&chunk).await?;
+		written += chunk.len() as u64;
+	}
+	out.flush().await?;
+	Ok(written)
+}
+
+/// Position of the export in the data
+#[derive(Debug)]
+enum Position {
+	/// Raw entries after the cursor
+	Raw(Option<EntryCursor>),
+	/// Rollups in the remaining time range
+	Rollup {
+		resolution: Resolution,
+		start: i64,
+		end: i64,
+	},
+	/// All data is encoded, the end of the file is missing
+	Finish,
+	Done,
+}
+
+/// State of a running export
+struct Exporter {
+	db: Arc<SentimentDB>,
+	keyword: String,
+	request: ExportRequest,
+	encoder: Encoder,
+	position: Position,
+}
+
+impl Exporter {
+	/// Read and encode the next page of data. Returns `None` at the end.
+	async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+		loop {
+			match &self.position {
+				Position::Raw(after) => {
+					let filter = EntryFilter {
+						from: self.request.from,
+						to: self.request.to,
+						after: after.clone(),
+						..EntryFilter::default()
+					};
+					let page = self.db.page(&self.keyword, &filter, PAGE_SIZE).await?;
+					self.position = match page.next {
+						Some(next) => Position::Raw(Some(next)),
+						None => Position::Finish,
+					};
+					if !page.entries.is_empty() {
+						return Ok(Some(self.encoder.encode(&page.entries)?));
+					}
+				}
+				&Position::Rollup { resolution, start, end } => {
+					if start >= end {
+						self.position = Position::Finish;
+						continue;
+					}
+					let window_end = (start + i64::from(PAGE_SIZE) * resolution.seconds()).min(end);
+					let buckets = self
+						.db
+						.rollups(&self.keyword, resolution, Some(start), Some(window_end))
+						.await?;
+					self.position = Position::Rollup { resolution, start: window_end, end };
+					if !buckets.is_empty() {
+						return Ok(Some(self.encoder.encode(&buckets)?));
+					}
+				}
+				Position::Finish => {
+					self.position = Position::Done;
+					let chunk = self.encoder.finish()?;
+					if !chunk.is_empty() {
+						return Ok(Some(chunk));
+					}
+				}
+				Position::Done => return Ok(None),
+			}
+		}
+	}
+}
+
+/// Rows that can be exported
+trait ExportRow: Serialize {
+	/// Schema of the rows in Parquet files
+	const PARQUET_SCHEMA: &'static str;
+
+	/// Values of the rows per column, in the order of the schema
+	fn columns(rows: &[Self]) -> Vec<ColumnData>
+	where
+		Self: Sized;
+}
+
+impl ExportRow for TweetSentiment {
+	const PARQUET_SCHEMA: &'static str = "message tweet_sentiment {
+		REQUIRED INT64 id;
+		REQUIRED BINARY keyword (UTF8);
+		REQUIRED BINARY source (UTF8);
+		REQUIRED INT64 created;
+		REQUIRED DOUBLE sentiment;
+		REQUIRED BOOLEAN retweet;
+		REQUIRED INT32 weight;
+		OPTIONAL BINARY lang (UTF8);
+		REQUIRED INT32 model_version;
+	}";
+
+	fn columns(rows: &[Self]) -> Vec<ColumnData> {
+		vec![
+			ColumnData::Int64(rows.iter().map(|row| row.id).collect()),
+			ColumnData::Text(rows.iter().map(|row| row.keyword.clone()).collect()),
+			ColumnData::Text(rows.iter().map(|row| row.source.clone()).collect()),
+			ColumnData::Int64(rows.iter().map(|row| row.created).collect()),
+			ColumnData::Double(rows.iter().map(|row| row.sentiment).collect()),
+			ColumnData::Boolean(rows.iter().map(|row| row.retweet).collect()),
+			ColumnData::Int32(rows.iter().map(|row| row.weight).collect()),
+			ColumnData::OptionalText(rows.iter().map(|row| row.lang.clone()).collect()),
+			ColumnData::Int32(rows.iter().map(|row| row.model_version).collect()),
+		]
+	}
+}
+
+impl ExportRow for RollupBucket {
+	const PARQUET_SCHEMA: &'static str = "message sentiment_rollup {
+		REQUIRED BINARY keyword (UTF8);
+		REQUIRED BINARY resolution (UTF8);
+		REQUIRED INT64 bucket;
+		REQUIRED INT64 count;
+		REQUIRED DOUBLE sum;
+		REQUIRED DOUBLE sum_squares;
+		REQUIRED DOUBLE min;
+		REQUIRED DOUBLE max;
+		REQUIRED INT64 positive;
+		REQUIRED INT64 negative;
+	}";
+
+	fn columns(rows: &[Self]) -> Vec<ColumnData> {
+		vec![
+			ColumnData::Text(rows.iter().map(|row| row.keyword.clone()).collect()),
+			ColumnData::Text(rows.iter().map(|row| row.resolution.clone()).collect()),
+			ColumnData::Int64(rows.iter().map(|row| row.bucket).collect()),
+			ColumnData::Int64(rows.iter().map(|row| row.count).collect()),
+			ColumnData::Double(rows.iter().map(|row| row.sum).collect()),
+			ColumnData::Double(rows.iter().map(|row| row.sum_squares).collect()),
+			ColumnData::Double(rows.iter().map(|row| row.min).collect()),
+			ColumnData::Double(rows.iter().map(|row| row.max).collect()),
+			ColumnData::Int64(rows.iter().map(|row| row.positive).collect()),
+			ColumnData::Int64(rows.iter().map(|row| row.negative).collect()),
+		]
+	}
+}
+
+/// Values of a column of rows
+#[derive(Debug)]
+enum ColumnData {
+	Int32(Vec<i32>),
+	Int64(Vec<i64>),
+	Double(Vec<f64>),
+	Boolean(Vec<bool>),
+	Text(Vec<String>),
+	OptionalText(Vec<Option<String>>),
+}
+
+/// Encoder of pages of rows in a format
+enum Encoder {
+	/// Whether the header row is written already
+	Csv {
+		header: bool,
+	},
+	Jsonl,
+	/// The file writer writes to the buffer, which is drained after each page.
+	/// The writer is taken when the file is finished.
+	Parquet {
+		writer: Option<SerializedFileWriter<SharedBuffer>>,
+		buffer: SharedBuffer,
+	},
+}
+
+impl Encoder {
+	/// Create an encoder for the format, with the schema for Parquet files
+	fn new(format: ExportFormat, parquet_schema: &str) -> Result<Self> {
+		Ok(match format {
+			ExportFormat::Csv => Encoder::Csv { header: false },
+			ExportFormat::Jsonl => Encoder::Jsonl,
+			ExportFormat::Parquet => {
+				let schema = Arc::new(parse_message_type(parquet_schema)?);
+				let properties = Arc::new(
+					WriterProperties::builder().set_compression(Compression::SNAPPY).build(),
+				);
+				let buffer = SharedBuffer::default();
+				let writer = SerializedFileWriter::new(buffer.clone(), schema, properties)?;
+				Encoder::Parquet { writer: Some(writer), buffer }
+			}
+		})
+	}
+
+	/// Encode a page of rows
+	fn encode<R: ExportRow>(&mut self, rows: &[R]) -> Result<Vec<u8>> {
+		match self {
+			Encoder::Csv { header } => {
+				let mut writer =
+					csv::WriterBuilder::new().has_headers(!*header).from_writer(Vec::new());
+				for row in rows {
+					writer.serialize(row)?;
+				}
+				*header = true;
+				Ok(writer.into_inner().map_err(|err| eyre!("Error writing CSV: {}", err))?)
+			}
+			Encoder::Jsonl => {
+				let mut chunk = Vec::new();
+				for row in rows {
+					serde_json::to_writer(&mut chunk, row)?;
+					chunk.push(b'\n');
+				}
+				Ok(chunk)
+			}
+			Encoder::Parquet { writer, buffer } => {
+				let writer = writer.as_mut().ok_or_else(|| eyre!("Parquet file is finished"))?;
+				let mut row_group = writer.next_row_group()?;
+				for column in R::columns(rows) {
+					let mut column_writer = row_group
+						.next_column()?
+						.ok_or_else(|| eyre!("More columns than in the Parquet schema"))?;
+					write_column(column_writer.untyped(), column)?;
+					column_writer.close()?;
+				}
+				row_group.close()?;
+				Ok(buffer.take())
+			}
+		}
+	}
+
+	/// Encode the end of the file
+	fn finish(&mut self) -> Result<Vec<u8>> {
+		match self {
+			Encoder::Csv { .. } | Encoder::Jsonl => Ok(Vec::new()),
+			Encoder::Parquet { writer, buffer } => {
+				if let Some(writer) = writer.take() {
+					writer.close()?;
+				}
+				Ok(buffer.take())
+			}
+		}
+	}
+}
+
+/// Write the values to a Parquet column
+fn write_column(writer: &mut ColumnWriter<'_>, column: ColumnData) -> Result<()> {
+	match (writer, column) {
+		(ColumnWriter::Int32ColumnWriter(writer), ColumnData::Int32(values)) => {
+			writer.write_batch(&values, None, None)?;
+		}
+		(ColumnWriter::Int64ColumnWriter(writer), ColumnData::Int64(values)) => {
+			writer.write_batch(&values, None, None)?;
+		}
+		(ColumnWriter::DoubleColumnWriter(writer), ColumnData::Double(values)) => {
+			writer.write_batch(&values, None, None)?;
+		}
+		(ColumnWriter::BoolColumnWriter(writer), ColumnData::Boolean(values)) => {
+			writer.write_batch(&values, None, None)?;
+		}
+		(ColumnWriter::ByteArrayColumnWriter(writer), ColumnData::Text(values)) => {
+			let values: Vec<ByteArray> = values.iter().map(|value| value.as_str().into()).collect();
+			writer.write_batch(&values, None, None)?;
+		}
+		(ColumnWriter::ByteArrayColumnWriter(writer), ColumnData::OptionalText(values)) => {
+			// Definition level 0 marks missing values, which have no value.
+			let levels: Vec<i16> = values.iter().map(|value| i16::from(value.is_some())).collect();
+			let values: Vec<ByteArray> =
+				values.iter().flatten().map(|value| value.as_str().into()).collect();
+			writer.write_batch(&values, Some(&levels), None)?;
+		}
+		(_, column) => return Err(eyre!("Column does not match the Parquet schema: {:?}", column)),
+	}
+	Ok(())
+}
+
+/// Buffer written by the Parquet file writer and drained after each page
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+	/// Take the written bytes
+	fn take(&self) -> Vec<u8> {
+		mem::take(&mut *self.0.lock().expect("Export buffer lock poisoned"))
+	}
+}
+
+impl Write for SharedBuffer {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.lock().expect("Export buffer lock poisoned").extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs::File;
+
+	use parquet::{
+		file::reader::{FileReader, SerializedFileReader},
+		record::{Field, RowAccessor},
+	};
+	use serde_json::Value;
+
+	use super::*;
+	use crate::{
+		database::{EntryBatch, RollupRange},
+		settings::ConflictPolicy,
+	};
+
+	/// Start of a day, so rollup buckets of all resolutions start with it
+	const START: i64 = 1_665_964_800;
+
+	/// Save `count` entries of the keyword, ten per second
+	async fn insert_entries(db: &SentimentDB, keyword: &str, count: u64) -> Result<()> {
+		let mut batch = EntryBatch::default();
+		for id in 0..count {
+			let created = START + id as i64 / 10;
+			let mut entry =
+				TweetSentiment::new(id, keyword.to_owned(), "twitter".to_owned(), created, 0.5);
+			entry.lang = (id % 2 == 0).then(|| "en".to_owned());
+			batch.entries.push((entry, ConflictPolicy::Ignore));
+		}
+		db.insert_batch(batch).await?;
+		Ok(())
+	}
+
+	/// Export all chunks of the keyword
+	async fn export_chunks(
+		db: &Arc<SentimentDB>,
+		keyword: &Keyword,
+		request: &ExportRequest,
+	) -> Result<Vec<Vec<u8>>> {
+		export(db.clone(), keyword, request)?.try_collect().await
+	}
+
+	/// Open the exported chunks as Parquet file
+	fn parquet_reader(chunks: &[Vec<u8>]) -> Result<SerializedFileReader<File>> {
+		let mut file = tempfile::tempfile()?;
+		for chunk in chunks {
+			file.write_all(chunk)?;
+		}
+		Ok(SerializedFileReader::new(file)?)
+	}
+
+	/// IDs and languages of the rows of an exported Parquet file
+	fn parquet_entries(reader: &SerializedFileReader<File>) -> Result<Vec<(i64, Option<String>)>> {
+		let mut entries = Vec::new();
+		for row in reader.get_row_iter(None)? {
+			let lang = match row.get_column_iter().nth(7).map(|(_, field)| field) {
+				Some(Field::Str(lang)) => Some(lang.clone()),
+				_ => None,
+			};
+			entries.push((row.get_long(0)?, lang));
+		}
+		Ok(entries)
+	}
+
+	/// IDs and languages of the rows of an exported CSV file
+	fn csv_entries(chunks: &[Vec<u8>]) -> Result<Vec<(i64, Option<String>)>> {
+		let mut reader = csv::Reader::from_reader(chunks.concat().as_slice());
+		let headers = reader.headers()?.clone();
+		assert_eq!(
+			headers.iter().collect::<Vec<_>>(),
+			vec![
+				"id",
+				"keyword",
+				"source",
+				"created",
+				"sentiment",
+				"retweet",
+				"weight",
+				"lang",
+				"model_version"
+			]
+		);
+		let mut entries = Vec::new();
+		for record in reader.records() {
+			let record = record?;
+			let lang = Some(record[7].to_owned()).filter(|lang| !lang.is_empty());
+			entries.push((record[0].parse()?, lang));
+		}
+		Ok(entries)
+	}
+
+	/// Rows of an exported JSON Lines file
+	fn jsonl_rows(chunks: &[Vec<u8>]) -> Result<Vec<Value>> {
+		let body = chunks.concat();
+		let rows = body
+			.split(|byte| *byte == b'\n')
+			.filter(|line| !line.is_empty())
+			.map(serde_json::from_slice)
+			.collect::<serde_json::Result<_>>()?;
+		Ok(rows)
+	}
+
+	/// IDs and languages of the saved entries
+	fn expected_entries(count: u64) -> Vec<(i64, Option<String>)> {
+		(0..count as i64).map(|id| (id, (id % 2 == 0).then(|| "en".to_owned()))).collect()
+	}
+
+	#[tokio::test]
+	async fn exports_entries_in_each_format() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		insert_entries(&db, "rust", 3).await?;
+		let keyword = Keyword::new("rust");
+		let expected = expected_entries(3);
+
+		let request = ExportRequest { format: ExportFormat::Csv, ..ExportRequest::default() };
+		let chunks = export_chunks(&db, &keyword, &request).await?;
+		assert_eq!(csv_entries(&chunks)?, expected);
+
+		let request = ExportRequest { format: ExportFormat::Jsonl, ..ExportRequest::default() };
+		let rows = jsonl_rows(&export_chunks(&db, &keyword, &request).await?)?;
+		let entry = TweetSentiment::new(0, "rust".to_owned(), "twitter".to_owned(), START, 0.5);
+		let entry = TweetSentiment { lang: Some("en".to_owned()), ..entry };
+		assert_eq!(rows[0], serde_json::to_value(&entry)?);
+		let entries: Vec<_> = rows
+			.iter()
+			.map(|row| (row["id"].as_i64().unwrap_or(-1), row["lang"].as_str().map(str::to_owned)))
+			.collect();
+		assert_eq!(entries, expected);
+
+		let request = ExportRequest { format: ExportFormat::Parquet, ..ExportRequest::default() };
+		let reader = parquet_reader(&export_chunks(&db, &keyword, &request).await?)?;
+		assert_eq!(reader.metadata().num_row_groups(), 1);
+		assert_eq!(parquet_entries(&reader)?, expected);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn exports_only_the_time_range() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		insert_entries(&db, "rust", 50).await?;
+		let request = ExportRequest {
+			format: ExportFormat::Csv,
+			from: Some(START + 1),
+			to: Some(START + 3),
+			..ExportRequest::default()
+		};
+		let chunks = export_chunks(&db, &Keyword::new("rust"), &request).await?;
+		let ids: Vec<_> = csv_entries(&chunks)?.into_iter().map(|(id, _)| id).collect();
+		assert_eq!(ids, (10..30).collect::<Vec<_>>());
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn splits_entries_at_page_boundaries() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		let keyword = Keyword::new("rust");
+		// A full last page, then one more entry on a page of its own. Saved
+		// entries are skipped when saving them again.
+		let full = u64::from(PAGE_SIZE) * 2;
+		for (count, pages) in [(full, 2), (full + 1, 3)] {
+			insert_entries(&db, "rust", count).await?;
+			let expected = expected_entries(count);
+
+			// The header is only written with the first page.
+			let request = ExportRequest { format: ExportFormat::Csv, ..ExportRequest::default() };
+			let chunks = export_chunks(&db, &keyword, &request).await?;
+			assert_eq!(chunks.len() as u64, pages);
+			assert!(chunks[1..].iter().all(|chunk| !chunk.starts_with(b"id,")));
+			assert_eq!(csv_entries(&chunks)?, expected);
+
+			let request = ExportRequest { format: ExportFormat::Jsonl, ..ExportRequest::default() };
+			let chunks = export_chunks(&db, &keyword, &request).await?;
+			assert_eq!(chunks.len() as u64, pages);
+			assert_eq!(jsonl_rows(&chunks)?.len() as u64, count);
+
+			// Each page is a row group, and the footer is the last chunk.
+			let request =
+				ExportRequest { format: ExportFormat::Parquet, ..ExportRequest::default() };
+			let chunks = export_chunks(&db, &keyword, &request).await?;
+			assert_eq!(chunks.len() as u64, pages + 1);
+			let reader = parquet_reader(&chunks)?;
+			assert_eq!(reader.metadata().num_row_groups() as u64, pages);
+			assert_eq!(parquet_entries(&reader)?, expected);
+		}
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn splits_rollups_at_window_boundaries() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		// The last minute of the first window and the first minute of the next
+		let window = i64::from(PAGE_SIZE) * Resolution::Minute.seconds();
+		let times = [START, START + window - 60, START + window];
+		let mut batch = EntryBatch::default();
+		for (id, created) in times.iter().enumerate() {
+			let entry = TweetSentiment::new(
+				id as u64,
+				"rust".to_owned(),
+				"twitter".to_owned(),
+				*created,
+				0.5,
+			);
+			batch.entries.push((entry, ConflictPolicy::Ignore));
+		}
+		db.insert_batch(batch).await?;
+		db.refresh_rollups(&RollupRange::covering("rust", START, START + window)).await?;
+
+		let keyword = Keyword {
+			first_seen: Some(START),
+			last_seen: Some(START + window),
+			..Keyword::new("rust")
+		};
+		let request = ExportRequest {
+			format: ExportFormat::Jsonl,
+			resolution: Some(Resolution::Minute),
+			..ExportRequest::default()
+		};
+		let chunks = export_chunks(&db, &keyword, &request).await?;
+		assert_eq!(chunks.len(), 2);
+		let buckets: Vec<_> = jsonl_rows(&chunks)?
+			.iter()
+			.map(|row| (row["bucket"].as_i64(), row["count"].as_i64()))
+			.collect();
+		let expected: Vec<_> = times.iter().map(|time| (Some(*time), Some(1))).collect();
+		assert_eq!(buckets, expected);
+
+		let request = ExportRequest {
+			format: ExportFormat::Parquet,
+			resolution: Some(Resolution::Minute),
+			..ExportRequest::default()
+		};
+		let reader = parquet_reader(&export_chunks(&db, &keyword, &request).await?)?;
+		assert_eq!(reader.metadata().num_row_groups(), 2);
+		assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+		Ok(())
+	}
+}
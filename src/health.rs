@@ -0,0 +1,317 @@
+//! Health state of the tweet streams and the reconnect policy after errors
+
+use std::{
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use color_eyre::Report;
+use rand::Rng;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// Class of a stream error, deciding how to reconnect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+	/// Rate limited by the platform (HTTP 420 or 429)
+	RateLimit,
+	/// Other HTTP error status
+	Http,
+	/// Network error or dropped connection
+	Network,
+	/// Authentication or authorization failure, reconnecting does not help
+	Fatal,
+	/// Any other error, e.g. in processing
+	Other,
+}
+
+impl ErrorClass {
+	/// Classify an error of the stream or its processing
+	pub fn of(err: &Report) -> Self {
+		if let Some(err) = err.downcast_ref::<egg_mode::error::Error>() {
+			return match err {
+				egg_mode::error::Error::RateLimit(_) => ErrorClass::RateLimit,
+				egg_mode::error::Error::BadStatus(status) => Self::of_status(status.as_u16()),
+				egg_mode::error::Error::NetError(_) => ErrorClass::Network,
+				_ => ErrorClass::Http,
+			};
+		}
+		if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+			return match err.status() {
+				Some(status) => Self::of_status(status.as_u16()),
+				None => ErrorClass::Network,
+			};
+		}
+		if err.downcast_ref::<std::io::Error>().is_some() {
+			return ErrorClass::Network;
+		}
+		ErrorClass::Other
+	}
+
+	/// Classify an HTTP error status
+	fn of_status(status: u16) -> Self {
+		match status {
+			401 | 403 => ErrorClass::Fatal,
+			420 | 429 => ErrorClass::RateLimit,
+			_ => ErrorClass::Http,
+		}
+	}
+
+	/// First delay before reconnecting, as recommended by Twitter for the
+	/// error class
+	fn min_delay(self) -> Duration {
+		match self {
+			ErrorClass::RateLimit => Duration::from_secs(60),
+			ErrorClass::Http | ErrorClass::Other => Duration::from_secs(5),
+			ErrorClass::Network => Duration::from_millis(250),
+			ErrorClass::Fatal => Duration::ZERO,
+		}
+	}
+}
+
+/// Exponential backoff with jitter for reconnecting. The delay starts at the
+/// minimum of the error class and doubles with every failed attempt up to the
+/// maximum.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+	max: Duration,
+	attempt: u32,
+}
+
+impl Backoff {
+	/// Create a new backoff with the given maximum delay. The minimum delay of
+	/// an error class is used if it is larger.
+	pub fn new(max: Duration) -> Self {
+		Backoff { max, attempt: 0 }
+	}
+
+	/// Number of failed attempts since the last reset
+	pub fn attempt(&self) -> u32 {
+		self.attempt
+	}
+
+	/// Reset after a successful connection
+	pub fn reset(&mut self) {
+		self.attempt = 0;
+	}
+
+	/// Delay before the next attempt after an error of the given class. Adds
+	/// up to a quarter of the delay as random jitter.
+	pub fn next_delay(&mut self, class: ErrorClass) -> Duration {
+		let min = class.min_delay();
+		let delay = min.saturating_mul(2_u32.saturating_pow(self.attempt)).min(self.max.max(min));
+		self.attempt = self.attempt.saturating_add(1);
+		let jitter = rand::thread_rng().gen_range(0.0..=0.25);
+		delay + delay.mul_f64(jitter)
+	}
+}
+
+/// Connection state of a stream
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum StreamState {
+	/// Connecting, no message received yet
+	Connecting,
+	/// Connected and receiving messages
+	Connected,
+	/// Waiting to reconnect after an error
+	BackingOff {
+		/// Timestamp of the next attempt
+		until: i64,
+		/// Number of failed attempts in a row
+		attempt: u32,
+		/// Last error
+		error: String,
+	},
+	/// Gave up after a fatal error
+	Failed {
+		/// Fatal error
+		error: String,
+	},
+}
+
+/// Health of a stream: its state and when it received the last message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamHealth {
+	/// Name of the stream's source
+	pub source: String,
+	/// Connection state
+	#[serde(flatten)]
+	pub state: StreamState,
+	/// Timestamp of the last received message
+	pub last_message: Option<i64>,
+	/// Statistics of the processed batches
+	pub batches: BatchStats,
+	/// Number of entries that were stored already
+	pub duplicates: u64,
+}
+
+/// Statistics of the batches of a stream
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BatchStats {
+	/// Number of flushed batches
+	pub count: u64,
+	/// Number of batches flushed before they were full, due to the latency
+	/// limit or the stream ending
+	pub incomplete: u64,
+	/// Average ratio of the batch size to the maximum batch size
+	pub fill_ratio: Option<f64>,
+	/// Sum of the fill ratios, to compute the average
+	#[serde(skip)]
+	fill_sum: f64,
+}
+
+/// Shared handle to the health of a stream, updated by the runner and read by
+/// the webserver
+#[derive(Debug, Clone)]
+pub struct StreamStatus {
+	health: Arc<Mutex<StreamHealth>>,
+}
+
+impl StreamStatus {
+	/// Create the status of a stream of the given source
+	pub fn new(source: &str) -> Self {
+		let health = StreamHealth {
+			source: source.to_owned(),
+			state: StreamState::Connecting,
+			last_message: None,
+			batches: BatchStats::default(),
+			duplicates: 0,
+		};
+		StreamStatus { health: Arc::new(Mutex::new(health)) }
+	}
+
+	/// Current health of the stream
+	pub fn get(&self) -> StreamHealth {
+		self.health.lock().expect("Stream status lock poisoned").clone()
+	}
+
+	/// Set the connection state
+	pub fn set_state(&self, state: StreamState) {
+		self.health.lock().expect("Stream status lock poisoned").state = state;
+	}
+
+	/// Record that a message was received, which means the stream is connected
+	pub fn message_received(&self) {
+		let mut health = self.health.lock().expect("Stream status lock poisoned");
+		health.state = StreamState::Connected;
+		health.last_message = Some(OffsetDateTime::now_utc().unix_timestamp());
+	}
+
+	/// Record that entries were stored already
+	pub fn duplicates_found(&self, count: u64) {
+		self.health.lock().expect("Stream status lock poisoned").duplicates += count;
+	}
+
+	/// Record that a batch of `len` items was flushed, with a maximum size of
+	/// `size` items
+	pub fn batch_flushed(&self, len: usize, size: usize) {
+		let mut health = self.health.lock().expect("Stream status lock poisoned");
+		let stats = &mut health.batches;
+		stats.count += 1;
+		if len < size {
+			stats.incomplete += 1;
+		}
+		stats.fill_sum += len as f64 / size.max(1) as f64;
+		stats.fill_ratio = Some(stats.fill_sum / stats.count as f64);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use color_eyre::eyre::eyre;
+	use hyper::StatusCode;
+
+	use super::*;
+
+	/// Assert the delay is the base delay plus up to a quarter of jitter
+	fn assert_delay(delay: Duration, base: Duration) {
+		assert!(delay >= base && delay <= base.mul_f64(1.25), "{:?} for {:?}", delay, base);
+	}
+
+	/// Error of a response with the status
+	fn reqwest_error(status: StatusCode) -> reqwest::Error {
+		let response =
+			hyper::Response::builder().status(status).body("").expect("Invalid response");
+		reqwest::Response::from(response).error_for_status().expect_err("No error status")
+	}
+
+	#[test]
+	fn backoff_doubles_up_to_the_maximum() {
+		let mut backoff = Backoff::new(Duration::from_secs(10));
+		for millis in [250, 500, 1000, 2000, 4000, 8000, 10_000, 10_000] {
+			assert_delay(backoff.next_delay(ErrorClass::Network), Duration::from_millis(millis));
+		}
+		assert_eq!(backoff.attempt(), 8);
+
+		// The maximum does not shorten the minimum delay of an error class.
+		assert_delay(backoff.next_delay(ErrorClass::RateLimit), Duration::from_secs(60));
+		assert_eq!(backoff.next_delay(ErrorClass::Fatal), Duration::ZERO);
+
+		// Many attempts do not overflow.
+		for _ in 0..100 {
+			backoff.next_delay(ErrorClass::Http);
+		}
+		assert_delay(backoff.next_delay(ErrorClass::Http), Duration::from_secs(10));
+	}
+
+	#[test]
+	fn backoff_restarts_after_reset() {
+		let mut backoff = Backoff::new(Duration::from_secs(600));
+		assert_delay(backoff.next_delay(ErrorClass::RateLimit), Duration::from_secs(60));
+		assert_delay(backoff.next_delay(ErrorClass::RateLimit), Duration::from_secs(120));
+		backoff.reset();
+		assert_eq!(backoff.attempt(), 0);
+		assert_delay(backoff.next_delay(ErrorClass::Http), Duration::from_secs(5));
+		assert_eq!(backoff.attempt(), 1);
+	}
+
+	#[test]
+	fn classifies_egg_mode_errors() {
+		use egg_mode::error::Error;
+
+		let cases = [
+			(Error::RateLimit(1_666_000_000), ErrorClass::RateLimit),
+			(Error::BadStatus(StatusCode::TOO_MANY_REQUESTS), ErrorClass::RateLimit),
+			(
+				Error::BadStatus(StatusCode::from_u16(420).expect("Invalid status")),
+				ErrorClass::RateLimit,
+			),
+			(Error::BadStatus(StatusCode::UNAUTHORIZED), ErrorClass::Fatal),
+			(Error::BadStatus(StatusCode::FORBIDDEN), ErrorClass::Fatal),
+			(Error::BadStatus(StatusCode::SERVICE_UNAVAILABLE), ErrorClass::Http),
+			(Error::MissingValue("id"), ErrorClass::Http),
+		];
+		for (err, class) in cases {
+			let message = err.to_string();
+			assert_eq!(ErrorClass::of(&Report::new(err)), class, "{}", message);
+		}
+	}
+
+	#[tokio::test]
+	async fn classifies_other_errors() {
+		let cases = [
+			(StatusCode::TOO_MANY_REQUESTS, ErrorClass::RateLimit),
+			(StatusCode::UNAUTHORIZED, ErrorClass::Fatal),
+			(StatusCode::FORBIDDEN, ErrorClass::Fatal),
+			(StatusCode::INTERNAL_SERVER_ERROR, ErrorClass::Http),
+		];
+		for (status, class) in cases {
+			assert_eq!(ErrorClass::of(&Report::new(reqwest_error(status))), class, "{}", status);
+		}
+
+		// Nothing listens on the discard port.
+		let err = reqwest::get("http://127.0.0.1:9/").await.expect_err("Connected");
+		assert_eq!(ErrorClass::of(&Report::new(err)), ErrorClass::Network);
+
+		let err = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Stream ended");
+		assert_eq!(ErrorClass::of(&Report::new(err)), ErrorClass::Network);
+		// Errors wrapped with context keep their class.
+		let err = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "Reset");
+		assert_eq!(
+			ErrorClass::of(&Report::new(err).wrap_err("Reading failed")),
+			ErrorClass::Network
+		);
+		assert_eq!(ErrorClass::of(&eyre!("Classifier failed")), ErrorClass::Other);
+	}
+}
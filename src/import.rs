@@ -0,0 +1,484 @@
+//! Bulk import of historical sentiment data from CSV or JSON Lines files
+
+use std::{
+	ffi::OsString,
+	fs::File,
+	io::{BufRead, BufReader, Seek},
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::Arc,
+	time::UNIX_EPOCH,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, task};
+use tracing::{info, warn};
+
+use crate::{
+	classifier::sentiment_to_float,
+	data,
+	database::{EntryBatch, InsertCounts, RollupRange, TweetSentiment},
+	settings::ConflictPolicy,
+	SentimentClassifier, SentimentDB,
+};
+
+/// Model version stored with pre-computed scores, so entries classified by
+/// the configured models replace them with the `keep_newest_model` policy
+const IMPORTED_MODEL_VERSION: i32 = 0;
+
+/// File format of an import
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+	/// Comma-separated values with a header row
+	Csv,
+	/// One JSON object per line
+	Jsonl,
+}
+
+impl ImportFormat {
+	/// Guess the format from the extension of the file
+	pub fn of_path(path: &Path) -> Option<Self> {
+		path.extension().and_then(|extension| extension.to_str()?.parse().ok())
+	}
+}
+
+impl FromStr for ImportFormat {
+	type Err = String;
+
+	fn from_str(format: &str) -> Result<Self, Self::Err> {
+		match format {
+			"csv" => Ok(ImportFormat::Csv),
+			"jsonl" | "ndjson" => Ok(ImportFormat::Jsonl),
+			_ => Err(format!("Unknown import format: {}", format)),
+		}
+	}
+}
+
+/// Record of an import file. Records need either a pre-computed sentiment
+/// or a text to classify.
+#[derive(Debug, Clone, Deserialize)]
+struct ImportRecord {
+	id: u64,
+	/// UNIX timestamp or RFC 3339
+	created: Timestamp,
+	#[serde(default)]
+	text: Option<String>,
+	/// Pre-computed sentiment between -1 and 1
+	#[serde(default)]
+	sentiment: Option<f64>,
+	#[serde(default)]
+	lang: Option<String>,
+}
+
+/// Timestamp of a record
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+	Unix(i64),
+	Text(String),
+}
+
+impl Timestamp {
+	/// UNIX timestamp, if valid
+	fn unix(&self) -> Option<i64> {
+		match self {
+			Timestamp::Unix(timestamp) => Some(*timestamp),
+			Timestamp::Text(text) => data::parse_timestamp(text),
+		}
+	}
+}
+
+/// Import a progress belongs to: the keyword and source of the entries and
+/// the state of the file when the import started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ImportTarget {
+	keyword: String,
+	source: String,
+	/// Size of the file in bytes
+	size: u64,
+	/// Modification time of the file as UNIX timestamp in milliseconds, if
+	/// known
+	modified: Option<i64>,
+}
+
+impl ImportTarget {
+	/// Target of importing the file for the keyword and source
+	fn of(path: &Path, keyword: &str, source: &str) -> Result<Self> {
+		let metadata = std::fs::metadata(path)?;
+		let modified =
+			metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+		Ok(ImportTarget {
+			keyword: keyword.to_owned(),
+			source: source.to_owned(),
+			size: metadata.len(),
+			modified: modified.map(|time| time.as_millis() as i64),
+		})
+	}
+}
+
+/// Progress of an import, saved after each batch to resume it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportProgress {
+	/// Import the progress belongs to, it is only resumed by the same import
+	#[serde(default)]
+	target: ImportTarget,
+	/// Number of records read, including invalid records
+	pub records: u64,
+	/// Number of invalid records that were skipped
+	pub invalid: u64,
+	pub inserted: u64,
+	pub updated: u64,
+	pub skipped: u64,
+	/// First and last creation time of the imported entries, the rollups of
+	/// this range are refreshed at the end
+	#[serde(default)]
+	created: Option<(i64, i64)>,
+}
+
+impl ImportProgress {
+	/// Progress of a new import
+	fn new(target: ImportTarget) -> Self {
+		ImportProgress { target, ..Self::default() }
+	}
+
+	/// Load the progress of a previous import of the target, if there is one.
+	/// Fails if the progress belongs to another keyword or source or the file
+	/// changed since.
+	async fn load(path: &Path, target: ImportTarget) -> Result<Self> {
+		let progress: Self = match fs::read(path).await {
+			Ok(content) => serde_json::from_slice(&content)?,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new(target)),
+			Err(err) => return Err(err.into()),
+		};
+		if progress.target != target {
+			let previous = &progress.target;
+			return Err(eyre!(
+				"{} belongs to another import (keyword `{}`, source `{}`) or the file changed \
+				 since, use --restart to start from the beginning",
+				path.display(),
+				previous.keyword,
+				previous.source
+			));
+		}
+		Ok(progress)
+	}
+
+	/// Save the progress, replacing the file atomically
+	async fn save(&self, path: &Path) -> Result<()> {
+		let tmp_path = with_suffix(path, ".tmp");
+		fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+		fs::rename(&tmp_path, path).await?;
+		Ok(())
+	}
+
+	/// Add the counts and time range of a saved batch
+	fn add(
+		&mut self,
+		records: usize,
+		invalid: usize,
+		counts: InsertCounts,
+		created: Option<(i64, i64)>,
+	) {
+		self.records += records as u64;
+		self.invalid += invalid as u64;
+		self.inserted += counts.inserted;
+		self.updated += counts.updated;
+		self.skipped += counts.skipped;
+		self.created = match (self.created, created) {
+			(Some((first, last)), Some((batch_first, batch_last))) => {
+				Some((first.min(batch_first), last.max(batch_last)))
+			}
+			(range, batch_range) => range.or(batch_range),
+		};
+	}
+}
+
+/// Importer of historical sentiment entries of a keyword from a file. The
+/// progress is saved next to the file after each batch, so an interrupted
+/// import resumes after the last saved batch. Entries that are stored already
+/// are handled by the conflict policy.
+#[derive(Debug, Builder)]
+pub struct Importer {
+	/// CSV or JSON Lines file to import
+	path: PathBuf,
+	format: ImportFormat,
+	/// Keyword to store the entries under
+	keyword: String,
+	/// Source name stored with the entries
+	#[builder(default = "\"import\".to_owned()")]
+	source: String,
+	#[builder(default)]
+	on_conflict: ConflictPolicy,
+	/// Number of records saved in one transaction
+	#[builder(default = "1000")]
+	batch_size: usize,
+	/// Classifier for the texts of records without sentiment. Such records are
+	/// invalid without classifier.
+	#[builder(default, setter(strip_option))]
+	sentiment_classifier: Option<SentimentClassifier>,
+	/// Ignore the progress of a previous import and start from the beginning
+	#[builder(default)]
+	restart: bool,
+	db: Arc<SentimentDB>,
+}
+
+impl Importer {
+	/// Get a builder to create an instance.
+	pub fn builder() -> ImporterBuilder {
+		ImporterBuilder::default()
+	}
+
+	/// Path of the file storing the progress
+	fn progress_path(&self) -> PathBuf {
+		with_suffix(&self.path, ".progress")
+	}
+
+	/// Import the file, resuming a previous import of the same file, keyword
+	/// and source. Returns the total progress.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn run(mut self) -> Result<ImportProgress> {
+		// Keywords are stored in lowercase, like tracked keywords.
+		self.keyword = self.keyword.to_lowercase();
+		let progress_path = self.progress_path();
+		let target = ImportTarget::of(&self.path, &self.keyword, &self.source)?;
+		let size = target.size.max(1);
+		let mut progress = match self.restart {
+			true => ImportProgress::new(target),
+			false => ImportProgress::load(&progress_path, target).await?,
+		};
+
+		let mut reader = RecordReader::open(&self.path, self.format)?;
+		if progress.records > 0 {
+			info!("Resuming import of {} after {} records.", self.path.display(), progress.records);
+			task::block_in_place(|| reader.skip(progress.records))?;
+		}
+
+		loop {
+			let (records, read) = task::block_in_place(|| reader.next_batch(self.batch_size))?;
+			if read == 0 {
+				break;
+			}
+			let entries = self.entries(records).await?;
+			let invalid = read - entries.len();
+			let created = entries.iter().map(|entry| entry.created);
+			let created = created.clone().min().zip(created.max());
+			let batch = EntryBatch {
+				entries: entries.into_iter().map(|entry| (entry, self.on_conflict)).collect(),
+				..EntryBatch::default()
+			};
+			let counts = self.db.insert_batch(batch).await?;
+			progress.add(read, invalid, counts, created);
+			progress.save(&progress_path).await?;
+
+			info!(
+				"Imported {:.1}%: {} records, {} inserted, {} duplicates, {} invalid.",
+				reader.position()? as f64 * 100.0 / size as f64,
+				progress.records,
+				progress.inserted,
+				progress.updated + progress.skipped,
+				progress.invalid
+			);
+		}
+
+		// The imported entries are mostly older than the periodic refresh of the
+		// rollups covers.
+		if let Some((first, last)) = progress.created {
+			let range = RollupRange::covering(&self.keyword, first, last);
+			let written = self.db.refresh_rollups(&range).await?;
+			info!("Refreshed {} rollup buckets of the imported entries.", written);
+		}
+		info!(
+			"Import of {} finished, rerun with --restart to import it again.",
+			self.path.display()
+		);
+		Ok(progress)
+	}
+
+	/// Create the entries of the records, classifying the texts of records
+	/// without sentiment. Invalid records are skipped with a warning.
+	async fn entries(&self, records: Vec<ImportRecord>) -> Result<Vec<TweetSentiment>> {
+		let mut entries = Vec::with_capacity(records.len());
+		let mut unclassified = Vec::new();
+		for record in records {
+			let created = match record.created.unix() {
+				Some(created) => created,
+				None => {
+					warn!("Skipping record {} with invalid time: {:?}", record.id, record.created);
+					continue;
+				}
+			};
+			let mut entry = TweetSentiment::new(
+				record.id,
+				self.keyword.clone(),
+				self.source.clone(),
+				created,
+				record.sentiment.unwrap_or_default(),
+			);
+			entry.lang = record.lang;
+			entry.model_version = IMPORTED_MODEL_VERSION;
+			match (record.sentiment, record.text, &self.sentiment_classifier) {
+				(Some(_), _, _) => entries.push(entry),
+				(None, Some(text), Some(_)) => unclassified.push((entry, text)),
+				(None, _, _) => warn!("Skipping record {} without sentiment.", record.id),
+			}
+		}
+
+		if let Some(classifier) = &self.sentiment_classifier {
+			if !unclassified.is_empty() {
+				let texts = unclassified
+					.iter()
+					.map(|(entry, text)| (text.clone(), entry.lang.clone()))
+					.collect();
+				let sentiments = classifier.predict_lang(texts).await?;
+				for ((mut entry, _), sentiment) in unclassified.into_iter().zip(sentiments) {
+					entry.sentiment = sentiment_to_float(&sentiment);
+					entry.model_version = classifier.model_version();
+					entries.push(entry);
+				}
+			}
+		}
+		Ok(entries)
+	}
+}
+
+/// Reader of the records of an import file
+enum RecordReader {
+	Csv { reader: csv::Reader<File>, headers: csv::StringRecord },
+	Jsonl { reader: BufReader<File>, line: String },
+}
+
+impl RecordReader {
+	/// Open the file
+	fn open(path: &Path, format: ImportFormat) -> Result<Self> {
+		let file = File::open(path)?;
+		Ok(match format {
+			ImportFormat::Csv => {
+				let mut reader = csv::Reader::from_reader(file);
+				let headers = reader.headers()?.clone();
+				RecordReader::Csv { reader, headers }
+			}
+			ImportFormat::Jsonl => {
+				RecordReader::Jsonl { reader: BufReader::new(file), line: String::new() }
+			}
+		})
+	}
+
+	/// Read the next record, `None` at the end of the file. Invalid records
+	/// are returned as error message.
+	fn next_record(&mut self) -> Result<Option<Result<ImportRecord, String>>> {
+		match self {
+			RecordReader::Csv { reader, headers } => {
+				let mut record = csv::StringRecord::new();
+				if !reader.read_record(&mut record)? {
+					return Ok(None);
+				}
+				Ok(Some(record.deserialize(Some(headers)).map_err(|err| err.to_string())))
+			}
+			RecordReader::Jsonl { reader, line } => loop {
+				line.clear();
+				if reader.read_line(line)? == 0 {
+					return Ok(None);
+				}
+				// Empty lines are no records.
+				if !line.trim().is_empty() {
+					return Ok(Some(serde_json::from_str(line).map_err(|err| err.to_string())));
+				}
+			},
+		}
+	}
+
+	/// Read up to `count` records. Invalid records are skipped with a warning.
+	/// Returns the valid records and the number of records read.
+	fn next_batch(&mut self, count: usize) -> Result<(Vec<ImportRecord>, usize)> {
+		let mut records = Vec::with_capacity(count);
+		let mut read = 0;
+		while read < count {
+			match self.next_record()? {
+				Some(Ok(record)) => records.push(record),
+				Some(Err(err)) => warn!("Skipping invalid record: {}", err),
+				None => break,
+			}
+			read += 1;
+		}
+		Ok((records, read))
+	}
+
+	/// Skip records that were imported already
+	fn skip(&mut self, count: u64) -> Result<()> {
+		for _ in 0..count {
+			if self.next_record()?.is_none() {
+				return Err(eyre!("File has less records than imported already"));
+			}
+		}
+		Ok(())
+	}
+
+	/// Position in the file in bytes
+	fn position(&mut self) -> Result<u64> {
+		Ok(match self {
+			RecordReader::Csv { reader, .. } => reader.position().byte(),
+			RecordReader::Jsonl { reader, .. } => reader.stream_position()?,
+		})
+	}
+}
+
+/// Path with the suffix appended to the file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut path = OsString::from(path);
+	path.push(suffix);
+	path.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use super::*;
+	use crate::database::EntryFilter;
+
+	fn importer(path: &Path, source: &str, restart: bool, db: Arc<SentimentDB>) -> Importer {
+		Importer::builder()
+			.path(path.to_owned())
+			.format(ImportFormat::Jsonl)
+			.keyword("Rust".to_owned())
+			.source(source.to_owned())
+			.batch_size(2)
+			.restart(restart)
+			.db(db)
+			.build()
+			.expect("Invalid importer")
+	}
+
+	fn append_records(path: &Path, ids: std::ops::Range<u64>) -> Result<()> {
+		let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+		for id in ids {
+			writeln!(file, r#"{{"id": {}, "created": 1666000000, "sentiment": 0.5}}"#, id)?;
+		}
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn resumes_only_the_same_import() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let path = dir.path().join("tweets.jsonl");
+		let db = Arc::new(SentimentDB::in_memory());
+		append_records(&path, 0..3)?;
+
+		let progress = importer(&path, "import", false, db.clone()).run().await?;
+		assert_eq!((progress.records, progress.inserted), (3, 3));
+		assert_eq!(db.get("rust", &EntryFilter::default()).await?.len(), 3);
+		// Finished imports are resumed at the end
+		let progress = importer(&path, "import", false, db.clone()).run().await?;
+		assert_eq!((progress.records, progress.inserted), (3, 3));
+
+		assert!(importer(&path, "archive", false, db.clone()).run().await.is_err());
+		append_records(&path, 3..4)?;
+		assert!(importer(&path, "import", false, db.clone()).run().await.is_err());
+
+		let progress = importer(&path, "import", true, db.clone()).run().await?;
+		assert_eq!((progress.records, progress.inserted, progress.skipped), (4, 1, 3));
+		Ok(())
+	}
+}
@@ -0,0 +1,109 @@
+//! Registry of the tracked keywords, manageable at runtime
+
+use std::sync::Arc;
+
+use color_eyre::Result;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::{
+	database::{Keyword, KeywordStatus, KeywordUpdate, SentimentDB},
+	matcher::KeywordMatcher,
+	settings::KeywordRule,
+};
+
+/// Registry of the tracked keywords, stored in the database with the other
+/// keywords. Every change publishes a new matcher of the active keywords, so
+/// runners reconnect with the new keywords.
+#[derive(Debug)]
+pub struct KeywordRegistry {
+	db: Arc<SentimentDB>,
+	sender: watch::Sender<Arc<KeywordMatcher>>,
+	/// Receiver to keep the channel open
+	receiver: watch::Receiver<Arc<KeywordMatcher>>,
+}
+
+impl KeywordRegistry {
+	/// Create the registry, adding the initial keyword rules to the database
+	/// if missing.
+	pub async fn new(db: Arc<SentimentDB>, initial: &[KeywordRule]) -> Result<Self> {
+		for rule in initial {
+			db.track_keyword(&Keyword::tracked(rule)?, false).await?;
+		}
+		let matcher = Self::load_matcher(&db).await?;
+		let (sender, receiver) = watch::channel(Arc::new(matcher));
+		Ok(KeywordRegistry { db, sender, receiver })
+	}
+
+	/// Get a receiver of the matcher of the active keywords, which is updated
+	/// on changes.
+	pub fn subscribe(&self) -> watch::Receiver<Arc<KeywordMatcher>> {
+		self.receiver.clone()
+	}
+
+	/// List all keywords with their metadata
+	pub async fn list(&self) -> Result<Vec<Keyword>> {
+		Ok(self.db.keywords().await?)
+	}
+
+	/// Add a keyword rule or replace the rule of an existing keyword. The rule
+	/// has to be validated with `KeywordMatcher::new` before.
+	pub async fn add(&self, rule: &KeywordRule) -> Result<()> {
+		info!("Adding keyword `{}`.", rule.keyword);
+		self.db.track_keyword(&Keyword::tracked(rule)?, true).await?;
+		self.reload().await
+	}
+
+	/// Pause or resume tracking of a keyword. Returns whether the keyword
+	/// exists.
+	pub async fn set_paused(&self, keyword: &str, paused: bool) -> Result<bool> {
+		info!("Setting keyword `{}` paused: {}.", keyword, paused);
+		let status = if paused { KeywordStatus::Paused } else { KeywordStatus::Active };
+		let found = self.db.set_keyword_status(&keyword.to_lowercase(), status).await?;
+		self.reload().await?;
+		Ok(found)
+	}
+
+	/// Remove a keyword from tracking by archiving it, keeping its data.
+	/// Returns whether the keyword exists.
+	pub async fn remove(&self, keyword: &str) -> Result<bool> {
+		info!("Archiving keyword `{}`.", keyword);
+		let found =
+			self.db.set_keyword_status(&keyword.to_lowercase(), KeywordStatus::Archived).await?;
+		self.reload().await?;
+		Ok(found)
+	}
+
+	/// Update the display name, description or tags of a keyword. Returns
+	/// whether the keyword exists.
+	pub async fn update(&self, keyword: &str, update: &KeywordUpdate) -> Result<bool> {
+		info!("Updating metadata of keyword `{}`.", keyword);
+		Ok(self.db.update_keyword(&keyword.to_lowercase(), update).await?)
+	}
+
+	/// Load the active keywords and publish their matcher.
+	async fn reload(&self) -> Result<()> {
+		let matcher = Self::load_matcher(&self.db).await?;
+		self.sender.send(Arc::new(matcher))?;
+		Ok(())
+	}
+
+	/// Build the matcher of the active keywords in the database. Invalid rules
+	/// are skipped.
+	async fn load_matcher(db: &SentimentDB) -> Result<KeywordMatcher> {
+		let mut rules = Vec::new();
+		for entry in db.keywords().await? {
+			if entry.status() != KeywordStatus::Active {
+				continue;
+			}
+			match entry.rule() {
+				Some(Ok(rule)) => rules.push(rule),
+				Some(Err(err)) => {
+					warn!("Skipping invalid rule of keyword `{}`: {}", entry.keyword, err)
+				}
+				None => {}
+			}
+		}
+		KeywordMatcher::new(&rules)
+	}
+}
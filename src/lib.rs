@@ -8,12 +8,34 @@
 //! - Sentiment classification is in `classifier`.
 //! - Data handling and transformation is in `data`.
 //! - Settings are in `settings`.
+//! - Sources of tweets are in `source`.
+//! - Backfill of historical tweets is in `backfill`.
+//! - Matching of tweets to keywords is in `matcher`.
+//! - Management of the tracked keywords is in `keywords`.
+//! - The on-disk queue of received tweets is in `spool`.
+//! - Stream health and the reconnect policy are in `health`.
+//! - Time-bounded batching of streams is in `batch`.
+//! - Periodic maintenance of the stored data is in `maintenance`.
+//! - Export of the stored data is in `export`.
+//! - Bulk import of historical data is in `import`.
+//! - The command line interface is in `cli`.
 
+mod backfill;
+mod batch;
 mod classifier;
+mod cli;
 mod data;
 mod database;
+mod export;
+mod health;
+mod import;
+mod keywords;
+mod maintenance;
+mod matcher;
 mod server;
 mod settings;
+mod source;
+mod spool;
 mod twitter_stream;
 
 use std::env;
@@ -22,7 +44,26 @@ use color_eyre::Result;
 use egg_mode::{KeyPair, Token};
 
 pub use self::{
-	classifier::SentimentClassifier, database::SentimentDB, server::Server, settings::Settings,
+	backfill::Backfiller,
+	classifier::SentimentClassifier,
+	cli::{Cli, Command},
+	database::{
+		EntryBatch, MemoryStorage, PostgresStorage, RetentionTarget, SentimentDB, SqliteStorage,
+		Storage, TweetSentiment,
+	},
+	export::{ExportFormat, ExportRequest},
+	health::{BatchStats, StreamHealth, StreamState, StreamStatus},
+	import::{ImportFormat, ImportProgress, Importer},
+	keywords::KeywordRegistry,
+	maintenance::MaintenanceRunner,
+	matcher::KeywordMatcher,
+	server::Server,
+	settings::{ConflictPolicy, Settings},
+	source::{
+		MastodonSource, ReplaySource, SourceEvent, SourceTweet, TweetMeta, TweetSource,
+		TweetStream, TwitterFilterSource, TwitterV2Source,
+	},
+	spool::Spool,
 	twitter_stream::TwitterStreamRunner,
 };
 
@@ -1,8 +1,8 @@
-use std::{env, sync::Arc};
+use std::{env, path::Path, sync::Arc};
 
+use clap::Parser;
 use color_eyre::Result;
 use futures::future;
-use sqlx::PgPool;
 use tokio::task;
 use tracing_subscriber::EnvFilter;
 use twitter_sentiment::*;
@@ -12,11 +12,11 @@ use twitter_sentiment::*;
 //   of tweet number for higher performace)
 // - multiple keywords in a graph
 // - better HTML views
-// - add tests
 
 #[tokio::main]
 #[tracing::instrument(level = "debug", err, skip_all)]
 async fn main() -> Result<()> {
+	let cli = Cli::parse();
 	let config = Arc::new(Settings::read()?);
 	let server_addr = config.bind.parse()?;
 
@@ -26,38 +26,120 @@ async fn main() -> Result<()> {
 		.add_directive("mio=info".parse()?)
 		.add_directive("want=info".parse()?)
 		.add_directive("sqlx=error".parse()?);
-	tracing_subscriber::fmt().with_env_filter(filter).init();
+	// Logs go to stderr, so commands can write their output to stdout.
+	tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
 
 	color_eyre::install()?;
 	dotenv::dotenv()?;
 
 	// Init DB
 	let db_url = env::var("DATABASE_URL")?;
-	let db_pool = PgPool::connect(&db_url).await?;
-	sqlx::migrate!().run(&db_pool).await?;
-	let db = Arc::new(SentimentDB::new(db_pool));
+	let db = Arc::new(SentimentDB::connect(&db_url).await?);
+	if let Some(command) = cli.command {
+		return command.run(db, &config).await;
+	}
 
 	// Init Twitter listener
-	let token = twitter_access_token()?;
-	let (classifier_runner, sentiment_classifier) = SentimentClassifier::spawn();
-	let twitter_streams = TwitterStreamRunner::builder()
+	let keywords = Arc::new(KeywordRegistry::new(db.clone(), &config.twitter.track_tweets).await?);
+	let (classifier_runner, sentiment_classifier) =
+		SentimentClassifier::spawn(config.classifier.clone());
+	let mut twitter_streams = TwitterStreamRunner::builder();
+	if let Some(replay) = &config.twitter.replay {
+		twitter_streams.source(Arc::new(ReplaySource::new(replay)));
+	} else {
+		if let Some(api_v2) = &config.twitter.api_v2 {
+			let bearer_token = env::var("TWITTER_BEARER_TOKEN")?;
+			twitter_streams.source(Arc::new(TwitterV2Source::new(api_v2, bearer_token)));
+		} else {
+			twitter_streams.source(Arc::new(TwitterFilterSource::new(twitter_access_token()?)));
+		}
+		// The v1.1 credentials are only needed by the v1.1 stream and the backfill.
+		if let Some(backfill) = &config.twitter.backfill {
+			let backfiller = Backfiller::builder()
+				.config(backfill.clone())
+				.store_text(config.twitter.store_text)
+				.on_conflict(config.twitter.on_conflict)
+				.token(twitter_access_token()?)
+				.sentiment_classifier(sentiment_classifier.clone())
+				.db(db.clone())
+				.build()?;
+			twitter_streams.backfiller(backfiller);
+		}
+	}
+	if let Some(spool) = &config.twitter.spool {
+		let dir = Path::new(&spool.path).join("twitter");
+		twitter_streams.spool(Spool::open(&dir, spool).await?);
+	}
+	let twitter_status = StreamStatus::new("twitter");
+	let mut stream_status = vec![twitter_status.clone()];
+	let twitter_streams = twitter_streams
 		.config(config.twitter.clone())
-		.token(token)
-		.sentiment_classifier(sentiment_classifier)
+		.status(twitter_status)
+		.keywords(keywords.subscribe())
+		.sentiment_classifier(sentiment_classifier.clone())
+		.db(db.clone())
+		.build()?;
+
+	// Init Mastodon listener
+	let mastodon_streams = match &config.mastodon {
+		Some(mastodon) => {
+			let access_token = env::var("MASTODON_ACCESS_TOKEN").ok();
+			let mut runner = TwitterStreamRunner::builder();
+			if let Some(spool) = &config.twitter.spool {
+				let dir = Path::new(&spool.path).join("mastodon");
+				runner.spool(Spool::open(&dir, spool).await?);
+			}
+			let status = StreamStatus::new("mastodon");
+			stream_status.push(status.clone());
+			let runner = runner
+				.config(config.twitter.clone())
+				.status(status)
+				.keywords(keywords.subscribe())
+				.source(Arc::new(MastodonSource::new(mastodon, access_token)))
+				.sentiment_classifier(sentiment_classifier.clone())
+				.db(db.clone())
+				.build()?;
+			Some(runner)
+		}
+		None => None,
+	};
+
+	// Init maintenance
+	let maintenance = MaintenanceRunner::builder()
+		.rollups(config.rollups.clone())
+		.retention(config.retention.clone())
 		.db(db.clone())
 		.build()?;
 
 	// Init webserver
-	let server = Server::builder().bind(server_addr).db(db).config(config).build()?;
+	let mut server = Server::builder();
+	if let Ok(ingest_token) = env::var("INGEST_TOKEN") {
+		server.ingest_token(ingest_token);
+	}
+	if let Ok(admin_token) = env::var("ADMIN_TOKEN") {
+		server.admin_token(admin_token);
+	}
+	let server = server
+		.bind(server_addr)
+		.db(db)
+		.config(config)
+		.sentiment_classifier(sentiment_classifier)
+		.keywords(keywords)
+		.streams(stream_status)
+		.build()?;
 
 	// Run all tasks/jobs/runners
-	let handles = vec![
+	let mut handles = vec![
 		task::spawn(twitter_streams.run()),
 		task::spawn(server.run()),
+		task::spawn(maintenance.run()),
 		task::spawn_blocking(move || {
 			classifier_runner.join().expect("Join error on classifier thread!")
 		}),
 	];
+	if let Some(mastodon_streams) = mastodon_streams {
+		handles.push(task::spawn(mastodon_streams.run()));
+	}
 	let (first_res, _, others) = future::select_all(handles).await;
 	for handle in others {
 		handle.abort();
@@ -0,0 +1,168 @@
+//! Periodic maintenance of the stored data
+
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+use color_eyre::Result;
+use derive_builder::Builder;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::time;
+use tracing::{debug, error, info};
+
+use crate::{
+	database::{Resolution, RetentionTarget, RollupRange},
+	settings::{RetentionSettings, RollupSettings},
+	SentimentDB,
+};
+
+/// Runner of the periodic maintenance: keeps the rollup tables up to date and
+/// enforces the retention policies
+#[derive(Debug, Builder)]
+pub struct MaintenanceRunner {
+	rollups: RollupSettings,
+	retention: RetentionSettings,
+	db: Arc<SentimentDB>,
+}
+
+/// Data of a keyword removed by the retention policy, or to be removed in a
+/// dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionItem {
+	pub keyword: String,
+	/// `raw` for sentiment entries or the resolution of rollups
+	pub data: String,
+	/// Timestamp the data before is removed
+	pub cutoff: i64,
+	/// Number of removed rows
+	pub rows: u64,
+}
+
+impl MaintenanceRunner {
+	/// Get a builder to create an instance.
+	pub fn builder() -> MaintenanceRunnerBuilder {
+		MaintenanceRunnerBuilder::default()
+	}
+
+	/// Refresh the rollups and enforce the retention policies periodically.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	pub async fn run(self) -> Result<()> {
+		info!("Starting maintenance.");
+		// All rollups are recomputed on start, as entries might have been added
+		// or removed without the runner, later only the buckets of the lookback
+		// period and of older entries saved since, e.g. by a backfill.
+		let mut since = 0;
+		let mut rollup_interval =
+			time::interval(Duration::from_secs(self.rollups.interval_secs.max(1)));
+		let mut retention_interval =
+			time::interval(Duration::from_secs(self.retention.interval_secs.max(1)));
+		loop {
+			tokio::select! {
+				_ = rollup_interval.tick() => {
+					let now = OffsetDateTime::now_utc().unix_timestamp();
+					match self.refresh_rollups(since).await {
+						Ok(written) => {
+							debug!("Refreshed {} rollup buckets.", written);
+							since = now - self.rollups.lookback_secs as i64;
+						}
+						Err(err) => error!("Error refreshing the rollups, retrying later: {}", err),
+					}
+				}
+				_ = retention_interval.tick() => {
+					let dry_run = self.retention.dry_run;
+					match enforce_retention(&self.db, &self.retention, dry_run).await {
+						Ok(items) => {
+							for item in items {
+								info!(
+									"Retention {}: {} {} rows of {} before {}.",
+									if dry_run { "dry run" } else { "enforced" },
+									item.rows,
+									item.data,
+									item.keyword,
+									item.cutoff
+								);
+							}
+						}
+						Err(err) => error!("Error enforcing the retention policies: {}", err),
+					}
+				}
+			}
+		}
+	}
+
+	/// Refresh the rollups since the timestamp and the rollups of older entries
+	/// saved since the last refresh. The older entries are kept for the next
+	/// refresh on errors.
+	async fn refresh_rollups(&self, since: i64) -> sqlx::Result<u64> {
+		let stale = self.db.take_stale_rollups();
+		let mut ranges = vec![RollupRange { from: since, ..RollupRange::default() }];
+		ranges.extend(
+			stale
+				.iter()
+				.filter(|(_, (first, _))| *first < since)
+				.map(|(keyword, (first, last))| RollupRange::covering(keyword, *first, *last)),
+		);
+
+		let mut written = 0;
+		for range in &ranges {
+			match self.db.refresh_rollups(range).await {
+				Ok(count) => written += count,
+				Err(err) => {
+					self.db.mark_rollups_stale(stale);
+					return Err(err);
+				}
+			}
+		}
+		Ok(written)
+	}
+}
+
+/// Enforce the retention policies of all keywords: remove the sentiment
+/// entries and rollups before the configured durations. The rollups of the
+/// removed entries are recomputed before, so they are kept. Only counts the
+/// data to remove in a dry run. Returns the data of each keyword that was
+/// removed.
+pub async fn enforce_retention(
+	db: &SentimentDB,
+	settings: &RetentionSettings,
+	dry_run: bool,
+) -> sqlx::Result<Vec<RetentionItem>> {
+	let mut keywords: BTreeSet<String> =
+		db.keywords().await?.into_iter().map(|entry| entry.keyword).collect();
+	keywords.extend(settings.keywords.keys().cloned());
+
+	let now = OffsetDateTime::now_utc().unix_timestamp();
+	let mut items = Vec::new();
+	for keyword in keywords {
+		let policy = settings.policy(&keyword);
+		// Entries are removed per day, so rollups of all resolutions are complete
+		// or removed completely.
+		let targets = [
+			(RetentionTarget::Raw, policy.raw, Resolution::Day),
+			(RetentionTarget::Rollup(Resolution::Minute), policy.minute, Resolution::Minute),
+			(RetentionTarget::Rollup(Resolution::Hour), policy.hour, Resolution::Hour),
+			(RetentionTarget::Rollup(Resolution::Day), policy.day, Resolution::Day),
+		];
+		for (target, duration, alignment) in targets {
+			let duration = match duration {
+				Some(duration) => duration,
+				None => continue,
+			};
+			let cutoff = alignment.bucket(now.saturating_sub(duration.whole_seconds()));
+			if target == RetentionTarget::Raw && !dry_run {
+				let range =
+					RollupRange { keyword: Some(keyword.clone()), from: 0, to: Some(cutoff) };
+				db.refresh_rollups(&range).await?;
+			}
+			let rows = db.expire(&keyword, target, cutoff, dry_run).await?;
+			if rows > 0 {
+				items.push(RetentionItem {
+					keyword: keyword.clone(),
+					data: target.to_string(),
+					cutoff,
+					rows,
+				});
+			}
+		}
+	}
+	Ok(items)
+}
@@ -0,0 +1,480 @@
+//! Matching of texts to keywords via keyword rules
+
+use std::{iter::Peekable, vec::IntoIter};
+
+use color_eyre::{
+	eyre::{bail, eyre},
+	Result,
+};
+use regex::{Regex, RegexBuilder};
+
+use crate::settings::{KeywordRule, MatchForm};
+
+/// Compiled keyword rules, attributing texts to keywords. Used for the
+/// streams and the search backfill alike.
+#[derive(Debug, Clone)]
+pub struct KeywordMatcher {
+	rules: Vec<CompiledRule>,
+}
+
+/// A compiled keyword rule
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+	/// Keyword to store matching texts as, in lowercase
+	keyword: String,
+	/// Keyword and aliases in their form, e.g. `#rust`
+	terms: Vec<String>,
+	/// Exclusion terms
+	exclude_terms: Vec<String>,
+	/// Pattern matching any of the terms
+	pattern: Regex,
+	/// Additional regular expression
+	regex: Option<Regex>,
+	/// Boolean expression to satisfy
+	expr: Option<Expr>,
+	/// Pattern matching any of the exclusion terms
+	exclude: Option<Regex>,
+	/// Languages to track, all if empty
+	languages: Vec<String>,
+}
+
+/// Boolean expression of terms
+#[derive(Debug, Clone)]
+enum Expr {
+	Term(Regex),
+	Not(Box<Expr>),
+	And(Vec<Expr>),
+	Or(Vec<Expr>),
+}
+
+/// Token of a boolean expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Open,
+	Close,
+	And,
+	Or,
+	Not,
+	Term(String),
+}
+
+impl KeywordMatcher {
+	/// Compile the keyword rules.
+	pub fn new(rules: &[KeywordRule]) -> Result<Self> {
+		let rules = rules.iter().map(CompiledRule::new).collect::<Result<_>>()?;
+		Ok(KeywordMatcher { rules })
+	}
+
+	/// The compiled rules
+	pub fn rules(&self) -> &[CompiledRule] {
+		&self.rules
+	}
+
+	/// All keywords of the rules
+	pub fn keywords(&self) -> Vec<String> {
+		self.rules.iter().map(|rule| rule.keyword.clone()).collect()
+	}
+
+	/// Whether the keyword belongs to one of the rules
+	pub fn contains(&self, keyword: &str) -> bool {
+		let keyword = keyword.to_lowercase();
+		self.rules.iter().any(|rule| rule.keyword == keyword)
+	}
+
+	/// All terms of all rules, to track in streams
+	pub fn track_terms(&self) -> Vec<String> {
+		self.rules.iter().flat_map(|rule| rule.terms.iter().cloned()).collect()
+	}
+
+	/// All languages of all rules, to track in streams. Empty if any rule
+	/// tracks all languages.
+	pub fn languages(&self) -> Vec<String> {
+		if self.rules.iter().any(|rule| rule.languages.is_empty()) {
+			return Vec::new();
+		}
+		let mut languages: Vec<String> =
+			self.rules.iter().flat_map(|rule| rule.languages.iter().cloned()).collect();
+		languages.sort();
+		languages.dedup();
+		languages
+	}
+
+	/// Get the keywords the text matches
+	pub fn matches(&self, text: &str) -> Vec<String> {
+		self.rules
+			.iter()
+			.filter(|rule| rule.matches(text))
+			.map(|rule| rule.keyword.clone())
+			.collect()
+	}
+
+	/// Get the keywords the text in the given language matches. Texts
+	/// without known language are matched regardless of the rules' languages.
+	pub fn matches_lang(&self, text: &str, lang: Option<&str>) -> Vec<String> {
+		self.rules
+			.iter()
+			.filter(|rule| rule.tracks_lang(lang) && rule.matches(text))
+			.map(|rule| rule.keyword.clone())
+			.collect()
+	}
+}
+
+impl CompiledRule {
+	/// Compile a keyword rule.
+	fn new(rule: &KeywordRule) -> Result<Self> {
+		let prefix = match rule.form {
+			MatchForm::Hashtag => "#",
+			MatchForm::Cashtag => "$",
+			MatchForm::Word | MatchForm::Substring => "",
+		};
+		let terms: Vec<String> = std::iter::once(&rule.keyword)
+			.chain(rule.aliases.iter())
+			.map(|term| format!("{}{}", prefix, term.trim_start_matches(prefix)))
+			.collect();
+
+		let exclude = if rule.exclude.is_empty() {
+			None
+		} else {
+			Some(terms_regex(&rule.exclude, MatchForm::Word)?)
+		};
+		let regex = rule
+			.regex
+			.as_deref()
+			.map(|regex| RegexBuilder::new(regex).case_insensitive(true).build())
+			.transpose()?;
+		let expr = rule.expr.as_deref().map(Expr::parse).transpose()?;
+
+		Ok(CompiledRule {
+			keyword: rule.keyword.to_lowercase(),
+			pattern: terms_regex(&terms, rule.form)?,
+			terms,
+			exclude_terms: rule.exclude.clone(),
+			regex,
+			expr,
+			exclude,
+			languages: rule.languages.iter().map(|lang| lang.to_lowercase()).collect(),
+		})
+	}
+
+	/// Keyword to store matching texts as
+	pub fn keyword(&self) -> &str {
+		&self.keyword
+	}
+
+	/// Keyword and aliases in the form they have to appear in
+	pub fn terms(&self) -> &[String] {
+		&self.terms
+	}
+
+	/// Whether the text matches the rule
+	pub fn matches(&self, text: &str) -> bool {
+		let included = self.pattern.is_match(text)
+			|| self.regex.as_ref().map_or(false, |regex| regex.is_match(text));
+		included
+			&& self.expr.as_ref().map_or(true, |expr| expr.eval(text))
+			&& !self.exclude.as_ref().map_or(false, |exclude| exclude.is_match(text))
+	}
+
+	/// Languages to track, all if empty
+	pub fn languages(&self) -> &[String] {
+		&self.languages
+	}
+
+	/// Whether texts in the language are tracked. Texts without known language
+	/// are always tracked.
+	pub fn tracks_lang(&self, lang: Option<&str>) -> bool {
+		match lang {
+			Some(lang) if !self.languages.is_empty() => {
+				self.languages.iter().any(|tracked| tracked.eq_ignore_ascii_case(lang))
+			}
+			_ => true,
+		}
+	}
+
+	/// Query for Twitter's search and stream rules, finding the terms in the
+	/// rule's languages without the exclusion terms. Regex and expression can
+	/// not be expressed, so results have to be filtered with `matches`.
+	pub fn search_query(&self) -> String {
+		let terms: Vec<String> = self.terms.iter().map(|term| quote_term(term)).collect();
+		let mut query = match terms.len() {
+			1 => terms[0].clone(),
+			_ => format!("({})", terms.join(" OR ")),
+		};
+		for term in &self.exclude_terms {
+			query.push_str(" -");
+			query.push_str(&quote_term(term));
+		}
+		let languages: Vec<String> =
+			self.languages.iter().map(|lang| format!("lang:{}", lang)).collect();
+		match languages.len() {
+			0 => {}
+			1 => query.push_str(&format!(" {}", languages[0])),
+			_ => query.push_str(&format!(" ({})", languages.join(" OR "))),
+		}
+		query
+	}
+}
+
+/// Quote a term for a Twitter query if it contains spaces
+fn quote_term(term: &str) -> String {
+	if term.contains(char::is_whitespace) {
+		format!("\"{}\"", term)
+	} else {
+		term.to_owned()
+	}
+}
+
+/// Build a case insensitive regex matching any of the terms in the given form
+fn terms_regex<S: AsRef<str>>(terms: &[S], form: MatchForm) -> Result<Regex> {
+	let terms: Vec<String> = terms.iter().map(|term| regex::escape(term.as_ref())).collect();
+	let terms = terms.join("|");
+	let pattern = match form {
+		MatchForm::Substring => format!("(?:{})", terms),
+		_ => format!(r"(?:^|\W)(?:{})(?:$|\W)", terms),
+	};
+	Ok(RegexBuilder::new(&pattern).case_insensitive(true).build()?)
+}
+
+impl Expr {
+	/// Parse a boolean expression of terms with `AND`, `OR`, `NOT` (or `-`),
+	/// parentheses and quoted phrases. Terms next to each other are joined
+	/// with `AND`.
+	fn parse(expr: &str) -> Result<Self> {
+		let mut tokens = tokenize(expr)?.into_iter().peekable();
+		let parsed = Self::parse_or(&mut tokens)?;
+		if let Some(token) = tokens.next() {
+			bail!("Unexpected {:?} in expression `{}`", token, expr);
+		}
+		Ok(parsed)
+	}
+
+	/// Parse terms joined with `OR`
+	fn parse_or(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Self> {
+		let mut terms = vec![Self::parse_and(tokens)?];
+		while tokens.next_if_eq(&Token::Or).is_some() {
+			terms.push(Self::parse_and(tokens)?);
+		}
+		Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::Or(terms) })
+	}
+
+	/// Parse terms joined with `AND` or nothing
+	fn parse_and(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Self> {
+		let mut terms = vec![Self::parse_unary(tokens)?];
+		loop {
+			match tokens.peek() {
+				Some(Token::And) => {
+					tokens.next();
+				}
+				Some(Token::Open | Token::Not | Token::Term(_)) => {}
+				_ => break,
+			}
+			terms.push(Self::parse_unary(tokens)?);
+		}
+		Ok(if terms.len() == 1 { terms.remove(0) } else { Expr::And(terms) })
+	}
+
+	/// Parse a negation, a parenthesized expression or a term
+	fn parse_unary(tokens: &mut Peekable<IntoIter<Token>>) -> Result<Self> {
+		match tokens.next() {
+			Some(Token::Not) => Ok(Expr::Not(Box::new(Self::parse_unary(tokens)?))),
+			Some(Token::Open) => {
+				let expr = Self::parse_or(tokens)?;
+				match tokens.next() {
+					Some(Token::Close) => Ok(expr),
+					_ => Err(eyre!("Missing closing parenthesis in expression")),
+				}
+			}
+			Some(Token::Term(term)) => Ok(Expr::Term(terms_regex(&[term], MatchForm::Word)?)),
+			token => Err(eyre!("Expected term in expression, found {:?}", token)),
+		}
+	}
+
+	/// Evaluate the expression on a text
+	fn eval(&self, text: &str) -> bool {
+		match self {
+			Expr::Term(regex) => regex.is_match(text),
+			Expr::Not(expr) => !expr.eval(text),
+			Expr::And(exprs) => exprs.iter().all(|expr| expr.eval(text)),
+			Expr::Or(exprs) => exprs.iter().any(|expr| expr.eval(text)),
+		}
+	}
+}
+
+/// Split a boolean expression into tokens
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+	let mut tokens = Vec::new();
+	let mut chars = expr.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			c if c.is_whitespace() => {}
+			'(' => tokens.push(Token::Open),
+			')' => tokens.push(Token::Close),
+			'-' => tokens.push(Token::Not),
+			'"' => {
+				let mut phrase = String::new();
+				loop {
+					match chars.next() {
+						Some('"') => break,
+						Some(c) => phrase.push(c),
+						None => bail!("Missing closing quote in expression `{}`", expr),
+					}
+				}
+				if phrase.trim().is_empty() {
+					bail!("Empty phrase in expression `{}`", expr);
+				}
+				tokens.push(Token::Term(phrase));
+			}
+			c => {
+				let mut word = c.to_string();
+				while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()\"".contains(*c)) {
+					word.push(c);
+				}
+				tokens.push(match word.as_str() {
+					"AND" => Token::And,
+					"OR" => Token::Or,
+					"NOT" => Token::Not,
+					_ => Token::Term(word),
+				});
+			}
+		}
+	}
+	Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn rule(keyword: &str) -> KeywordRule {
+		KeywordRule::new(keyword.to_owned())
+	}
+
+	fn matcher(rule: KeywordRule) -> KeywordMatcher {
+		KeywordMatcher::new(&[rule]).expect("Invalid test rule")
+	}
+
+	fn with_expr(expr: &str) -> KeywordMatcher {
+		matcher(KeywordRule { expr: Some(expr.to_owned()), ..rule("rust") })
+	}
+
+	#[test]
+	fn matches_whole_words() {
+		let matcher = matcher(rule("Rust"));
+		for text in ["Rust", "I love rust!", "rust-lang", "(RUST)", "#rust", "$rust"] {
+			assert_eq!(matcher.matches(text), vec!["rust"], "{}", text);
+		}
+		for text in ["trust me", "rustacean", "rusty", "ru st"] {
+			assert!(matcher.matches(text).is_empty(), "{}", text);
+		}
+		assert!(matcher.contains("RUST"));
+	}
+
+	#[test]
+	fn matches_hashtags_and_cashtags() {
+		let hashtag = matcher(KeywordRule { form: MatchForm::Hashtag, ..rule("#rust") });
+		assert_eq!(hashtag.track_terms(), vec!["#rust"]);
+		assert_eq!(hashtag.matches("Learning #Rust today"), vec!["#rust"]);
+		assert!(hashtag.matches("Learning rust today").is_empty());
+		assert!(hashtag.matches("#rustlang").is_empty());
+
+		let cashtag = matcher(KeywordRule { form: MatchForm::Cashtag, ..rule("TSLA") });
+		assert_eq!(cashtag.track_terms(), vec!["$TSLA"]);
+		assert_eq!(cashtag.matches("$tsla is up"), vec!["tsla"]);
+		assert!(cashtag.matches("TSLA is up").is_empty());
+		assert!(cashtag.matches("#TSLA is up").is_empty());
+
+		let substring = matcher(KeywordRule { form: MatchForm::Substring, ..rule("rust") });
+		assert_eq!(substring.matches("trust me"), vec!["rust"]);
+	}
+
+	#[test]
+	fn matches_regex_terms() {
+		let matcher =
+			matcher(KeywordRule { regex: Some(r"\brustaceans?\b".to_owned()), ..rule("rust") });
+		assert_eq!(matcher.matches("Hello Rustaceans"), vec!["rust"]);
+		assert_eq!(matcher.matches("hello rust"), vec!["rust"]);
+		assert!(matcher.matches("hello rustaceanfoo").is_empty());
+
+		let invalid = KeywordRule { regex: Some("(rust".to_owned()), ..rule("rust") };
+		assert!(KeywordMatcher::new(&[invalid]).is_err());
+	}
+
+	#[test]
+	fn applies_expression_precedence() {
+		// NOT binds tighter than AND, AND tighter than OR
+		let matcher = with_expr("programming OR language NOT game");
+		for text in ["rust programming", "rust language", "rust game programming"] {
+			assert_eq!(matcher.matches(text), vec!["rust"], "{}", text);
+		}
+		for text in ["rust", "rust language game", "programming"] {
+			assert!(matcher.matches(text).is_empty(), "{}", text);
+		}
+
+		let matcher = with_expr("(programming OR language) AND -game");
+		assert_eq!(matcher.matches("rust language"), vec!["rust"]);
+		assert!(matcher.matches("rust game programming").is_empty());
+
+		let matcher = with_expr("NOT (game OR movie) \"systems language\"");
+		assert_eq!(matcher.matches("Rust is a systems language"), vec!["rust"]);
+		assert!(matcher.matches("rust language of systems").is_empty());
+		assert!(matcher.matches("rust game in a systems language").is_empty());
+	}
+
+	#[test]
+	fn applies_aliases_and_excludes() {
+		let matcher = matcher(KeywordRule {
+			aliases: vec!["rustlang".to_owned(), "ferris crab".to_owned()],
+			exclude: vec!["game".to_owned(), "iron oxide".to_owned()],
+			..rule("rust")
+		});
+		assert_eq!(matcher.track_terms(), vec!["rust", "rustlang", "ferris crab"]);
+		for text in ["rust", "#rustlang rocks", "Ferris Crab says hi", "gamers love rust"] {
+			assert_eq!(matcher.matches(text), vec!["rust"], "{}", text);
+		}
+		for text in ["rust game", "rust and Iron Oxide", "ferris"] {
+			assert!(matcher.matches(text).is_empty(), "{}", text);
+		}
+	}
+
+	#[test]
+	fn matches_rule_languages() {
+		let matcher = matcher(KeywordRule { languages: vec!["EN".to_owned()], ..rule("rust") });
+		assert_eq!(matcher.languages(), vec!["en"]);
+		assert_eq!(matcher.matches_lang("rust", Some("en")), vec!["rust"]);
+		assert_eq!(matcher.matches_lang("rust", None), vec!["rust"]);
+		assert!(matcher.matches_lang("rust", Some("de")).is_empty());
+	}
+
+	#[test]
+	fn rejects_malformed_expressions() {
+		for expr in
+			["(game OR movie", "game OR", "AND game", ")", "game)", "NOT", "\"game", "\"\"", ""]
+		{
+			let rule = KeywordRule { expr: Some(expr.to_owned()), ..rule("rust") };
+			assert!(KeywordMatcher::new(&[rule]).is_err(), "{}", expr);
+		}
+	}
+
+	#[test]
+	fn builds_search_queries() {
+		let query = |rule: KeywordRule| matcher(rule).rules()[0].search_query();
+		assert_eq!(query(rule("rust")), "rust lang:en");
+		assert_eq!(query(KeywordRule { languages: Vec::new(), ..rule("rust") }), "rust");
+		assert_eq!(
+			query(KeywordRule { form: MatchForm::Hashtag, ..rule("rust") }),
+			"#rust lang:en"
+		);
+		let full = KeywordRule {
+			aliases: vec!["rustlang".to_owned(), "ferris crab".to_owned()],
+			exclude: vec!["game".to_owned(), "iron oxide".to_owned()],
+			regex: Some("rustaceans?".to_owned()),
+			expr: Some("programming".to_owned()),
+			languages: vec!["en".to_owned(), "de".to_owned()],
+			..rule("rust")
+		};
+		assert_eq!(
+			query(full),
+			r#"(rust OR rustlang OR "ferris crab") -game -"iron oxide" (lang:en OR lang:de)"#
+		);
+	}
+}
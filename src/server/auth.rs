@@ -0,0 +1,81 @@
+//! Authentication of API routes with bearer tokens
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+	extract::{Extension, FromRequest, RequestParts},
+	http::{header::AUTHORIZATION, HeaderMap},
+};
+use subtle::ConstantTimeEq;
+
+use super::error::ServerError;
+
+/// Bearer tokens of the authenticated routes. Routes without token are
+/// disabled.
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens {
+	/// Token for pushing texts to the ingest route
+	pub ingest: Option<String>,
+	/// Token for the admin routes
+	pub admin: Option<String>,
+}
+
+impl ApiTokens {
+	/// Check that the request headers contain the expected bearer token.
+	pub fn check(expected: Option<&str>, headers: &HeaderMap) -> Result<(), ServerError> {
+		let expected = expected.ok_or_else(|| ServerError::forbidden("Route is disabled!"))?;
+		let token = headers
+			.get(AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.strip_prefix("Bearer "));
+		// Constant-time comparison, so the time taken does not reveal the token
+		match token {
+			Some(token) if bool::from(token.as_bytes().ct_eq(expected.as_bytes())) => Ok(()),
+			_ => Err(ServerError::unauthorized("Missing or invalid bearer token!")),
+		}
+	}
+}
+
+/// Extractor checking the bearer token of the ingest route. Extractors run in
+/// order, so placed before the body extractor it rejects requests before their
+/// body is read.
+#[derive(Debug, Clone, Copy)]
+pub struct IngestAuth;
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for IngestAuth {
+	type Rejection = ServerError;
+
+	async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		check_request(req, |tokens| tokens.ingest.as_deref()).await?;
+		Ok(IngestAuth)
+	}
+}
+
+/// Extractor checking the bearer token of the admin routes, see `IngestAuth`.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminAuth;
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for AdminAuth {
+	type Rejection = ServerError;
+
+	async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		check_request(req, |tokens| tokens.admin.as_deref()).await?;
+		Ok(AdminAuth)
+	}
+}
+
+/// Check the bearer token of a request against the selected token.
+async fn check_request<B, F>(req: &mut RequestParts<B>, select: F) -> Result<(), ServerError>
+where
+	B: Send,
+	F: FnOnce(&ApiTokens) -> Option<&str>,
+{
+	let Extension(tokens) =
+		Extension::<Arc<ApiTokens>>::from_request(req).await.map_err(ServerError::internal)?;
+	let headers =
+		req.headers().ok_or_else(|| ServerError::internal("Headers were taken already!"))?;
+	ApiTokens::check(select(&tokens), headers)
+}
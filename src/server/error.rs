@@ -36,4 +36,16 @@ impl ServerError {
 	pub fn bad_request<T: ToString>(msg: T) -> Self {
 		Self { status_code: StatusCode::BAD_REQUEST, error_msg: msg.to_string() }
 	}
+
+	pub fn unauthorized<T: ToString>(msg: T) -> Self {
+		Self { status_code: StatusCode::UNAUTHORIZED, error_msg: msg.to_string() }
+	}
+
+	pub fn forbidden<T: ToString>(msg: T) -> Self {
+		Self { status_code: StatusCode::FORBIDDEN, error_msg: msg.to_string() }
+	}
+
+	pub fn internal<T: ToString>(msg: T) -> Self {
+		Self { status_code: StatusCode::INTERNAL_SERVER_ERROR, error_msg: msg.to_string() }
+	}
 }
@@ -1,5 +1,6 @@
 //! Webserver to serve the Twitter sentiment info
 
+mod auth;
 mod error;
 mod routes;
 mod svg;
@@ -7,11 +8,15 @@ mod templates;
 
 use std::{net::SocketAddr, sync::Arc};
 
-use axum::{routing::get, AddExtensionLayer, Router};
+use axum::{
+	routing::{get, patch, post},
+	AddExtensionLayer, Router,
+};
 use color_eyre::Result;
 use derive_builder::Builder;
 
-use crate::{SentimentDB, Settings};
+use self::auth::ApiTokens;
+use crate::{KeywordRegistry, SentimentClassifier, SentimentDB, Settings, StreamStatus};
 
 /// Webserver
 #[derive(Debug, Clone, Builder)]
@@ -22,6 +27,19 @@ pub struct Server {
 	db: Arc<SentimentDB>,
 	/// The app's configuration
 	config: Arc<Settings>,
+	/// Classifier for pushed texts
+	sentiment_classifier: SentimentClassifier,
+	/// Registry of the tracked keywords
+	keywords: Arc<KeywordRegistry>,
+	/// Status of the tweet streams
+	#[builder(default)]
+	streams: Vec<StreamStatus>,
+	/// Bearer token for the ingest route, the route is disabled if not set
+	#[builder(default, setter(strip_option))]
+	ingest_token: Option<String>,
+	/// Bearer token for the admin routes, the routes are disabled if not set
+	#[builder(default, setter(strip_option))]
+	admin_token: Option<String>,
 }
 
 impl Server {
@@ -34,17 +52,226 @@ impl Server {
 	fn routes() -> Router {
 		Router::new()
 			.route("/", get(routes::list_keywords))
+			.route("/keywords", get(routes::keywords))
 			.route("/svg/:keyword/ema", get(routes::exp_moving_avg))
 			.route("/svg/:keyword/ma", get(routes::moving_avg))
+			.route("/svg/:keyword/rollup", get(routes::rollup_avg))
+			.route("/rollup/:keyword", get(routes::rollups))
+			.route("/export/:keyword", get(routes::export))
+			.route("/ingest/:keyword", post(routes::ingest))
+			.route("/status", get(routes::stream_status))
+			.route(
+				"/admin/keywords",
+				get(routes::admin::list_keywords).post(routes::admin::add_keyword),
+			)
+			.route(
+				"/admin/keywords/:keyword",
+				patch(routes::admin::update_keyword).delete(routes::admin::remove_keyword),
+			)
+			.route("/admin/keywords/:keyword/pause", post(routes::admin::pause_keyword))
+			.route("/admin/keywords/:keyword/resume", post(routes::admin::resume_keyword))
+			.route("/admin/compliance", get(routes::admin::compliance_counts))
+			.route("/admin/retention", get(routes::admin::retention_report))
+	}
+
+	/// The app with all routes and their state, e.g. to call the routes
+	/// in-process without binding the server
+	pub fn router(&self) -> Router {
+		let tokens =
+			ApiTokens { ingest: self.ingest_token.clone(), admin: self.admin_token.clone() };
+		Self::routes()
+			.layer(AddExtensionLayer::new(self.db.clone()))
+			.layer(AddExtensionLayer::new(self.config.clone()))
+			.layer(AddExtensionLayer::new(self.sentiment_classifier.clone()))
+			.layer(AddExtensionLayer::new(self.keywords.clone()))
+			.layer(AddExtensionLayer::new(Arc::new(self.streams.clone())))
+			.layer(AddExtensionLayer::new(Arc::new(tokens)))
 	}
 
 	/// Run the webserver
 	#[tracing::instrument(level = "debug", err, skip_all)]
 	pub async fn run(self) -> Result<()> {
-		let app = Self::routes()
-			.layer(AddExtensionLayer::new(self.db))
-			.layer(AddExtensionLayer::new(self.config));
+		let app = self.router();
 		axum::Server::bind(&self.bind).serve(app.into_make_service()).await?;
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use axum::{
+		body::Body,
+		http::{header, Request, StatusCode},
+		response::Response,
+	};
+	use serde_json::{json, Value};
+	use tower::ServiceExt;
+
+	use super::*;
+	use crate::{
+		database::{EntryBatch, EntryFilter, TweetSentiment},
+		settings::ConflictPolicy,
+	};
+
+	const INGEST_TOKEN: &str = "ingest-token";
+
+	fn settings() -> Settings {
+		serde_json::from_value(json!({
+			"bind": "127.0.0.1:0",
+			"log_level": "info",
+			"twitter": {
+				"track_tweets": ["rust"],
+				"concurrency": 1,
+				"chunk_size": 10,
+				"secs_reconnect": 1,
+			},
+			"web_defaults": { "alpha": 0.1, "window": 10 },
+		}))
+		.expect("Invalid test settings")
+	}
+
+	async fn app(db: Arc<SentimentDB>) -> Result<Router> {
+		let config = Arc::new(settings());
+		let keywords = KeywordRegistry::new(db.clone(), &config.twitter.track_tweets).await?;
+		let server = Server::builder()
+			.bind(([127, 0, 0, 1], 0).into())
+			.db(db)
+			.config(config)
+			.sentiment_classifier(SentimentClassifier::fake())
+			.keywords(Arc::new(keywords))
+			.ingest_token(INGEST_TOKEN.to_owned())
+			.build()?;
+		Ok(server.router())
+	}
+
+	async fn call(app: &Router, request: Request<Body>) -> Result<(StatusCode, Vec<u8>)> {
+		let response: Response = app.clone().oneshot(request).await?;
+		let status = response.status();
+		let body = hyper::body::to_bytes(response.into_body()).await?;
+		Ok((status, body.to_vec()))
+	}
+
+	fn ingest_request(keyword: &str, token: Option<&str>, body: &str) -> Request<Body> {
+		let mut request = Request::post(format!("/ingest/{}", keyword))
+			.header(header::CONTENT_TYPE, "application/json")
+			.header(header::CONTENT_LENGTH, body.len());
+		if let Some(token) = token {
+			request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+		}
+		request.body(Body::from(body.to_owned())).expect("Invalid request")
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn ingest_classifies_and_saves_texts() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		let app = app(db.clone()).await?;
+		let body = json!([
+			{ "id": 1, "created": 1_666_000_000, "text": "Rust is good" },
+			{ "id": 2, "created": 1_666_000_060, "text": "Rust is bad", "lang": "en" },
+		])
+		.to_string();
+
+		let (status, response) =
+			call(&app, ingest_request("Rust", Some(INGEST_TOKEN), &body)).await?;
+		assert_eq!(status, StatusCode::OK);
+		let results: Value = serde_json::from_slice(&response)?;
+		assert_eq!(
+			results,
+			json!([
+				{ "id": 1, "sentiment": 0.9, "duplicate": false },
+				{ "id": 2, "sentiment": -0.9, "duplicate": false },
+			])
+		);
+
+		let entries = db.get("rust", &EntryFilter::default()).await?;
+		assert_eq!(entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), vec![1, 2]);
+		assert!(entries.iter().all(|entry| entry.source == "ingest"));
+		assert_eq!(entries[1].lang.as_deref(), Some("en"));
+
+		let (status, response) =
+			call(&app, ingest_request("rust", Some(INGEST_TOKEN), &body)).await?;
+		assert_eq!(status, StatusCode::OK);
+		let results: Value = serde_json::from_slice(&response)?;
+		assert_eq!(results[0]["duplicate"], json!(true));
+		assert_eq!(results[1]["duplicate"], json!(true));
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn ingest_rejects_unauthorized_and_oversized_requests() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		let app = app(db.clone()).await?;
+		let body =
+			json!([{ "id": 1, "created": 1_666_000_000, "text": "Rust is good" }]).to_string();
+
+		let (status, _) = call(&app, ingest_request("rust", None, &body)).await?;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+		let (status, _) = call(&app, ingest_request("rust", Some("wrong-token"), &body)).await?;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+		let text = "a".repeat(2 * 1024 * 1024);
+		let body = json!([{ "id": 1, "created": 1_666_000_000, "text": text }]).to_string();
+		let (status, _) = call(&app, ingest_request("rust", Some(INGEST_TOKEN), &body)).await?;
+		assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+		let items: Vec<_> = (0..=1000)
+			.map(|id| json!({ "id": id, "created": 1_666_000_000, "text": "Rust" }))
+			.collect();
+		let body = Value::from(items).to_string();
+		let (status, _) = call(&app, ingest_request("rust", Some(INGEST_TOKEN), &body)).await?;
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+
+		assert!(db.get("rust", &EntryFilter::default()).await?.is_empty());
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn export_streams_all_pages() -> Result<()> {
+		let db = Arc::new(SentimentDB::in_memory());
+		let app = app(db.clone()).await?;
+		// More entries than fit on one export page, with equal creation times
+		let count = 10_005;
+		let mut batch = EntryBatch::default();
+		for id in 0..count {
+			let created = 1_666_000_000 + id as i64 / 10;
+			let entry =
+				TweetSentiment::new(id, "rust".to_owned(), "twitter".to_owned(), created, 0.5);
+			batch.entries.push((entry, ConflictPolicy::Ignore));
+		}
+		db.insert_batch(batch).await?;
+
+		let request = Request::get("/export/Rust?format=jsonl").body(Body::empty())?;
+		let (status, body) = call(&app, request).await?;
+		assert_eq!(status, StatusCode::OK);
+		let ids = body
+			.split(|byte| *byte == b'\n')
+			.filter(|line| !line.is_empty())
+			.map(|line| serde_json::from_slice::<Value>(line).map(|entry| entry["id"].as_u64()))
+			.collect::<serde_json::Result<Vec<_>>>()?;
+		assert_eq!(ids, (0..count).map(Some).collect::<Vec<_>>());
+
+		let request = Request::get("/export/unknown").body(Body::empty())?;
+		let (status, _) = call(&app, request).await?;
+		assert_eq!(status, StatusCode::NOT_FOUND);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn rejects_oversized_durations() -> Result<()> {
+		let app = app(Arc::new(SentimentDB::in_memory())).await?;
+		for uri in [
+			"/rollup/rust?last=99999999999d",
+			"/rollup/rust?last=99999999999999999999s",
+			"/rollup/rust?to=-9223372036854775807&last=2s",
+			"/svg/rust/ema?half_life=9999999999999999w",
+		] {
+			let (status, _) = call(&app, Request::get(uri).body(Body::empty())?).await?;
+			assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+		}
+
+		let request = Request::get("/rollup/rust?last=7d").body(Body::empty())?;
+		let (status, _) = call(&app, request).await?;
+		assert_eq!(status, StatusCode::OK);
+		Ok(())
+	}
+}
@@ -0,0 +1,136 @@
+//! Admin routes to manage the tracked keywords and inspect compliance
+//! processing and retention
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+	extract::{Extension, Path},
+	http::StatusCode,
+	Json,
+};
+use tracing::info;
+
+use super::KeywordInfo;
+use crate::{
+	database::KeywordUpdate,
+	maintenance::{self, RetentionItem},
+	server::{auth::AdminAuth, error::ServerError},
+	settings::KeywordRule,
+	KeywordMatcher, KeywordRegistry, SentimentDB, Settings,
+};
+
+/// Lists all keywords with their rules and metadata, including archived ones.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn list_keywords(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+) -> Result<Json<Vec<KeywordInfo>>, ServerError> {
+	info!("List of keywords is being retrieved.");
+
+	let keywords = registry
+		.list()
+		.await
+		.map_err(ServerError::internal)?
+		.into_iter()
+		.map(KeywordInfo::from)
+		.collect();
+	Ok(Json(keywords))
+}
+
+/// Adds a keyword rule or replaces the rule of an existing keyword.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn add_keyword(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+	Json(rule): Json<KeywordRule>,
+) -> Result<StatusCode, ServerError> {
+	KeywordMatcher::new(&[rule.clone()]).map_err(ServerError::bad_request)?;
+
+	registry.add(&rule).await.map_err(ServerError::internal)?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+/// Updates the display name, description or tags of a keyword.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn update_keyword(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+	Path(keyword): Path<String>,
+	Json(update): Json<KeywordUpdate>,
+) -> Result<StatusCode, ServerError> {
+	if update.tags.iter().flatten().any(|tag| tag.contains(',')) {
+		return Err(ServerError::bad_request("Tags must not contain commas!"));
+	}
+	match registry.update(&keyword, &update).await.map_err(ServerError::internal)? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(ServerError::not_found("Keyword does not exist!")),
+	}
+}
+
+/// Removes a keyword from tracking by archiving it, keeping its data.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn remove_keyword(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+	Path(keyword): Path<String>,
+) -> Result<StatusCode, ServerError> {
+	match registry.remove(&keyword).await.map_err(ServerError::internal)? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(ServerError::not_found("Keyword does not exist!")),
+	}
+}
+
+/// Pauses tracking of a keyword.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn pause_keyword(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+	Path(keyword): Path<String>,
+) -> Result<StatusCode, ServerError> {
+	set_paused(&registry, &keyword, true).await
+}
+
+/// Resumes tracking of a paused keyword.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn resume_keyword(
+	_auth: AdminAuth,
+	Extension(registry): Extension<Arc<KeywordRegistry>>,
+	Path(keyword): Path<String>,
+) -> Result<StatusCode, ServerError> {
+	set_paused(&registry, &keyword, false).await
+}
+
+/// Pause or resume tracking of a keyword.
+async fn set_paused(
+	registry: &KeywordRegistry,
+	keyword: &str,
+	paused: bool,
+) -> Result<StatusCode, ServerError> {
+	match registry.set_paused(keyword, paused).await.map_err(ServerError::internal)? {
+		true => Ok(StatusCode::NO_CONTENT),
+		false => Err(ServerError::not_found("Keyword does not exist!")),
+	}
+}
+
+/// Counts of the processed compliance messages (deletions, geo scrubbing,
+/// withheld content) per kind.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn compliance_counts(
+	_auth: AdminAuth,
+	Extension(db): Extension<Arc<SentimentDB>>,
+) -> Result<Json<BTreeMap<String, i64>>, ServerError> {
+	let counts = db.compliance_counts().await?;
+	Ok(Json(counts.into_iter().collect()))
+}
+
+/// Reports which data the retention policies would remove now, without
+/// removing it.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn retention_report(
+	_auth: AdminAuth,
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Extension(settings): Extension<Arc<Settings>>,
+) -> Result<Json<Vec<RetentionItem>>, ServerError> {
+	let items = maintenance::enforce_retention(&db, &settings.retention, true).await?;
+	Ok(Json(items))
+}
@@ -0,0 +1,446 @@
+pub mod admin;
+
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+	body::StreamBody,
+	extract::{ContentLengthLimit, Extension, Path, Query},
+	response::{Headers, Html, IntoResponse, Response},
+	Json,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use super::{auth::IngestAuth, error::ServerError, svg::Svg, templates};
+use crate::{
+	classifier::sentiment_to_float,
+	data,
+	database::{
+		EntryBatch, EntryFilter, Keyword, KeywordStatus, Resolution, RollupBucket, TweetSentiment,
+	},
+	export::{self, ExportFormat, ExportRequest},
+	settings::KeywordRule,
+	SentimentClassifier, SentimentDB, Settings, StreamHealth, StreamStatus,
+};
+
+/// Maximum number of texts in one ingest request
+const MAX_INGEST_BATCH: usize = 1000;
+/// Maximum size of the body of an ingest request in bytes
+const MAX_INGEST_BODY: u64 = 2 * 1024 * 1024;
+
+/// Lines of entries to plot, with the line name
+type EntryLines = Vec<(String, Vec<TweetSentiment>)>;
+
+/// How to split the entries of a keyword into lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+	/// One line for all entries
+	#[serde(alias = "false")]
+	None,
+	/// One line per source
+	#[serde(alias = "true")]
+	Source,
+	/// One line per language
+	Lang,
+}
+
+impl Default for Split {
+	fn default() -> Self {
+		Split::None
+	}
+}
+
+/// Get the entries for a keyword as lines to plot: either one line for all
+/// entries or one line per source or language side by side.
+async fn entry_lines(
+	db: &SentimentDB,
+	keyword: &str,
+	filter: &EntryFilter,
+	split: Split,
+) -> Result<EntryLines, ServerError> {
+	let entries = db.get(keyword, filter).await.map_err(ServerError::not_found)?;
+	let groups = match split {
+		Split::None => return Ok(vec![(keyword.to_owned(), entries)]),
+		Split::Source => data::split_by(entries, |entry| entry.source.clone()),
+		Split::Lang => data::split_by(entries, |entry| {
+			entry.lang.clone().unwrap_or_else(|| "unknown".to_owned())
+		}),
+	};
+	Ok(groups
+		.into_iter()
+		.map(|(group, entries)| (format!("{} ({})", keyword, group), entries))
+		.collect())
+}
+
+/// Time range of the entries to show: `from` and `to` as UNIX timestamp or
+/// RFC 3339, or the duration `last` before `to` or now, e.g. `7d`. Returns the
+/// timestamps of the start and end.
+fn time_range(
+	from: Option<&str>,
+	to: Option<&str>,
+	last: Option<&str>,
+) -> Result<(Option<i64>, Option<i64>), ServerError> {
+	let parse_time = |time: &str| {
+		data::parse_timestamp(time)
+			.ok_or_else(|| ServerError::bad_request(format!("Invalid time: {}", time)))
+	};
+	let to = to.map(parse_time).transpose()?;
+	let from = match (from, last) {
+		(Some(_), Some(_)) => {
+			return Err(ServerError::bad_request("Only one of `from` and `last` is allowed!"))
+		}
+		(Some(from), None) => Some(parse_time(from)?),
+		(None, Some(last)) => {
+			let duration = data::parse_duration(last)
+				.ok_or_else(|| ServerError::bad_request(format!("Invalid duration: {}", last)))?;
+			let end = to.unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp());
+			// The start has to be a valid date, not only a valid integer
+			let from = end
+				.checked_sub(duration.whole_seconds())
+				.filter(|from| OffsetDateTime::from_unix_timestamp(*from).is_ok())
+				.ok_or_else(|| ServerError::bad_request(format!("Duration too long: {}", last)))?;
+			Some(from)
+		}
+		(None, None) => None,
+	};
+	Ok((from, to))
+}
+
+/// Shows page with list of keywords.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn list_keywords(
+	Extension(db): Extension<Arc<SentimentDB>>,
+) -> Result<Html<String>, ServerError> {
+	info!("List of keywords is being retrieved.");
+
+	let keywords = db
+		.keywords()
+		.await?
+		.into_iter()
+		.filter(|entry| entry.status() != KeywordStatus::Archived)
+		.collect();
+
+	let keywords = templates::ListKeywords { keywords };
+	Ok(Html(keywords.render()?))
+}
+
+/// Keyword with its metadata
+#[derive(Debug, Serialize)]
+pub struct KeywordInfo {
+	keyword: String,
+	display_name: Option<String>,
+	description: Option<String>,
+	rule: Option<KeywordRule>,
+	status: KeywordStatus,
+	tags: Vec<String>,
+	created: i64,
+	first_seen: Option<i64>,
+	last_seen: Option<i64>,
+}
+
+impl From<Keyword> for KeywordInfo {
+	fn from(entry: Keyword) -> Self {
+		KeywordInfo {
+			rule: entry.rule().and_then(Result::ok),
+			status: entry.status(),
+			tags: entry.tags(),
+			keyword: entry.keyword,
+			display_name: entry.display_name,
+			description: entry.description,
+			created: entry.created,
+			first_seen: entry.first_seen,
+			last_seen: entry.last_seen,
+		}
+	}
+}
+
+/// Lists the keywords that are not archived with their metadata.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn keywords(
+	Extension(db): Extension<Arc<SentimentDB>>,
+) -> Result<Json<Vec<KeywordInfo>>, ServerError> {
+	let keywords = db
+		.keywords()
+		.await?
+		.into_iter()
+		.filter(|entry| entry.status() != KeywordStatus::Archived)
+		.map(KeywordInfo::from)
+		.collect();
+	Ok(Json(keywords))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryAlpha {
+	alpha: Option<f64>,
+	/// Decay by the elapsed time with this half-life instead of per entry with
+	/// `alpha`, e.g. `6h`
+	half_life: Option<String>,
+	/// Only show entries of this source
+	source: Option<String>,
+	/// Only show entries in this language
+	lang: Option<String>,
+	/// Only show entries since this time
+	from: Option<String>,
+	/// Only show entries before this time
+	to: Option<String>,
+	/// Only show entries of this duration before `to` or now, e.g. `7d`
+	last: Option<String>,
+	/// Show one line per source or language
+	#[serde(default)]
+	split: Split,
+}
+
+/// Responds with a SVG graph for the given keyword and parameters.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn exp_moving_avg(
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Extension(settings): Extension<Arc<Settings>>,
+	Path(keyword): Path<String>,
+	Query(params): Query<QueryAlpha>,
+) -> Result<Svg, ServerError> {
+	info!("SVG graph of exponential moving average is retrieved.");
+	let half_life = match (params.alpha, params.half_life.as_deref()) {
+		(Some(_), Some(_)) => {
+			return Err(ServerError::bad_request("Only one of `alpha` and `half_life` is allowed!"))
+		}
+		(_, Some(half_life)) => match data::parse_duration(half_life) {
+			Some(duration) if duration.is_positive() => Some(duration),
+			_ => return Err(ServerError::bad_request(format!("Invalid half-life: {}", half_life))),
+		},
+		(_, None) => None,
+	};
+	let alpha = params.alpha.unwrap_or(settings.web_defaults.alpha);
+
+	let (from, to) =
+		time_range(params.from.as_deref(), params.to.as_deref(), params.last.as_deref())?;
+	let filter =
+		EntryFilter { source: params.source, lang: params.lang, from, to, ..Default::default() };
+	let lines: Vec<_> = entry_lines(&db, &keyword, &filter, params.split)
+		.await?
+		.into_iter()
+		.map(|(name, entries)| {
+			let points = match half_life {
+				Some(half_life) => data::exp_moving_avg_half_life(&entries, half_life),
+				None => data::exp_moving_avg(&entries, alpha),
+			};
+			(name, points)
+		})
+		.collect();
+
+	let plot = data::plot("Sentiment - Exponential moving average", &lines)?;
+	Ok(Svg(plot))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryWindow {
+	window: Option<usize>,
+	/// Only show entries of this source
+	source: Option<String>,
+	/// Only show entries in this language
+	lang: Option<String>,
+	/// Only show entries since this time
+	from: Option<String>,
+	/// Only show entries before this time
+	to: Option<String>,
+	/// Only show entries of this duration before `to` or now, e.g. `7d`
+	last: Option<String>,
+	/// Show one line per source or language
+	#[serde(default)]
+	split: Split,
+}
+
+/// Responds with a SVG graph for the given keyword and parameters.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn moving_avg(
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Extension(settings): Extension<Arc<Settings>>,
+	Path(keyword): Path<String>,
+	Query(params): Query<QueryWindow>,
+) -> Result<Svg, ServerError> {
+	info!("SVG graph of moving average is retrieved.");
+	let window = params.window.unwrap_or(settings.web_defaults.window);
+	if window == 0 {
+		return Err(ServerError::bad_request("Window size of 0 is not allowed!"));
+	}
+
+	let (from, to) =
+		time_range(params.from.as_deref(), params.to.as_deref(), params.last.as_deref())?;
+	let filter =
+		EntryFilter { source: params.source, lang: params.lang, from, to, ..Default::default() };
+	let lines: Vec<_> = entry_lines(&db, &keyword, &filter, params.split)
+		.await?
+		.into_iter()
+		.map(|(name, entries)| (name, data::moving_avg(&entries, window)))
+		.collect();
+
+	let plot = data::plot("Sentiment - Moving average", &lines)?;
+	Ok(Svg(plot))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRollup {
+	/// Resolution of the buckets, hourly by default
+	resolution: Option<Resolution>,
+	/// Only buckets since this time
+	from: Option<String>,
+	/// Only buckets before this time
+	to: Option<String>,
+	/// Only buckets of this duration before `to` or now, e.g. `30d`
+	last: Option<String>,
+}
+
+/// Get the rollups of a keyword for the query
+async fn query_rollups(
+	db: &SentimentDB,
+	keyword: &str,
+	params: &QueryRollup,
+) -> Result<Vec<RollupBucket>, ServerError> {
+	let (from, to) =
+		time_range(params.from.as_deref(), params.to.as_deref(), params.last.as_deref())?;
+	let resolution = params.resolution.unwrap_or(Resolution::Hour);
+	Ok(db.rollups(keyword, resolution, from, to).await?)
+}
+
+/// Responds with the aggregated sentiment of a keyword per time bucket.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn rollups(
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Path(keyword): Path<String>,
+	Query(params): Query<QueryRollup>,
+) -> Result<Json<Vec<RollupBucket>>, ServerError> {
+	info!("Rollups are retrieved.");
+	Ok(Json(query_rollups(&db, &keyword, &params).await?))
+}
+
+/// Responds with a SVG graph of the average sentiment per time bucket.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn rollup_avg(
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Path(keyword): Path<String>,
+	Query(params): Query<QueryRollup>,
+) -> Result<Svg, ServerError> {
+	info!("SVG graph of the rollup average is retrieved.");
+	let buckets = query_rollups(&db, &keyword, &params).await?;
+	let lines = vec![(keyword, data::rollup_avg(&buckets))];
+	let plot = data::plot("Sentiment - Average per time bucket", &lines)?;
+	Ok(Svg(plot))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryExport {
+	/// File format, CSV by default
+	#[serde(default)]
+	format: ExportFormat,
+	/// Export the rollups of this resolution instead of the raw entries
+	resolution: Option<Resolution>,
+	/// Only data since this time
+	from: Option<String>,
+	/// Only data before this time
+	to: Option<String>,
+	/// Only data of this duration before `to` or now, e.g. `30d`
+	last: Option<String>,
+}
+
+/// Streams the raw entries or the rollups of a keyword as CSV, JSON Lines or
+/// Parquet file.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn export(
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Path(keyword): Path<String>,
+	Query(params): Query<QueryExport>,
+) -> Result<Response, ServerError> {
+	let keyword = keyword.to_lowercase();
+	info!("Export of keyword `{}` is retrieved.", keyword);
+	let (from, to) =
+		time_range(params.from.as_deref(), params.to.as_deref(), params.last.as_deref())?;
+	let entry = db
+		.keyword(&keyword)
+		.await?
+		.ok_or_else(|| ServerError::not_found("Keyword does not exist!"))?;
+	let request = ExportRequest { format: params.format, resolution: params.resolution, from, to };
+	let chunks = export::export(db.clone(), &entry, &request).map_err(ServerError::internal)?;
+
+	let filename = format!("{}.{}", entry.keyword, request.format.extension());
+	let headers = Headers(vec![
+		("content-type", request.format.content_type().to_owned()),
+		("content-disposition", format!("attachment; filename=\"{}\"", filename)),
+	]);
+	Ok((headers, StreamBody::new(chunks)).into_response())
+}
+
+/// Responds with the health of the tweet streams.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn stream_status(
+	Extension(streams): Extension<Arc<Vec<StreamStatus>>>,
+) -> Json<Vec<StreamHealth>> {
+	Json(streams.iter().map(StreamStatus::get).collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IngestItem {
+	id: u64,
+	created: i64,
+	text: String,
+	/// Language of the text, classified with the default model if not set
+	#[serde(default)]
+	lang: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestResult {
+	id: u64,
+	sentiment: f64,
+	/// Whether an entry for the text was stored already
+	duplicate: bool,
+}
+
+/// Classifies a batch of pushed texts, saves them for the given keyword and
+/// responds with their sentiment.
+#[tracing::instrument(level = "debug", err, skip_all)]
+pub async fn ingest(
+	_auth: IngestAuth,
+	Extension(db): Extension<Arc<SentimentDB>>,
+	Extension(settings): Extension<Arc<Settings>>,
+	Extension(sentiment_classifier): Extension<SentimentClassifier>,
+	Path(keyword): Path<String>,
+	ContentLengthLimit(Json(items)): ContentLengthLimit<Json<Vec<IngestItem>>, MAX_INGEST_BODY>,
+) -> Result<Json<Vec<IngestResult>>, ServerError> {
+	let keyword = keyword.to_lowercase();
+	if items.len() > MAX_INGEST_BATCH {
+		return Err(ServerError::bad_request(format!(
+			"At most {} texts are allowed per request!",
+			MAX_INGEST_BATCH
+		)));
+	}
+	info!("Ingesting {} pushed texts.", items.len());
+
+	let texts = items.iter().map(|item| (item.text.clone(), item.lang.clone())).collect();
+	let sentiments =
+		sentiment_classifier.predict_lang(texts).await.map_err(ServerError::internal)?;
+
+	// All entries are saved in one transaction, the duplicates are known after.
+	let mut batch = EntryBatch::default();
+	let mut results = Vec::with_capacity(items.len());
+	for (item, sentiment) in items.into_iter().zip(sentiments) {
+		let sentiment = sentiment_to_float(&sentiment);
+		let mut entry = TweetSentiment::new(
+			item.id,
+			keyword.clone(),
+			"ingest".to_owned(),
+			item.created,
+			sentiment,
+		);
+		entry.lang = item.lang;
+		entry.model_version = sentiment_classifier.model_version();
+		batch.entries.push((entry, settings.twitter.on_conflict));
+		results.push(IngestResult { id: item.id, sentiment, duplicate: false });
+	}
+	let outcomes = db.insert_batch_outcomes(batch).await?;
+	for (result, outcome) in results.iter_mut().zip(outcomes) {
+		result.duplicate = outcome.is_duplicate();
+	}
+	Ok(Json(results))
+}
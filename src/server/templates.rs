@@ -2,9 +2,12 @@
 
 use askama::Template;
 
+use crate::database::Keyword;
+
 /// List keywords template
 #[derive(Debug, Clone, Template)]
 #[template(path = "list_keywords.htm")]
 pub struct ListKeywords {
-	pub keywords: Vec<String>,
+	/// Keywords that are not archived
+	pub keywords: Vec<Keyword>,
 }
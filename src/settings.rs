@@ -1,7 +1,10 @@
 //! Configuration module
 
+use std::{collections::HashMap, str::FromStr};
+
 use config::{ConfigError, Environment, File};
-use serde::{de::Error, Deserialize, Deserializer};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use time::{macros::format_description, Date, Duration};
 use tracing::{metadata::ParseLevelError, Level};
 
 /// This app's configuration
@@ -14,6 +17,19 @@ pub struct Settings {
 	pub log_level: Level,
 	/// Twitter listener & runner settings
 	pub twitter: TwitterSettings,
+	/// Mastodon listener settings, disabled if not set. Uses the Twitter
+	/// runner settings for keywords and processing.
+	#[serde(default)]
+	pub mastodon: Option<MastodonSettings>,
+	/// Sentiment models, by default only the English model is used
+	#[serde(default)]
+	pub classifier: ClassifierSettings,
+	/// Maintenance of the rollup tables
+	#[serde(default)]
+	pub rollups: RollupSettings,
+	/// Removal of old data, everything is kept by default
+	#[serde(default)]
+	pub retention: RetentionSettings,
 	/// Defaults for server routes
 	pub web_defaults: WebDefaults,
 }
@@ -21,14 +37,463 @@ pub struct Settings {
 /// Twitter listener & processor settings
 #[derive(Debug, Clone, Deserialize)]
 pub struct TwitterSettings {
-	/// Words to track via Twitter streams
-	pub track_tweets: Vec<String>,
+	/// Initial keywords to track via Twitter streams, either plain words or
+	/// match rules. They are added to the tracked keywords in the database on
+	/// startup if missing, so removed keywords have to be removed here too.
+	pub track_tweets: Vec<KeywordRule>,
 	/// Concurrency of tweet classification
 	pub concurrency: usize,
 	/// Chunk size of tweets
 	pub chunk_size: usize,
-	/// Number of seconds to wait until reconnecting
+	/// Maximum number of milliseconds a tweet waits for its chunk to fill
+	/// before the incomplete chunk is processed
+	#[serde(default = "default_batch_latency_ms")]
+	pub batch_latency_ms: u64,
+	/// Maximum number of seconds to wait until reconnecting. The delay grows
+	/// exponentially from the recommended minimum of the error up to this,
+	/// unless the minimum is larger.
 	pub secs_reconnect: u64,
+	/// Store the texts of the tweets along with their metadata. Disable for
+	/// privacy-sensitive deployments.
+	#[serde(default = "default_store_text")]
+	pub store_text: bool,
+	/// How an entry for a tweet that is stored already is handled, e.g. when
+	/// it is received by both backfill and stream
+	#[serde(default)]
+	pub on_conflict: ConflictPolicy,
+	/// How retweets are handled
+	#[serde(default)]
+	pub retweets: RetweetPolicy,
+	/// Which text of quote tweets is classified
+	#[serde(default)]
+	pub quotes: QuotePolicy,
+	/// Replay recorded tweets from a file instead of connecting to Twitter
+	#[serde(default)]
+	pub replay: Option<ReplaySettings>,
+	/// Use Twitter's v2 filtered stream instead of the v1.1 filter stream
+	#[serde(default)]
+	pub api_v2: Option<TwitterV2Settings>,
+	/// On-disk queue between receiving and processing tweets, disabled if not
+	/// set
+	#[serde(default)]
+	pub spool: Option<SpoolSettings>,
+	/// Backfill of historical tweets via the search API, disabled if not set
+	#[serde(default)]
+	pub backfill: Option<BackfillSettings>,
+}
+
+/// Rule for matching tweets to a keyword. A tweet matches if it contains the
+/// keyword or one of the aliases or matches the regex, satisfies the
+/// expression if given and contains none of the exclusion terms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "KeywordRuleConfig")]
+pub struct KeywordRule {
+	/// Keyword the matching tweets are stored as
+	pub keyword: String,
+	/// Further terms matching the keyword
+	pub aliases: Vec<String>,
+	/// How the keyword and aliases have to appear in the text
+	pub form: MatchForm,
+	/// Regular expression matching the keyword, in addition to the terms
+	pub regex: Option<String>,
+	/// Boolean expression that has to be satisfied as well, e.g.
+	/// `programming OR (language AND NOT game)`
+	pub expr: Option<String>,
+	/// Terms that exclude a tweet from the keyword
+	pub exclude: Vec<String>,
+	/// Languages of the tweets to track, all languages if empty
+	pub languages: Vec<String>,
+}
+
+/// Configuration of a keyword rule: a plain word or the full rule
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum KeywordRuleConfig {
+	/// Plain keyword matched as whole word
+	Plain(String),
+	/// Full rule
+	Rule {
+		keyword: String,
+		#[serde(default)]
+		aliases: Vec<String>,
+		#[serde(default)]
+		form: MatchForm,
+		#[serde(default)]
+		regex: Option<String>,
+		#[serde(default)]
+		expr: Option<String>,
+		#[serde(default)]
+		exclude: Vec<String>,
+		#[serde(default = "default_languages")]
+		languages: Vec<String>,
+	},
+}
+
+impl From<KeywordRuleConfig> for KeywordRule {
+	fn from(config: KeywordRuleConfig) -> Self {
+		match config {
+			KeywordRuleConfig::Plain(keyword) => KeywordRule::new(keyword),
+			KeywordRuleConfig::Rule { keyword, aliases, form, regex, expr, exclude, languages } => {
+				KeywordRule { keyword, aliases, form, regex, expr, exclude, languages }
+			}
+		}
+	}
+}
+
+impl KeywordRule {
+	/// Rule matching the keyword as whole word
+	pub fn new(keyword: String) -> Self {
+		KeywordRule {
+			keyword,
+			aliases: Vec::new(),
+			form: MatchForm::default(),
+			regex: None,
+			expr: None,
+			exclude: Vec::new(),
+			languages: default_languages(),
+		}
+	}
+}
+
+/// Default languages of keyword rules: English
+fn default_languages() -> Vec<String> {
+	vec!["en".to_owned()]
+}
+
+/// How terms have to appear in a text to match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchForm {
+	/// As whole word, including hashtags and cashtags
+	Word,
+	/// Only as hashtag, e.g. `#rust`
+	Hashtag,
+	/// Only as cashtag, e.g. `$TSLA`
+	Cashtag,
+	/// Anywhere in the text, also within other words
+	Substring,
+}
+
+impl Default for MatchForm {
+	fn default() -> Self {
+		MatchForm::Word
+	}
+}
+
+/// How an entry for a tweet and keyword that is stored already is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+	/// Keep the stored entry
+	Ignore,
+	/// Replace the stored entry
+	Overwrite,
+	/// Replace the stored entry if it was classified with an older model
+	/// version
+	KeepNewestModel,
+}
+
+impl Default for ConflictPolicy {
+	fn default() -> Self {
+		ConflictPolicy::Ignore
+	}
+}
+
+impl FromStr for ConflictPolicy {
+	type Err = String;
+
+	fn from_str(policy: &str) -> Result<Self, Self::Err> {
+		match policy {
+			"ignore" => Ok(ConflictPolicy::Ignore),
+			"overwrite" => Ok(ConflictPolicy::Overwrite),
+			"keep_newest_model" => Ok(ConflictPolicy::KeepNewestModel),
+			_ => Err(format!("Unknown conflict policy: {}", policy)),
+		}
+	}
+}
+
+/// How retweets are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetweetPolicy {
+	/// Retweets are dropped
+	Drop,
+	/// Retweets add weight to the entries of the retweeted tweet instead of
+	/// being stored on their own
+	Weight,
+	/// Retweets are stored as separate entries, flagged as retweets
+	Separate,
+}
+
+impl Default for RetweetPolicy {
+	fn default() -> Self {
+		RetweetPolicy::Separate
+	}
+}
+
+/// Which text of quote tweets is classified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotePolicy {
+	/// Only the commentary of the quote tweet
+	Commentary,
+	/// The commentary together with the quoted text
+	Combined,
+	/// The commentary as entry of the quote tweet and the quoted text as entry
+	/// of the quoted tweet
+	Separate,
+}
+
+impl Default for QuotePolicy {
+	fn default() -> Self {
+		QuotePolicy::Commentary
+	}
+}
+
+/// Default maximum latency of chunks: 2 seconds
+fn default_batch_latency_ms() -> u64 {
+	2000
+}
+
+/// Default for storing tweet texts: enabled
+fn default_store_text() -> bool {
+	true
+}
+
+/// Settings for replaying recorded tweets
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplaySettings {
+	/// Path to the JSONL file with the recorded tweets
+	pub path: String,
+	/// Replay speed factor relative to the original speed. 0 replays without
+	/// any delay.
+	#[serde(default = "default_replay_speed")]
+	pub speed: f64,
+	/// Name of the source the recorded tweets are stored as
+	#[serde(default = "default_replay_source")]
+	pub source: String,
+}
+
+/// Settings for Twitter's v2 API
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwitterV2Settings {
+	/// Base URL of the API, can be changed to use a mock server
+	#[serde(default = "default_twitter_api_url")]
+	pub base_url: String,
+}
+
+/// Default Twitter API base URL
+fn default_twitter_api_url() -> String {
+	"https://api.twitter.com".to_owned()
+}
+
+/// Settings for the on-disk queue of received tweets
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpoolSettings {
+	/// Directory for the spool files, each source gets a sub-directory
+	pub path: String,
+	/// Number of tweets per segment file
+	pub segment_size: usize,
+	/// Milliseconds between syncs of appended tweets to disk, 0 syncs every
+	/// tweet. Tweets appended since the last sync can be lost when the system
+	/// crashes.
+	#[serde(default = "default_spool_sync_ms")]
+	pub sync_ms: u64,
+	/// Number of attempts to process a chunk of spooled tweets. Tweets of a
+	/// chunk that fails every attempt are processed one by one and the ones
+	/// that still fail are moved to the dead-letter file.
+	#[serde(default = "default_spool_max_attempts")]
+	pub max_attempts: u32,
+}
+
+/// Default interval of spool syncs: 100 milliseconds
+fn default_spool_sync_ms() -> u64 {
+	100
+}
+
+/// Default number of attempts to process spooled tweets: 5
+fn default_spool_max_attempts() -> u32 {
+	5
+}
+
+/// Settings for backfilling keywords with search results
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackfillSettings {
+	/// Date (YYYY-MM-DD) to backfill tweets back to
+	#[serde(deserialize_with = "deserialize_date")]
+	pub since: Date,
+	/// Number of tweets per search page, at most 100
+	pub page_size: u32,
+	/// Base URL of the API, can be changed to use a mock server
+	#[serde(default = "default_twitter_api_url")]
+	pub base_url: String,
+}
+
+/// Default replay speed: original speed
+fn default_replay_speed() -> f64 {
+	1.0
+}
+
+/// Default source name of replayed tweets
+fn default_replay_source() -> String {
+	"twitter".to_owned()
+}
+
+/// Mastodon listener settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct MastodonSettings {
+	/// Base URL of the Mastodon instance
+	pub instance_url: String,
+	/// Listen to the hashtag timelines of the keywords instead of the public
+	/// timeline
+	#[serde(default)]
+	pub hashtags: bool,
+}
+
+/// Sentiment classification settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClassifierSettings {
+	/// Models for specific languages. Texts in other languages or without
+	/// known language are classified by the default English model.
+	#[serde(default)]
+	pub models: Vec<ModelSettings>,
+	/// Version of the configured models, stored with the entries. Increase it
+	/// when changing models to replace older entries with the
+	/// `keep_newest_model` conflict policy.
+	#[serde(default = "default_model_version")]
+	pub model_version: i32,
+}
+
+/// Default model version
+fn default_model_version() -> i32 {
+	1
+}
+
+impl Default for ClassifierSettings {
+	fn default() -> Self {
+		ClassifierSettings { models: Vec::new(), model_version: default_model_version() }
+	}
+}
+
+/// Settings of a sentiment model for some languages. The resources are URLs
+/// to download them from or local paths.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelSettings {
+	/// Languages to classify with this model, e.g. `["de", "fr"]` for a
+	/// multilingual model
+	pub languages: Vec<String>,
+	/// Model architecture: `bert`, `distilbert`, `roberta` or `xlmroberta`
+	pub model_type: String,
+	/// Model weights (`rust_model.ot`)
+	pub model: String,
+	/// Model configuration (`config.json`)
+	pub config: String,
+	/// Vocabulary (`vocab.txt`, `vocab.json` or `sentencepiece.bpe.model`)
+	pub vocab: String,
+	/// Merges for BPE tokenizers (`merges.txt`)
+	#[serde(default)]
+	pub merges: Option<String>,
+	/// Whether the tokenizer lowercases the texts
+	#[serde(default)]
+	pub lower_case: bool,
+}
+
+/// Settings for refreshing the rollup tables
+#[derive(Debug, Clone, Deserialize)]
+pub struct RollupSettings {
+	/// Number of seconds between refreshes
+	#[serde(default = "default_rollup_interval_secs")]
+	pub interval_secs: u64,
+	/// Number of seconds before now the rollups are recomputed for on each
+	/// refresh. The rollups of older entries that are added later, e.g. by a
+	/// backfill, are recomputed on the next refresh.
+	#[serde(default = "default_rollup_lookback_secs")]
+	pub lookback_secs: u64,
+}
+
+/// Default rollup refresh interval: a minute
+fn default_rollup_interval_secs() -> u64 {
+	60
+}
+
+/// Default rollup lookback: two days
+fn default_rollup_lookback_secs() -> u64 {
+	2 * 24 * 60 * 60
+}
+
+impl Default for RollupSettings {
+	fn default() -> Self {
+		RollupSettings {
+			interval_secs: default_rollup_interval_secs(),
+			lookback_secs: default_rollup_lookback_secs(),
+		}
+	}
+}
+
+/// Settings for removing old data
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetentionSettings {
+	/// Policy of the keywords without own policy
+	#[serde(default)]
+	pub default: RetentionPolicy,
+	/// Policies per keyword, replacing the default policy. The keywords are
+	/// lowercased like tracked keywords.
+	#[serde(default, deserialize_with = "deserialize_keyword_map")]
+	pub keywords: HashMap<String, RetentionPolicy>,
+	/// Number of seconds between enforcing the policies
+	#[serde(default = "default_retention_interval_secs")]
+	pub interval_secs: u64,
+	/// Only log what would be removed
+	#[serde(default)]
+	pub dry_run: bool,
+}
+
+/// Default retention interval: an hour
+fn default_retention_interval_secs() -> u64 {
+	60 * 60
+}
+
+impl Default for RetentionSettings {
+	fn default() -> Self {
+		RetentionSettings {
+			default: RetentionPolicy::default(),
+			keywords: HashMap::new(),
+			interval_secs: default_retention_interval_secs(),
+			dry_run: false,
+		}
+	}
+}
+
+impl RetentionSettings {
+	/// Policy of a keyword, ignoring case
+	pub fn policy(&self, keyword: &str) -> &RetentionPolicy {
+		self.keywords.get(&keyword.to_lowercase()).unwrap_or(&self.default)
+	}
+}
+
+/// How long data of a keyword is kept, e.g. `90d`. Data is kept forever if
+/// not set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetentionPolicy {
+	/// Sentiment entries of the tweets
+	#[serde(default, deserialize_with = "deserialize_duration")]
+	pub raw: Option<Duration>,
+	/// Rollups per minute
+	#[serde(default, deserialize_with = "deserialize_duration")]
+	pub minute: Option<Duration>,
+	/// Rollups per hour
+	#[serde(default, deserialize_with = "deserialize_duration")]
+	pub hour: Option<Duration>,
+	/// Rollups per day
+	#[serde(default, deserialize_with = "deserialize_duration")]
+	pub day: Option<Duration>,
+}
+
+impl RetentionPolicy {
+	/// Whether anything is removed
+	pub fn is_active(&self) -> bool {
+		self.raw.is_some() || self.minute.is_some() || self.hour.is_some() || self.day.is_some()
+	}
 }
 
 /// Defaults for webserver
@@ -51,6 +516,47 @@ where
 	Ok(log_level)
 }
 
+/// Deserialize an optional duration with unit, e.g. `90d`
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let text = String::deserialize(deserializer)?;
+	let duration = crate::data::parse_duration(&text)
+		.ok_or_else(|| D::Error::custom(format!("Invalid duration: {}", text)))?;
+	Ok(Some(duration))
+}
+
+/// Deserialize a map by keyword with lowercased keywords
+fn deserialize_keyword_map<'de, D, V>(deserializer: D) -> Result<HashMap<String, V>, D::Error>
+where
+	D: Deserializer<'de>,
+	V: Deserialize<'de>,
+{
+	let mut map = HashMap::new();
+	for (keyword, value) in HashMap::<String, V>::deserialize(deserializer)? {
+		let keyword = keyword.to_lowercase();
+		if map.contains_key(&keyword) {
+			return Err(D::Error::custom(format!("Duplicate keyword: {}", keyword)));
+		}
+		map.insert(keyword, value);
+	}
+	Ok(map)
+}
+
+/// Deserialize a Date in the format YYYY-MM-DD
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Date, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let date = Date::parse(
+		&String::deserialize(deserializer)?,
+		format_description!("[year]-[month]-[day]"),
+	)
+	.map_err(|err| D::Error::custom(err.to_string()))?;
+	Ok(date)
+}
+
 impl Settings {
 	/// Read configuration from `config.yaml` by default. Calls `read_from`.
 	#[inline]
@@ -70,3 +576,27 @@ impl Settings {
 		config.try_into()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn retention_policies_ignore_keyword_case() {
+		let settings: RetentionSettings = serde_json::from_value(json!({
+			"default": { "raw": "90d" },
+			"keywords": { "Rust": { "raw": "365d" } },
+		}))
+		.expect("Invalid retention settings");
+		assert_eq!(settings.keywords.keys().collect::<Vec<_>>(), vec!["rust"]);
+		for keyword in ["rust", "Rust", "RUST"] {
+			assert_eq!(settings.policy(keyword).raw, Some(Duration::days(365)));
+		}
+		assert_eq!(settings.policy("golang").raw, Some(Duration::days(90)));
+
+		let duplicate = json!({ "keywords": { "Rust": {}, "rust": {} } });
+		assert!(serde_json::from_value::<RetentionSettings>(duplicate).is_err());
+	}
+}
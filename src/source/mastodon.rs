@@ -0,0 +1,193 @@
+//! Source of statuses from a Mastodon instance's streaming API
+
+use color_eyre::{eyre::eyre, Result};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use reqwest::Client;
+use serde::Deserialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::info;
+
+use super::{response_lines, SourceEvent, SourceTweet, TweetMeta, TweetSource, TweetStream};
+use crate::{matcher::KeywordMatcher, settings::MastodonSettings};
+
+/// Source receiving statuses from a Mastodon instance's streaming API, either
+/// from the public timeline or from the hashtag timelines of the keywords.
+#[derive(Debug, Clone)]
+pub struct MastodonSource {
+	client: Client,
+	instance_url: String,
+	hashtags: bool,
+	access_token: Option<String>,
+}
+
+/// Status in the streaming API
+#[derive(Debug, Deserialize)]
+struct Status {
+	id: String,
+	created_at: String,
+	content: String,
+	language: Option<String>,
+	reblog: Option<serde_json::Value>,
+	account: Account,
+	reblogs_count: Option<i32>,
+	favourites_count: Option<i32>,
+	in_reply_to_id: Option<String>,
+	application: Option<Application>,
+}
+
+/// Account of a status
+#[derive(Debug, Deserialize)]
+struct Account {
+	id: String,
+	followers_count: Option<i32>,
+}
+
+/// Application a status was sent with
+#[derive(Debug, Deserialize)]
+struct Application {
+	name: String,
+}
+
+impl TryFrom<Status> for SourceTweet {
+	type Error = color_eyre::Report;
+
+	fn try_from(status: Status) -> Result<Self> {
+		let meta = TweetMeta {
+			author_id: status.account.id.parse().ok(),
+			author_followers: status.account.followers_count,
+			retweet_count: status.reblogs_count,
+			like_count: status.favourites_count,
+			reply_to: status.in_reply_to_id.as_deref().and_then(|id| id.parse().ok()),
+			quote_of: None,
+			retweet_of: None,
+			quoted_text: None,
+			client: status.application.map(|application| application.name),
+		};
+		Ok(SourceTweet {
+			id: status.id.parse()?,
+			created_at: OffsetDateTime::parse(&status.created_at, &Rfc3339)?.unix_timestamp(),
+			text: strip_html(&status.content),
+			lang: status.language,
+			keywords: None,
+			meta,
+		})
+	}
+}
+
+impl MastodonSource {
+	/// Create a new Mastodon source, optionally using an access token
+	pub fn new(settings: &MastodonSettings, access_token: Option<String>) -> Self {
+		MastodonSource {
+			client: Client::new(),
+			instance_url: settings.instance_url.trim_end_matches('/').to_owned(),
+			hashtags: settings.hashtags,
+			access_token,
+		}
+	}
+
+	/// Connect to the public timeline or to the timeline of a hashtag, given
+	/// with the keyword its statuses are attributed to.
+	async fn connect(self, hashtag: Option<(String, String)>) -> Result<TweetStream> {
+		let timeline = if hashtag.is_some() { "hashtag" } else { "public" };
+		let mut request = self
+			.client
+			.get(format!("{}/api/v1/streaming/{}", self.instance_url, timeline))
+			.header("accept", "text/event-stream");
+		if let Some((tag, _)) = &hashtag {
+			request = request.query(&[("tag", tag.trim_start_matches(&['#', '$'][..]))]);
+		}
+		if let Some(token) = &self.access_token {
+			request = request.bearer_auth(token);
+		}
+		let response = request.send().await?.error_for_status()?;
+		let keyword = hashtag.map(|(_, keyword)| keyword);
+
+		// Server-sent events: `event: <name>` followed by `data: <payload>`
+		let mut event = String::new();
+		let events = response_lines(response).try_filter_map(move |line| {
+			let parsed = if let Some(name) = line.strip_prefix("event:") {
+				event = name.trim().to_owned();
+				Ok(None)
+			} else if let Some(data) = line.strip_prefix("data:") {
+				match event.as_str() {
+					"update" => parse_status(data, keyword.clone()),
+					"delete" => parse_delete(data),
+					_ => Ok(None),
+				}
+			} else {
+				Ok(None)
+			};
+			future::ready(parsed)
+		});
+		Ok(events.boxed())
+	}
+}
+
+/// Parse a status from the streaming API. Boosts are skipped, as the original
+/// status is already part of the timeline.
+fn parse_status(data: &str, keyword: Option<String>) -> Result<Option<SourceEvent>> {
+	let status: Status = serde_json::from_str(data.trim())
+		.map_err(|err| eyre!("Invalid status `{}`: {}", data, err))?;
+	if status.reblog.is_some() {
+		return Ok(None);
+	}
+	let mut tweet = SourceTweet::try_from(status)?;
+	tweet.keywords = keyword.map(|keyword| vec![keyword]);
+	Ok(Some(SourceEvent::Tweet(tweet)))
+}
+
+/// Parse a deletion from the streaming API, its payload is the status ID.
+fn parse_delete(data: &str) -> Result<Option<SourceEvent>> {
+	let id = data
+		.trim()
+		.trim_matches('"')
+		.parse()
+		.map_err(|err| eyre!("Invalid deleted status ID `{}`: {}", data, err))?;
+	Ok(Some(SourceEvent::Delete { id, user_id: None }))
+}
+
+impl TweetSource for MastodonSource {
+	fn name(&self) -> &str {
+		"mastodon"
+	}
+
+	fn stream(&self, matcher: &KeywordMatcher) -> TweetStream {
+		info!("Connecting to Mastodon streaming API at {}.", self.instance_url);
+		if self.hashtags {
+			let hashtags = matcher.rules().iter().flat_map(|rule| {
+				rule.terms().iter().map(|term| (term.clone(), rule.keyword().to_owned()))
+			});
+			let timelines = hashtags.map(|(hashtag, keyword)| {
+				stream::once(self.clone().connect(Some((hashtag, keyword)))).try_flatten().boxed()
+			});
+			stream::select_all(timelines).boxed()
+		} else {
+			stream::once(self.clone().connect(None)).try_flatten().boxed()
+		}
+	}
+}
+
+/// Convert the HTML content of a status to plain text.
+fn strip_html(html: &str) -> String {
+	let mut text = String::with_capacity(html.len());
+	let mut rest = html;
+	while let Some(start) = rest.find('<') {
+		text.push_str(&rest[..start]);
+		let end = rest[start..].find('>').map_or(rest.len(), |end| start + end + 1);
+		let tag = rest[start..end].to_lowercase();
+		if tag.starts_with("<br") || tag.starts_with("</p") {
+			text.push('\n');
+		}
+		rest = &rest[end..];
+	}
+	text.push_str(rest);
+
+	text.trim()
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&apos;", "'")
+		.replace("&nbsp;", " ")
+		.replace("&amp;", "&")
+}
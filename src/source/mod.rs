@@ -0,0 +1,167 @@
+//! Sources of tweets to feed into the sentiment pipeline
+
+mod mastodon;
+mod replay;
+mod twitter;
+mod twitter_v2;
+
+use std::{fmt::Debug, io};
+
+use color_eyre::Result;
+use egg_mode::tweet::Tweet;
+use futures::{
+	stream::{self, BoxStream},
+	StreamExt, TryStreamExt,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::io::StreamReader;
+
+pub use self::{
+	mastodon::MastodonSource, replay::ReplaySource, twitter::TwitterFilterSource,
+	twitter_v2::TwitterV2Source,
+};
+use crate::matcher::KeywordMatcher;
+
+/// A tweet as received from any source, independent of the source's API types.
+/// Also the format of the lines in recorded JSONL files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceTweet {
+	/// Tweet ID
+	pub id: u64,
+	/// Creation time as UNIX timestamp in seconds
+	pub created_at: i64,
+	/// Text content of the tweet. For retweets, the text of the retweeted
+	/// tweet. For quote tweets, only the commentary.
+	pub text: String,
+	/// Language of the tweet, if known
+	#[serde(default)]
+	pub lang: Option<String>,
+	/// Keywords the source attributed the tweet to, e.g. by tagged stream
+	/// rules. The keyword rules are only applied to the text if not set.
+	#[serde(default)]
+	pub keywords: Option<Vec<String>>,
+	/// Further metadata of the tweet, as far as provided by the source
+	#[serde(default)]
+	pub meta: TweetMeta,
+}
+
+/// Metadata of a tweet
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TweetMeta {
+	/// ID of the author
+	pub author_id: Option<u64>,
+	/// Number of followers of the author
+	pub author_followers: Option<i32>,
+	/// Number of retweets (or boosts)
+	pub retweet_count: Option<i32>,
+	/// Number of likes (or favourites)
+	pub like_count: Option<i32>,
+	/// ID of the tweet this tweet replies to
+	pub reply_to: Option<u64>,
+	/// ID of the tweet this tweet quotes
+	pub quote_of: Option<u64>,
+	/// ID of the tweet this tweet is a retweet of
+	pub retweet_of: Option<u64>,
+	/// Text of the quoted tweet
+	pub quoted_text: Option<String>,
+	/// Name of the client app the tweet was sent with
+	pub client: Option<String>,
+}
+
+impl From<Tweet> for SourceTweet {
+	fn from(tweet: Tweet) -> Self {
+		let meta = TweetMeta {
+			author_id: tweet.user.as_ref().map(|user| user.id),
+			author_followers: tweet.user.as_ref().map(|user| user.followers_count),
+			retweet_count: Some(tweet.retweet_count),
+			like_count: Some(tweet.favorite_count),
+			reply_to: tweet.in_reply_to_status_id,
+			quote_of: tweet.quoted_status_id,
+			retweet_of: tweet.retweeted_status.as_ref().map(|retweeted| retweeted.id),
+			quoted_text: tweet.quoted_status.as_ref().map(|quoted| quoted.text.clone()),
+			client: tweet.source.map(|source| source.name),
+		};
+		// The text of retweets is truncated and prefixed with `RT @user:`
+		let text = match tweet.retweeted_status {
+			Some(retweeted) => retweeted.text,
+			None => tweet.text,
+		};
+		SourceTweet {
+			id: tweet.id,
+			created_at: tweet.created_at.timestamp(),
+			text,
+			lang: tweet.lang,
+			keywords: None,
+			meta,
+		}
+	}
+}
+
+/// Event received from a source: a new tweet or a compliance message that
+/// requires removing or anonymising stored data. Also the format of the
+/// records in the spool.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SourceEvent {
+	/// A new tweet
+	Tweet(SourceTweet),
+	/// The tweet was deleted, its data has to be removed
+	Delete {
+		/// ID of the deleted tweet
+		id: u64,
+		/// ID of the author, if provided
+		user_id: Option<u64>,
+	},
+	/// Location data of the user's tweets up to the given tweet has to be
+	/// removed
+	ScrubGeo {
+		/// ID of the user
+		user_id: u64,
+		/// ID of the newest affected tweet
+		up_to_id: u64,
+	},
+	/// The tweet, or all tweets of the user if no tweet is given, is withheld
+	/// in some countries and has to be anonymised
+	Withheld {
+		/// ID of the withheld tweet, `None` if the user is withheld
+		id: Option<u64>,
+		/// ID of the user
+		user_id: u64,
+		/// Country codes the content is withheld in
+		countries: Vec<String>,
+	},
+}
+
+impl From<SourceTweet> for SourceEvent {
+	fn from(tweet: SourceTweet) -> Self {
+		SourceEvent::Tweet(tweet)
+	}
+}
+
+/// Stream of events produced by a `TweetSource`
+pub type TweetStream = BoxStream<'static, Result<SourceEvent>>;
+
+/// A source of tweets, consumed by the `TwitterStreamRunner`
+pub trait TweetSource: Debug + Send + Sync {
+	/// Name of the source, stored with the entries to tell sources apart
+	fn name(&self) -> &str;
+
+	/// Start receiving tweets for the keywords of the matcher, along with the
+	/// compliance messages of the source. The stream ending or returning an
+	/// error makes the runner reconnect by calling this again.
+	fn stream(&self, matcher: &KeywordMatcher) -> TweetStream;
+}
+
+/// Stream of the lines of a HTTP response body
+fn response_lines(response: reqwest::Response) -> BoxStream<'static, Result<String>> {
+	let body =
+		response.bytes_stream().map_err(|err| io::Error::new(io::ErrorKind::Other, err)).boxed();
+	let lines = StreamReader::new(body).lines();
+	stream::try_unfold(lines, |mut lines| async move {
+		let line = lines.next_line().await?;
+		Ok(line.map(|line| (line, lines)))
+	})
+	.boxed()
+}
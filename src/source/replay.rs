@@ -0,0 +1,93 @@
+//! Tweet source replaying recorded tweets from a JSONL file
+
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use futures::{stream, StreamExt};
+use tokio::{
+	fs::File,
+	io::{AsyncBufReadExt, BufReader, Lines},
+	time::{self, Duration, Instant},
+};
+use tracing::info;
+
+use super::{SourceEvent, SourceTweet, TweetSource, TweetStream};
+use crate::{matcher::KeywordMatcher, settings::ReplaySettings};
+
+/// Tweet source replaying recorded tweets from a JSONL file, one `SourceTweet`
+/// per line, in original or accelerated speed.
+#[derive(Debug, Clone)]
+pub struct ReplaySource {
+	path: PathBuf,
+	speed: f64,
+	source: String,
+}
+
+/// State of the replay stream
+struct ReplayState {
+	lines: Option<Lines<BufReader<File>>>,
+	/// Wall clock time and tweet timestamp of the first tweet
+	start: Option<(Instant, i64)>,
+}
+
+impl ReplaySource {
+	/// Create a new replay source from the settings
+	pub fn new(settings: &ReplaySettings) -> Self {
+		ReplaySource {
+			path: settings.path.clone().into(),
+			speed: settings.speed,
+			source: settings.source.clone(),
+		}
+	}
+
+	/// Read the next tweet from the file and wait until it is due. Returns
+	/// `None` when the file is finished.
+	async fn next_tweet(&self, state: &mut ReplayState) -> Result<Option<SourceTweet>> {
+		let lines = match state.lines.as_mut() {
+			Some(lines) => lines,
+			None => state.lines.insert(BufReader::new(File::open(&self.path).await?).lines()),
+		};
+
+		let tweet: SourceTweet = loop {
+			match lines.next_line().await? {
+				Some(line) if line.trim().is_empty() => continue,
+				Some(line) => break serde_json::from_str(&line)?,
+				None => return Ok(None),
+			}
+		};
+
+		if self.speed > 0.0 {
+			let (start, first_created) =
+				*state.start.get_or_insert((Instant::now(), tweet.created_at));
+			let offset = (tweet.created_at - first_created).max(0) as f64 / self.speed;
+			time::sleep_until(start + Duration::from_secs_f64(offset)).await;
+		}
+		Ok(Some(tweet))
+	}
+}
+
+impl TweetSource for ReplaySource {
+	fn name(&self) -> &str {
+		&self.source
+	}
+
+	fn stream(&self, _matcher: &KeywordMatcher) -> TweetStream {
+		info!("Replaying tweets from {}.", self.path.display());
+		let source = self.clone();
+		let state = ReplayState { lines: None, start: None };
+		stream::try_unfold(state, move |mut state| {
+			let source = source.clone();
+			async move {
+				let tweet = source.next_tweet(&mut state).await?;
+				if tweet.is_none() {
+					info!("Replay of {} finished.", source.path.display());
+				}
+				Ok(tweet.map(|tweet| (SourceEvent::Tweet(tweet), state)))
+			}
+		})
+		// Keep the stream open after the replay, so the runner does not replay
+		// the same tweets again.
+		.chain(stream::pending())
+		.boxed()
+	}
+}
@@ -0,0 +1,68 @@
+//! Tweet source using Twitter's v1.1 filter stream
+
+use egg_mode::{stream::StreamMessage, Token};
+use futures::{future, StreamExt, TryStreamExt};
+use tracing::info;
+
+use super::{SourceEvent, TweetSource, TweetStream};
+use crate::matcher::KeywordMatcher;
+
+/// Tweet source receiving tweets from Twitter's filter stream via egg-mode
+#[derive(Debug, Clone)]
+pub struct TwitterFilterSource {
+	token: Token,
+}
+
+impl TwitterFilterSource {
+	/// Create a new filter stream source using the given token
+	pub fn new(token: Token) -> Self {
+		TwitterFilterSource { token }
+	}
+}
+
+impl TweetSource for TwitterFilterSource {
+	fn name(&self) -> &str {
+		"twitter"
+	}
+
+	fn stream(&self, matcher: &KeywordMatcher) -> TweetStream {
+		info!("Connecting to Twitter filter stream.");
+		let languages = matcher.languages();
+		let languages: Vec<&str> = languages.iter().map(String::as_str).collect();
+		let mut filter = egg_mode::stream::filter().track(matcher.track_terms());
+		if !languages.is_empty() {
+			filter = filter.language(&languages);
+		}
+		filter
+			.start(&self.token)
+			.try_filter_map(|msg| {
+				let event = match msg {
+					StreamMessage::Tweet(tweet) => Some(SourceEvent::Tweet(tweet.into())),
+					StreamMessage::Delete { status_id, user_id } => {
+						Some(SourceEvent::Delete { id: status_id, user_id: Some(user_id) })
+					}
+					StreamMessage::ScrubGeo { user_id, up_to_status_id } => {
+						Some(SourceEvent::ScrubGeo { user_id, up_to_id: up_to_status_id })
+					}
+					StreamMessage::StatusWithheld { status_id, user_id, withheld_in_countries } => {
+						Some(SourceEvent::Withheld {
+							id: Some(status_id),
+							user_id,
+							countries: withheld_in_countries,
+						})
+					}
+					StreamMessage::UserWithheld { user_id, withheld_in_countries } => {
+						Some(SourceEvent::Withheld {
+							id: None,
+							user_id,
+							countries: withheld_in_countries,
+						})
+					}
+					_ => None,
+				};
+				future::ready(Ok(event))
+			})
+			.map_err(color_eyre::Report::from)
+			.boxed()
+	}
+}
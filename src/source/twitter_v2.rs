@@ -0,0 +1,434 @@
+//! Tweet source using Twitter's v2 filtered stream with rule management
+
+use std::collections::HashSet;
+
+use color_eyre::{eyre::eyre, Result};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{debug, info};
+
+use super::{response_lines, SourceEvent, SourceTweet, TweetMeta, TweetSource, TweetStream};
+use crate::{
+	matcher::{CompiledRule, KeywordMatcher},
+	settings::TwitterV2Settings,
+};
+
+/// Tweet source receiving tweets from Twitter's v2 filtered stream. The stream
+/// rules are synced with the keyword rules on every connect, each rule is
+/// tagged with its keyword to attribute the tweets to keywords.
+#[derive(Debug, Clone)]
+pub struct TwitterV2Source {
+	client: Client,
+	base_url: String,
+	bearer_token: String,
+}
+
+/// A filtered stream rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Rule {
+	#[serde(default, skip_serializing)]
+	id: String,
+	value: String,
+	tag: Option<String>,
+}
+
+/// Response of the rules endpoint
+#[derive(Debug, Deserialize)]
+struct RulesResponse {
+	#[serde(default)]
+	data: Vec<Rule>,
+}
+
+/// Request to the rules endpoint, adding or deleting rules
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum RulesRequest {
+	Add(Vec<Rule>),
+	Delete { ids: Vec<String> },
+}
+
+/// Message in the filtered stream
+#[derive(Debug, Deserialize)]
+struct StreamData {
+	data: TweetData,
+	#[serde(default)]
+	includes: Includes,
+	#[serde(default)]
+	matching_rules: Vec<MatchingRule>,
+}
+
+/// Tweet in the filtered stream
+#[derive(Debug, Deserialize)]
+struct TweetData {
+	id: String,
+	text: String,
+	created_at: String,
+	lang: Option<String>,
+	author_id: Option<String>,
+	source: Option<String>,
+	public_metrics: Option<PublicMetrics>,
+	#[serde(default)]
+	referenced_tweets: Vec<ReferencedTweet>,
+}
+
+/// Metrics of a tweet or user
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PublicMetrics {
+	retweet_count: Option<i32>,
+	like_count: Option<i32>,
+	followers_count: Option<i32>,
+}
+
+/// Tweet referenced by a tweet, e.g. the quoted one
+#[derive(Debug, Deserialize)]
+struct ReferencedTweet {
+	#[serde(rename = "type")]
+	kind: String,
+	id: String,
+}
+
+/// Expanded objects of a stream message
+#[derive(Debug, Default, Deserialize)]
+struct Includes {
+	#[serde(default)]
+	users: Vec<User>,
+	#[serde(default)]
+	tweets: Vec<IncludedTweet>,
+}
+
+/// Expanded referenced tweet
+#[derive(Debug, Deserialize)]
+struct IncludedTweet {
+	id: String,
+	text: String,
+}
+
+/// Expanded user
+#[derive(Debug, Deserialize)]
+struct User {
+	id: String,
+	public_metrics: Option<PublicMetrics>,
+}
+
+/// Rule that matched a tweet in the filtered stream
+#[derive(Debug, Deserialize)]
+struct MatchingRule {
+	tag: Option<String>,
+}
+
+impl TryFrom<StreamData> for SourceTweet {
+	type Error = color_eyre::Report;
+
+	fn try_from(msg: StreamData) -> Result<Self> {
+		let keywords = msg.matching_rules.into_iter().filter_map(|rule| rule.tag).collect();
+		let data = msg.data;
+		let referenced_id = |kind: &str| {
+			data.referenced_tweets
+				.iter()
+				.find(|referenced| referenced.kind == kind)
+				.map(|referenced| referenced.id.as_str())
+		};
+		let referenced = |kind: &str| referenced_id(kind).and_then(|id| id.parse().ok());
+		let referenced_text = |kind: &str| {
+			let id = referenced_id(kind)?;
+			msg.includes.tweets.iter().find(|tweet| tweet.id == id).map(|tweet| tweet.text.clone())
+		};
+		let author_followers = msg
+			.includes
+			.users
+			.iter()
+			.find(|user| Some(&user.id) == data.author_id.as_ref())
+			.and_then(|user| user.public_metrics.as_ref())
+			.and_then(|metrics| metrics.followers_count);
+		let meta = TweetMeta {
+			author_id: data.author_id.as_deref().and_then(|id| id.parse().ok()),
+			author_followers,
+			retweet_count: data.public_metrics.as_ref().and_then(|metrics| metrics.retweet_count),
+			like_count: data.public_metrics.as_ref().and_then(|metrics| metrics.like_count),
+			reply_to: referenced("replied_to"),
+			quote_of: referenced("quoted"),
+			retweet_of: referenced("retweeted"),
+			quoted_text: referenced_text("quoted"),
+			client: data.source.clone(),
+		};
+		// The text of retweets is truncated and prefixed with `RT @user:`
+		let text = referenced_text("retweeted").unwrap_or(data.text);
+		Ok(SourceTweet {
+			id: data.id.parse()?,
+			created_at: OffsetDateTime::parse(&data.created_at, &Rfc3339)?.unix_timestamp(),
+			text,
+			lang: data.lang,
+			keywords: Some(keywords),
+			meta,
+		})
+	}
+}
+
+impl TwitterV2Source {
+	/// Create a new v2 filtered stream source using the given app bearer token
+	pub fn new(settings: &TwitterV2Settings, bearer_token: String) -> Self {
+		TwitterV2Source {
+			client: Client::new(),
+			base_url: settings.base_url.trim_end_matches('/').to_owned(),
+			bearer_token,
+		}
+	}
+
+	/// URL of the rules endpoint
+	fn rules_url(&self) -> String {
+		format!("{}/2/tweets/search/stream/rules", self.base_url)
+	}
+
+	/// Stream rule for a keyword rule
+	fn rule_for(rule: &CompiledRule) -> Rule {
+		Rule { id: String::new(), value: rule.search_query(), tag: Some(rule.keyword().to_owned()) }
+	}
+
+	/// Sync the stream rules with the keyword rules: delete rules that are not
+	/// wanted anymore and add the missing ones.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	async fn sync_rules(&self, matcher: &KeywordMatcher) -> Result<()> {
+		let existing: RulesResponse = self
+			.client
+			.get(self.rules_url())
+			.bearer_auth(&self.bearer_token)
+			.send()
+			.await?
+			.error_for_status()?
+			.json()
+			.await?;
+
+		let wanted: Vec<Rule> = matcher.rules().iter().map(Self::rule_for).collect();
+		let wanted_keys: HashSet<_> = wanted.iter().map(|rule| (&rule.value, &rule.tag)).collect();
+		let existing_keys: HashSet<_> =
+			existing.data.iter().map(|rule| (&rule.value, &rule.tag)).collect();
+
+		let delete: Vec<String> = existing
+			.data
+			.iter()
+			.filter(|rule| !wanted_keys.contains(&(&rule.value, &rule.tag)))
+			.map(|rule| rule.id.clone())
+			.collect();
+		let add: Vec<Rule> = wanted
+			.iter()
+			.filter(|rule| !existing_keys.contains(&(&rule.value, &rule.tag)))
+			.cloned()
+			.collect();
+
+		if !delete.is_empty() {
+			debug!("Deleting {} stream rules.", delete.len());
+			self.post_rules(&RulesRequest::Delete { ids: delete }).await?;
+		}
+		if !add.is_empty() {
+			debug!("Adding {} stream rules.", add.len());
+			self.post_rules(&RulesRequest::Add(add)).await?;
+		}
+		Ok(())
+	}
+
+	/// Send a request to add or delete rules
+	async fn post_rules(&self, request: &RulesRequest) -> Result<()> {
+		self.client
+			.post(self.rules_url())
+			.bearer_auth(&self.bearer_token)
+			.json(request)
+			.send()
+			.await?
+			.error_for_status()?;
+		Ok(())
+	}
+
+	/// Sync the rules and connect to the filtered stream
+	async fn connect(self, matcher: KeywordMatcher) -> Result<TweetStream> {
+		self.sync_rules(&matcher).await?;
+
+		let response = self
+			.client
+			.get(format!("{}/2/tweets/search/stream", self.base_url))
+			.query(&[
+				(
+					"tweet.fields",
+					"created_at,lang,author_id,source,public_metrics,referenced_tweets",
+				),
+				("expansions", "author_id,referenced_tweets.id"),
+				("user.fields", "public_metrics"),
+			])
+			.bearer_auth(&self.bearer_token)
+			.send()
+			.await?
+			.error_for_status()?;
+
+		let tweets = response_lines(response)
+			// Empty lines are keep-alive signals
+			.try_filter(|line| future::ready(!line.trim().is_empty()))
+			.and_then(|line| async move {
+				let msg: StreamData = serde_json::from_str(&line)
+					.map_err(|err| eyre!("Invalid stream message `{}`: {}", line, err))?;
+				SourceTweet::try_from(msg).map(SourceEvent::Tweet)
+			});
+		Ok(tweets.boxed())
+	}
+}
+
+impl TweetSource for TwitterV2Source {
+	fn name(&self) -> &str {
+		"twitter"
+	}
+
+	fn stream(&self, matcher: &KeywordMatcher) -> TweetStream {
+		info!("Connecting to Twitter v2 filtered stream.");
+		stream::once(self.clone().connect(matcher.clone())).try_flatten().boxed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::{json, Value};
+	use wiremock::{
+		matchers::{body_json, header, method, path},
+		Mock, MockServer, ResponseTemplate,
+	};
+
+	use super::*;
+	use crate::settings::KeywordRule;
+
+	const RULES_PATH: &str = "/2/tweets/search/stream/rules";
+	const STREAM_PATH: &str = "/2/tweets/search/stream";
+
+	fn matcher(keywords: &[&str]) -> Result<KeywordMatcher> {
+		let rules: Vec<_> =
+			keywords.iter().map(|keyword| KeywordRule::new((*keyword).to_owned())).collect();
+		KeywordMatcher::new(&rules)
+	}
+
+	fn source(server: &MockServer) -> TwitterV2Source {
+		let settings = TwitterV2Settings { base_url: server.uri() };
+		TwitterV2Source::new(&settings, "token".to_owned())
+	}
+
+	async fn mock_rules(server: &MockServer, existing: Value) {
+		Mock::given(method("GET"))
+			.and(path(RULES_PATH))
+			.and(header("authorization", "Bearer token"))
+			.respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": existing })))
+			.mount(server)
+			.await;
+	}
+
+	#[tokio::test]
+	async fn syncs_rules_with_keywords() -> Result<()> {
+		let server = MockServer::start().await;
+		let matcher = matcher(&["rust", "golang"])?;
+		let queries: Vec<_> = matcher.rules().iter().map(CompiledRule::search_query).collect();
+		mock_rules(
+			&server,
+			json!([
+				{ "id": "1", "value": queries[0], "tag": "rust" },
+				{ "id": "2", "value": queries[1], "tag": "go" },
+				{ "id": "3", "value": "java", "tag": "java" },
+			]),
+		)
+		.await;
+		// Rules are matched by value and tag, changed ones are replaced
+		Mock::given(method("POST"))
+			.and(path(RULES_PATH))
+			.and(body_json(json!({ "delete": { "ids": ["2", "3"] } })))
+			.respond_with(ResponseTemplate::new(200))
+			.expect(1)
+			.mount(&server)
+			.await;
+		Mock::given(method("POST"))
+			.and(path(RULES_PATH))
+			.and(body_json(json!({ "add": [{ "value": queries[1], "tag": "golang" }] })))
+			.respond_with(ResponseTemplate::new(200))
+			.expect(1)
+			.mount(&server)
+			.await;
+
+		source(&server).sync_rules(&matcher).await?;
+		server.verify().await;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn keeps_rules_in_sync() -> Result<()> {
+		let server = MockServer::start().await;
+		let matcher = matcher(&["rust"])?;
+		let query = matcher.rules()[0].search_query();
+		mock_rules(&server, json!([{ "id": "1", "value": query, "tag": "rust" }])).await;
+		Mock::given(method("POST"))
+			.respond_with(ResponseTemplate::new(200))
+			.expect(0)
+			.mount(&server)
+			.await;
+
+		source(&server).sync_rules(&matcher).await?;
+		server.verify().await;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn attributes_tweets_by_rule_tags() -> Result<()> {
+		let server = MockServer::start().await;
+		let matcher = matcher(&["rust", "golang"])?;
+		let queries: Vec<_> = matcher.rules().iter().map(CompiledRule::search_query).collect();
+		mock_rules(
+			&server,
+			json!([
+				{ "id": "1", "value": queries[0], "tag": "rust" },
+				{ "id": "2", "value": queries[1], "tag": "golang" },
+			]),
+		)
+		.await;
+		let messages = [
+			json!({
+				"data": {
+					"id": "10",
+					"text": "Trying out a new language",
+					"created_at": "2022-10-17T12:00:00.000Z",
+					"lang": "en",
+					"author_id": "100",
+				},
+				"includes": { "users": [{ "id": "100", "public_metrics": { "followers_count": 42 } }] },
+				"matching_rules": [{ "id": "1", "tag": "rust" }, { "id": "2", "tag": "golang" }],
+			}),
+			json!({
+				"data": {
+					"id": "11",
+					"text": "RT @someone: Rust is...",
+					"created_at": "2022-10-17T12:00:01.000Z",
+					"referenced_tweets": [{ "type": "retweeted", "id": "9" }],
+				},
+				"includes": { "tweets": [{ "id": "9", "text": "Rust is good" }] },
+				"matching_rules": [{ "id": "1", "tag": "rust" }],
+			}),
+		];
+		// Empty lines are keep-alive signals
+		let body = format!("{}\r\n\r\n{}\r\n", messages[0], messages[1]);
+		Mock::given(method("GET"))
+			.and(path(STREAM_PATH))
+			.respond_with(ResponseTemplate::new(200).set_body_string(body))
+			.mount(&server)
+			.await;
+
+		let events: Vec<_> = source(&server).stream(&matcher).try_collect().await?;
+		let tweets: Vec<_> = events
+			.into_iter()
+			.filter_map(|event| match event {
+				SourceEvent::Tweet(tweet) => Some(tweet),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(tweets.len(), 2);
+		assert_eq!(tweets[0].keywords, Some(vec!["rust".to_owned(), "golang".to_owned()]));
+		assert_eq!(tweets[0].created_at, 1_666_008_000);
+		assert_eq!(tweets[0].meta.author_followers, Some(42));
+		assert_eq!(tweets[1].keywords, Some(vec!["rust".to_owned()]));
+		assert_eq!(tweets[1].text, "Rust is good");
+		assert_eq!(tweets[1].meta.retweet_of, Some(9));
+		Ok(())
+	}
+}
@@ -0,0 +1,430 @@
+//! Durable on-disk queue between receiving and processing tweets
+
+use std::{
+	io::SeekFrom,
+	path::{Path, PathBuf},
+	sync::{Arc, Weak},
+};
+
+use color_eyre::Result;
+use futures::{
+	stream::{self, BoxStream},
+	StreamExt,
+};
+use tokio::{
+	fs::{self, File, OpenOptions},
+	io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+	sync::{Mutex, Notify},
+	time::{self, Duration, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+
+use crate::{settings::SpoolSettings, source::SourceEvent};
+
+/// Name of the file storing the acknowledged position
+const ACK_FILE: &str = "ack";
+/// Name of the file storing the events that failed processing
+const DEAD_LETTER_FILE: &str = "dead_letter.jsonl";
+/// Time to wait for new records before checking the files again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Append-only on-disk queue of source events in segment files with one JSON
+/// record per line. Events are appended as they arrive and read by the
+/// processing stage, which acknowledges the position up to which the events
+/// have been processed. Unacknowledged events are read again after a restart
+/// and segments before the acknowledged position are deleted.
+///
+/// Appended events are handed to the operating system right away, so they
+/// survive a crash of the process. They are synced to disk on every append or
+/// every sync interval, so a crash of the system loses at most the events
+/// appended in the last interval. A record torn by a crash while writing is
+/// dropped.
+///
+/// Events that keep failing processing are moved to a dead-letter file in the
+/// same format as the segments, so they do not block the events after them.
+#[derive(Debug)]
+pub struct Spool {
+	dir: PathBuf,
+	segment_size: usize,
+	/// Sync every append if zero
+	sync_interval: Duration,
+	max_attempts: u32,
+	writer: Mutex<SegmentWriter>,
+	notify: Notify,
+	acked: Mutex<SpoolPosition>,
+}
+
+/// Writer of the current segment
+#[derive(Debug)]
+struct SegmentWriter {
+	segment: u64,
+	records: usize,
+	/// Whether records were appended since the last sync
+	unsynced: bool,
+	file: File,
+}
+
+/// Position in the spool: segment number and byte offset in the segment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpoolPosition {
+	segment: u64,
+	offset: u64,
+}
+
+/// State of a spool reader
+struct ReadState {
+	spool: Arc<Spool>,
+	position: SpoolPosition,
+	reader: Option<BufReader<File>>,
+	line: String,
+}
+
+impl Spool {
+	/// Open or create the spool in the given directory. New records are
+	/// written to a new segment, segments are rotated after the configured
+	/// number of records.
+	pub async fn open(dir: &Path, settings: &SpoolSettings) -> Result<Arc<Self>> {
+		fs::create_dir_all(dir).await?;
+
+		let acked = match fs::read_to_string(dir.join(ACK_FILE)).await {
+			Ok(content) => {
+				let mut parts = content.split_whitespace();
+				let segment = parts.next().unwrap_or_default().parse()?;
+				let offset = parts.next().unwrap_or_default().parse()?;
+				SpoolPosition { segment, offset }
+			}
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound => SpoolPosition::default(),
+			Err(err) => return Err(err.into()),
+		};
+
+		let last_segment = Self::segments(dir).await?.into_iter().max();
+		let segment = last_segment.map_or(acked.segment, |last| last + 1).max(acked.segment);
+		let file = Self::create_segment(dir, segment).await?;
+		info!("Opened spool in {} at segment {}.", dir.display(), segment);
+
+		let spool = Arc::new(Spool {
+			dir: dir.to_owned(),
+			segment_size: settings.segment_size.max(1),
+			sync_interval: Duration::from_millis(settings.sync_ms),
+			max_attempts: settings.max_attempts.max(1),
+			writer: Mutex::new(SegmentWriter { segment, records: 0, unsynced: false, file }),
+			notify: Notify::new(),
+			acked: Mutex::new(acked),
+		});
+		if !spool.sync_interval.is_zero() {
+			tokio::spawn(Self::sync_periodically(Arc::downgrade(&spool)));
+		}
+		Ok(spool)
+	}
+
+	/// Sync the appended records every sync interval, until the spool is
+	/// dropped
+	async fn sync_periodically(spool: Weak<Self>) {
+		let interval = match spool.upgrade() {
+			Some(spool) => spool.sync_interval,
+			None => return,
+		};
+		let mut ticks = time::interval(interval);
+		ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			ticks.tick().await;
+			let spool = match spool.upgrade() {
+				Some(spool) => spool,
+				None => return,
+			};
+			let mut writer = spool.writer.lock().await;
+			if !writer.unsynced {
+				continue;
+			}
+			match writer.file.sync_data().await {
+				Ok(()) => writer.unsynced = false,
+				Err(err) => warn!("Syncing spool segment {} failed: {}", writer.segment, err),
+			}
+		}
+	}
+
+	/// Number of attempts to process events before failing events are moved
+	/// to the dead-letter file
+	pub fn max_attempts(&self) -> u32 {
+		self.max_attempts
+	}
+
+	/// Path of a segment file
+	fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+		dir.join(format!("{:016}.jsonl", segment))
+	}
+
+	/// Create a new, empty segment file
+	async fn create_segment(dir: &Path, segment: u64) -> Result<File> {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(Self::segment_path(dir, segment))
+			.await?;
+		Ok(file)
+	}
+
+	/// List the numbers of the existing segments
+	async fn segments(dir: &Path) -> Result<Vec<u64>> {
+		let mut segments = Vec::new();
+		let mut entries = fs::read_dir(dir).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			let name = entry.file_name();
+			if let Some(segment) = name.to_str().and_then(|name| name.strip_suffix(".jsonl")) {
+				if let Ok(segment) = segment.parse() {
+					segments.push(segment);
+				}
+			}
+		}
+		Ok(segments)
+	}
+
+	/// Append an event to the spool
+	pub async fn append(&self, event: &SourceEvent) -> Result<()> {
+		let mut line = serde_json::to_vec(event)?;
+		line.push(b'\n');
+
+		let mut writer = self.writer.lock().await;
+		if writer.records >= self.segment_size {
+			writer.file.sync_all().await?;
+			writer.segment += 1;
+			writer.records = 0;
+			writer.unsynced = false;
+			writer.file = Self::create_segment(&self.dir, writer.segment).await?;
+			debug!("Rotated spool to segment {}.", writer.segment);
+		}
+		writer.file.write_all(&line).await?;
+		writer.file.flush().await?;
+		writer.records += 1;
+		if self.sync_interval.is_zero() {
+			writer.file.sync_data().await?;
+		} else {
+			writer.unsynced = true;
+		}
+		drop(writer);
+
+		self.notify.notify_waiters();
+		Ok(())
+	}
+
+	/// Move an event that keeps failing processing to the dead-letter file. It
+	/// is still acknowledged with the events around it.
+	pub async fn dead_letter(&self, event: &SourceEvent) -> Result<()> {
+		let mut line = serde_json::to_vec(event)?;
+		line.push(b'\n');
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.dir.join(DEAD_LETTER_FILE))
+			.await?;
+		file.write_all(&line).await?;
+		file.sync_all().await?;
+		Ok(())
+	}
+
+	/// Acknowledge that all events up to the position have been processed.
+	/// Deletes segments that are fully processed.
+	pub async fn ack(&self, position: SpoolPosition) -> Result<()> {
+		let mut acked = self.acked.lock().await;
+		if position <= *acked {
+			return Ok(());
+		}
+
+		let tmp_path = self.dir.join(format!("{}.tmp", ACK_FILE));
+		fs::write(&tmp_path, format!("{} {}", position.segment, position.offset)).await?;
+		fs::rename(&tmp_path, self.dir.join(ACK_FILE)).await?;
+
+		for segment in acked.segment..position.segment {
+			match fs::remove_file(Self::segment_path(&self.dir, segment)).await {
+				Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+				_ => {}
+			}
+		}
+		*acked = position;
+		Ok(())
+	}
+
+	/// Read the events from the acknowledged position on, waiting for new
+	/// events at the end. Every event comes with the position after it to
+	/// acknowledge.
+	pub async fn read(
+		self: &Arc<Self>,
+	) -> BoxStream<'static, Result<(SourceEvent, SpoolPosition)>> {
+		let position = *self.acked.lock().await;
+		let state = ReadState { spool: self.clone(), position, reader: None, line: String::new() };
+		stream::try_unfold(state, |mut state| async move {
+			let event = state.next_event().await?;
+			Ok(Some(((event, state.position), state)))
+		})
+		.boxed()
+	}
+}
+
+impl ReadState {
+	/// Read the next event, waiting for it if necessary.
+	async fn next_event(&mut self) -> Result<SourceEvent> {
+		loop {
+			let reader = match self.reader.as_mut() {
+				Some(reader) => reader,
+				None => {
+					let path = Spool::segment_path(&self.spool.dir, self.position.segment);
+					let mut file = match File::open(&path).await {
+						Ok(file) => file,
+						Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+							self.wait_or_next_segment().await?;
+							continue;
+						}
+						Err(err) => return Err(err.into()),
+					};
+					file.seek(SeekFrom::Start(self.position.offset)).await?;
+					self.reader.insert(BufReader::new(file))
+				}
+			};
+
+			// A line without line break is not completely written yet and is
+			// continued on the next read.
+			reader.read_line(&mut self.line).await?;
+			if !self.line.ends_with('\n') {
+				self.wait_or_next_segment().await?;
+			}
+			if self.line.ends_with('\n') {
+				self.position.offset += self.line.len() as u64;
+				let event = serde_json::from_str(self.line.trim_end())?;
+				self.line.clear();
+				return Ok(event);
+			}
+		}
+	}
+
+	/// At the end of the segment: switch to the next segment if it exists,
+	/// otherwise wait for new events.
+	async fn wait_or_next_segment(&mut self) -> Result<()> {
+		let next = self.position.segment + 1;
+		if fs::metadata(Spool::segment_path(&self.spool.dir, next)).await.is_err() {
+			let _ = time::timeout(POLL_INTERVAL, self.spool.notify.notified()).await;
+			return Ok(());
+		}
+
+		// Segments are complete once the next one exists, so the rest of a
+		// partially read line is readable now. A line still without line break
+		// was torn by a crash while writing and is dropped.
+		if !self.line.is_empty() {
+			if let Some(reader) = self.reader.as_mut() {
+				reader.read_line(&mut self.line).await?;
+			}
+			if self.line.ends_with('\n') {
+				return Ok(());
+			}
+			warn!(
+				"Dropping torn record at the end of spool segment {}: {}",
+				self.position.segment, self.line
+			);
+			self.line.clear();
+		}
+		self.position = SpoolPosition { segment: next, offset: 0 };
+		self.reader = None;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::TryStreamExt;
+
+	use super::*;
+	use crate::source::SourceTweet;
+
+	fn tweet(id: u64) -> SourceEvent {
+		SourceTweet {
+			id,
+			created_at: 1_666_000_000,
+			text: format!("Tweet {}", id),
+			lang: None,
+			keywords: None,
+			meta: Default::default(),
+		}
+		.into()
+	}
+
+	fn settings(segment_size: usize, sync_ms: u64) -> SpoolSettings {
+		SpoolSettings { path: String::new(), segment_size, sync_ms, max_attempts: 3 }
+	}
+
+	async fn read_events(spool: &Arc<Spool>, count: usize) -> Result<Vec<SourceEvent>> {
+		let read = spool.read().await.take(count).map_ok(|(event, _)| event).try_collect();
+		time::timeout(Duration::from_secs(10), read).await?
+	}
+
+	#[tokio::test]
+	async fn reads_across_segments() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &settings(2, 100)).await?;
+		for id in 1..=5 {
+			spool.append(&tweet(id)).await?;
+		}
+		let events = read_events(&spool, 5).await?;
+		assert_eq!(events, (1..=5).map(tweet).collect::<Vec<_>>());
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn drops_torn_record_after_restart() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &settings(10, 100)).await?;
+		spool.append(&tweet(1)).await?;
+		// Crash while writing the second record
+		let mut file =
+			OpenOptions::new().append(true).open(Spool::segment_path(dir.path(), 0)).await?;
+		file.write_all(br#"{"event":"tweet","id":2,"cre"#).await?;
+		file.flush().await?;
+		drop(spool);
+
+		let spool = Spool::open(dir.path(), &settings(10, 100)).await?;
+		spool.append(&tweet(3)).await?;
+		let events = read_events(&spool, 2).await?;
+		assert_eq!(events, vec![tweet(1), tweet(3)]);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn syncs_appended_records() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &settings(10, 0)).await?;
+		spool.append(&tweet(1)).await?;
+		assert!(!spool.writer.lock().await.unsynced);
+
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &settings(10, 10)).await?;
+		spool.append(&tweet(1)).await?;
+		assert!(spool.writer.lock().await.unsynced);
+		time::timeout(Duration::from_secs(10), async {
+			while spool.writer.lock().await.unsynced {
+				time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await?;
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn keeps_dead_letters_out_of_the_segments() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &settings(10, 100)).await?;
+		spool.append(&tweet(1)).await?;
+		spool.dead_letter(&tweet(2)).await?;
+		spool.dead_letter(&tweet(3)).await?;
+		drop(spool);
+
+		let content = fs::read_to_string(dir.path().join(DEAD_LETTER_FILE)).await?;
+		let events = content
+			.lines()
+			.map(serde_json::from_str)
+			.collect::<serde_json::Result<Vec<SourceEvent>>>()?;
+		assert_eq!(events, vec![tweet(2), tweet(3)]);
+
+		let spool = Spool::open(dir.path(), &settings(10, 100)).await?;
+		spool.append(&tweet(4)).await?;
+		assert_eq!(read_events(&spool, 2).await?, vec![tweet(1), tweet(4)]);
+		Ok(())
+	}
+}
@@ -1,32 +1,54 @@
 //! Runner to receive the twitter streams and put sentiment data into the DB
 
-use std::{sync::Arc, time::Duration};
+use std::{
+	collections::BTreeMap,
+	io,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
 
 use color_eyre::Result;
 use derive_builder::Builder;
-use egg_mode::{search::ResultType, stream::StreamMessage, tweet::Tweet, Token};
-use futures::{future, TryStreamExt};
-use rust_bert::pipelines::sentiment::{Sentiment, SentimentPolarity};
-use tracing::{error, info, trace};
+use futures::{future, Stream, StreamExt, TryStreamExt};
+use rust_bert::pipelines::sentiment::Sentiment;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-	database::{self, SentimentDB},
-	settings::TwitterSettings,
+	backfill::Backfiller,
+	batch,
+	classifier::sentiment_to_float,
+	database::{self, ComplianceRecord, SentimentDB},
+	health::{Backoff, ErrorClass, StreamState, StreamStatus},
+	matcher::KeywordMatcher,
+	settings::{ConflictPolicy, QuotePolicy, RetweetPolicy, TwitterSettings},
+	source::{SourceEvent, SourceTweet, TweetMeta, TweetSource},
+	spool::Spool,
 	SentimentClassifier,
 };
 
-fn sentiment_to_float(sentiment: &Sentiment) -> f64 {
-	match sentiment.polarity {
-		SentimentPolarity::Positive => sentiment.score,
-		SentimentPolarity::Negative => -sentiment.score,
-	}
-}
-
 /// Runner to receive the twitter streams and put sentiment data into the DB
 #[derive(Debug, Builder)]
 pub struct TwitterStreamRunner {
 	config: TwitterSettings,
-	token: Token,
+	/// Keyword rules of the active keywords to attribute tweets to keywords.
+	/// The stream is reconnected when they change.
+	keywords: watch::Receiver<Arc<KeywordMatcher>>,
+	/// Source of the tweets to classify
+	source: Arc<dyn TweetSource>,
+	/// Backfill of historical tweets for the keywords, skipped if not set
+	#[builder(default, setter(strip_option))]
+	backfiller: Option<Backfiller>,
+	/// On-disk queue between receiving and processing tweets. Tweets are
+	/// processed directly if not set.
+	#[builder(default, setter(strip_option))]
+	spool: Option<Arc<Spool>>,
+	/// Health of the stream, shared with the webserver
+	status: StreamStatus,
 	sentiment_classifier: SentimentClassifier,
 	db: Arc<SentimentDB>,
 }
@@ -37,115 +59,639 @@ impl TwitterStreamRunner {
 		TwitterStreamRunnerBuilder::default()
 	}
 
-	/// If the database for a keyword is empty, fill it with some search results
+	/// Runner for retrieving/receiving tweets from Twitter. Backfill historical
+	/// tweets for the keywords and listen to Twitter's tweet streams for the
+	/// keywords and save the entries in the DB.
 	#[tracing::instrument(level = "debug", err, skip_all)]
-	pub async fn save_search_results(&self) -> Result<()> {
-		info!("Retrieving Twitter search results.");
-		for keyword in self.config.track_tweets.iter() {
-			if self.db.exists(keyword).await? {
-				continue;
+	pub async fn run(self) -> Result<()> {
+		let (_, stream_res, spool_res) =
+			future::join3(self.run_backfill(), self.run_stream(), self.run_spool()).await;
+		stream_res.and(spool_res)
+	}
+
+	/// Backfill the keywords and backfill again whenever the keywords change.
+	/// Keywords with a complete backfill only get the gap since their newest
+	/// tweet filled.
+	async fn run_backfill(&self) {
+		let backfiller = match &self.backfiller {
+			Some(backfiller) => backfiller,
+			None => return,
+		};
+		let mut keywords = self.keywords.clone();
+		loop {
+			let matcher = keywords.borrow_and_update().clone();
+			backfiller.run(&matcher).await;
+			if keywords.changed().await.is_err() {
+				break;
 			}
+		}
+	}
 
-			let search = egg_mode::search::search("rust")
-				.lang("en")
-				.result_type(ResultType::Mixed)
-				.count(10)
-				.call(&self.token)
-				.await?;
-			let tweets =
-				egg_mode::tweet::lookup(search.statuses.iter().map(|tweet| tweet.id), &self.token)
-					.await?;
+	/// Listen to the tweet stream, reconnect on errors and on keyword changes.
+	/// Waits with exponential backoff depending on the kind of error and gives
+	/// up on fatal errors, which is shown in the stream status.
+	async fn run_stream(&self) -> Result<()> {
+		let mut backoff = Backoff::new(Duration::from_secs(self.config.secs_reconnect));
+		loop {
+			self.status.set_state(StreamState::Connecting);
+			let err = match self.internal_run(&mut backoff).await {
+				Ok(()) => continue,
+				Err(err) => err,
+			};
 
-			let tweets = tweets.response;
-			let sentiments = self.predict_sentiment(&tweets).await?;
-			for (tweet, sentiment) in tweets.into_iter().zip(sentiments) {
-				let id = tweet.id;
-				let created = tweet.created_at.timestamp();
+			let class = ErrorClass::of(&err);
+			if class == ErrorClass::Fatal {
+				error!("Giving up on TwitterStreamRunner after fatal error: {}", err);
+				self.status.set_state(StreamState::Failed { error: err.to_string() });
+				return Ok(());
+			}
 
-				let entry = database::TweetSentiment::new(
-					id,
-					keyword.to_owned(),
-					created,
-					sentiment_to_float(&sentiment),
-				);
+			let delay = backoff.next_delay(class);
+			error!(
+				"Reconnecting in {:.1}s after {:?} error in TwitterStreamRunner: {}",
+				delay.as_secs_f64(),
+				class,
+				err
+			);
+			let until = OffsetDateTime::now_utc() + delay;
+			self.status.set_state(StreamState::BackingOff {
+				until: until.unix_timestamp(),
+				attempt: backoff.attempt(),
+				error: err.to_string(),
+			});
+			tokio::time::sleep(delay).await;
+		}
+	}
 
-				self.db.insert(entry).await?;
+	/// Listen to the tweet source's stream for the keywords and either save
+	/// the events in the spool or process them directly. Returns when the
+	/// keywords change, after the events received until then are processed.
+	/// The backoff is reset once the stream delivers messages.
+	#[tracing::instrument(level = "debug", err, skip_all)]
+	async fn internal_run(&self, backoff: &mut Backoff) -> Result<()> {
+		info!("Starting Twitter stream listener.");
+		let mut keywords = self.keywords.clone();
+		let matcher = keywords.borrow_and_update().clone();
+		// The stream ends when the keywords change, which flushes the partial
+		// batch.
+		let keywords_changed = AtomicBool::new(false);
+		let stop = Box::pin(async {
+			match keywords.changed().await {
+				Ok(()) => keywords_changed.store(true, Ordering::Relaxed),
+				Err(_closed) => future::pending().await,
+			}
+		});
+		let stream = self.source.stream(&matcher).take_until(stop).inspect_ok(|_| {
+			self.status.message_received();
+			backoff.reset();
+		});
+		let stream = stream.try_filter(|event| {
+			let keep = match event {
+				SourceEvent::Tweet(tweet) => !Self::tweet_keywords(&matcher, tweet).is_empty(),
+				_ => true,
+			};
+			future::ready(keep)
+		});
+
+		match &self.spool {
+			Some(spool) => {
+				stream.try_for_each(|event| async move { spool.append(&event).await }).await?;
+			}
+			None => {
+				self.batches(stream)
+					.try_for_each_concurrent(self.config.concurrency, |events| {
+						self.process_events(&matcher, events)
+					})
+					.await?;
 			}
 		}
+
+		if !keywords_changed.load(Ordering::Relaxed) {
+			// Treated like a dropped connection
+			return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Stream ended").into());
+		}
+		info!("Keywords changed, reconnecting.");
+		info!("Twitter stream listener stopped.");
 		Ok(())
 	}
 
-	/// Runner for retrieving/receiving tweets from Twitter. Save search results
-	/// for new keywords and listen to Twitter's tweet streams for the keywords
-	/// and save the entries in the DB.
+	/// Process the events in the spool, restarting on errors.
+	async fn run_spool(&self) -> Result<()> {
+		let spool = match &self.spool {
+			Some(spool) => spool,
+			None => return Ok(()),
+		};
+		loop {
+			if let Err(err) = self.process_spool(spool).await {
+				error!("Restarting soon after error in spool processing: {}", err);
+				tokio::time::sleep(Duration::from_secs(self.config.secs_reconnect)).await;
+			}
+		}
+	}
+
+	/// Read the events from the spool, process them and acknowledge them in
+	/// order.
 	#[tracing::instrument(level = "debug", err, skip_all)]
-	pub async fn run(self) -> Result<()> {
-		self.save_search_results().await?;
+	async fn process_spool(&self, spool: &Arc<Spool>) -> Result<()> {
+		info!("Starting spool processing.");
+		self.batches(spool.read().await)
+			.map_ok(|records| async move {
+				let position = records.last().map(|(_, position)| *position);
+				let events = records.into_iter().map(|(event, _)| event).collect();
+				self.process_spooled(spool, events).await?;
+				Ok::<_, color_eyre::Report>(position)
+			})
+			.try_buffered(self.config.concurrency)
+			.try_for_each(|position| async move {
+				match position {
+					Some(position) => spool.ack(position).await,
+					None => Ok(()),
+				}
+			})
+			.await
+	}
 
-		while let Err(err) = self.internal_run().await {
-			error!("Reconnecting soon after error in TwitterStreamRunner: {}", err);
+	/// Process events read from the spool, retrying failed batches. When a
+	/// batch failed every attempt, its events are processed one by one and
+	/// the ones that still fail are moved to the dead-letter file, so they do
+	/// not block the spool.
+	async fn process_spooled(&self, spool: &Spool, events: Vec<SourceEvent>) -> Result<()> {
+		let mut attempt = 1;
+		loop {
+			let matcher = self.keywords.borrow().clone();
+			let err = match self.process_events(&matcher, events.clone()).await {
+				Ok(()) => return Ok(()),
+				Err(err) => err,
+			};
+			if attempt >= spool.max_attempts() {
+				warn!(
+					"Processing spooled events one by one after {} failed attempts: {}",
+					attempt, err
+				);
+				break;
+			}
+			error!("Retrying spooled events soon after error in attempt {}: {}", attempt, err);
 			tokio::time::sleep(Duration::from_secs(self.config.secs_reconnect)).await;
+			attempt += 1;
 		}
 
+		let matcher = self.keywords.borrow().clone();
+		for event in events {
+			if let Err(err) = self.process_events(&matcher, vec![event.clone()]).await {
+				error!("Moving spooled event to the dead-letter file after error: {}", err);
+				spool.dead_letter(&event).await?;
+			}
+		}
 		Ok(())
 	}
 
-	/// Listen to Twitter's tweet streams for the keywords and save the entries
-	/// in the DB
-	#[tracing::instrument(level = "debug", err, skip_all)]
-	async fn internal_run(&self) -> Result<()> {
-		info!("Starting Twitter stream listener.");
-		let stream = egg_mode::stream::filter()
-			.track(&self.config.track_tweets)
-			.language(&["en"])
-			.start(&self.token);
-
-		let keywords: Vec<String> =
-			self.config.track_tweets.iter().map(|s| s.to_lowercase()).collect();
-
-		stream
-			.try_filter_map(|msg| {
-				if let StreamMessage::Tweet(tweet) = msg {
-					let text = tweet.text.to_lowercase();
-					if keywords.iter().any(|keyword| text.contains(keyword)) {
-						return future::ready(Ok(Some(tweet)));
+	/// Group the items of a stream into batches of the configured size,
+	/// flushing incomplete batches after the configured maximum latency. The
+	/// fill ratio of the batches is recorded in the stream status.
+	fn batches<'a, S, T>(&'a self, stream: S) -> impl Stream<Item = Result<Vec<T>>> + 'a
+	where
+		S: Stream<Item = Result<T>> + Unpin + 'a,
+		T: 'a,
+	{
+		let size = self.config.chunk_size;
+		let max_latency = Duration::from_millis(self.config.batch_latency_ms);
+		batch::batches(stream, size, max_latency).inspect_ok(move |batch| {
+			trace!("Flushing batch of {}/{} items.", batch.len(), size);
+			self.status.batch_flushed(batch.len(), size);
+		})
+	}
+
+	/// Process received events: classify and save the tweets, then apply the
+	/// compliance messages.
+	async fn process_events(
+		&self,
+		matcher: &KeywordMatcher,
+		events: Vec<SourceEvent>,
+	) -> Result<()> {
+		let mut tweets = Vec::new();
+		let mut compliance = Vec::new();
+		for event in events {
+			match event {
+				SourceEvent::Tweet(tweet) => tweets.push(tweet),
+				event => compliance.push(event),
+			}
+		}
+
+		if !tweets.is_empty() {
+			self.process_tweets(matcher, tweets).await?;
+		}
+		for event in compliance {
+			self.process_compliance(event).await?;
+		}
+		Ok(())
+	}
+
+	/// Remove or anonymise the data affected by a compliance message and
+	/// record it in the audit log.
+	async fn process_compliance(&self, event: SourceEvent) -> Result<()> {
+		let source = self.source.name();
+		let record = match event {
+			SourceEvent::Tweet(_) => return Ok(()),
+			SourceEvent::Delete { id, user_id } => {
+				let mut record =
+					ComplianceRecord::new(source.to_owned(), "delete", Some(id), user_id);
+				record.rows_affected = self.db.delete_tweet(source, id).await? as i64;
+				record
+			}
+			// No location data is stored, so there is nothing to remove.
+			SourceEvent::ScrubGeo { user_id, up_to_id } => {
+				ComplianceRecord::new(source.to_owned(), "scrub_geo", Some(up_to_id), Some(user_id))
+			}
+			SourceEvent::Withheld { id, user_id, countries } => {
+				let mut record =
+					ComplianceRecord::new(source.to_owned(), "withheld", id, Some(user_id));
+				record.countries = Some(countries.join(","));
+				record.rows_affected = self.db.anonymise_tweets(source, id, user_id).await? as i64;
+				record
+			}
+		};
+
+		debug!(
+			"Processed {} message for tweet {:?}, {} rows affected.",
+			record.kind, record.tweet_id, record.rows_affected
+		);
+		self.db.record_compliance(&record).await?;
+		Ok(())
+	}
+
+	/// Classify tweets and save the entries for their keywords in the DB in
+	/// one transaction. Entries of tweets that are stored already are handled
+	/// by the conflict policy and counted as duplicates.
+	async fn process_tweets(
+		&self,
+		matcher: &KeywordMatcher,
+		tweets: Vec<SourceTweet>,
+	) -> Result<()> {
+		trace!("New incoming Tweets: {}", tweets.len());
+
+		let tweets = self.apply_policies(tweets).await?;
+		if tweets.is_empty() {
+			return Ok(());
+		}
+		let texts: Vec<_> =
+			tweets.iter().map(|(tweet, _)| (tweet.text.clone(), tweet.lang.clone())).collect();
+		let sentiments = self.predict_sentiment(texts).await?;
+		let mut batch = database::EntryBatch::default();
+		let mut newest_ids = BTreeMap::new();
+		for ((tweet, kind), sentiment) in tweets.into_iter().zip(sentiments) {
+			let keywords = Self::tweet_keywords(matcher, &tweet);
+			if keywords.is_empty() {
+				continue;
+			}
+
+			batch.tweets.push(database::StoredTweet::new(
+				self.source.name().to_owned(),
+				&tweet,
+				self.config.store_text,
+			));
+
+			for keyword in keywords {
+				let mut entry = database::TweetSentiment::new(
+					tweet.id,
+					keyword,
+					self.source.name().to_owned(),
+					tweet.created_at,
+					sentiment_to_float(&sentiment),
+				);
+				entry.lang = tweet.lang.clone();
+				entry.model_version = self.sentiment_classifier.model_version();
+
+				if matches!(kind, EntryKind::Original | EntryKind::Retweet) {
+					let newest_id = newest_ids.entry(entry.keyword.clone()).or_insert(entry.id);
+					*newest_id = (*newest_id).max(entry.id);
+				}
+				match kind {
+					EntryKind::Original => batch.entries.push((entry, self.config.on_conflict)),
+					EntryKind::Retweet => {
+						entry.retweet = true;
+						batch.entries.push((entry, self.config.on_conflict));
 					}
+					EntryKind::Retweeted => batch.weighted.push(entry),
+					EntryKind::Quoted => batch.entries.push((entry, ConflictPolicy::Ignore)),
 				}
-				future::ready(Ok(None))
-			})
-			.try_chunks(self.config.chunk_size)
-			.map_err(color_eyre::Report::from)
-			.try_for_each_concurrent(self.config.concurrency, |tweets| async {
-				trace!("New incoming Tweets: {}", tweets.len());
-
-				let sentiments = self.predict_sentiment(&tweets).await?;
-				for (tweet, sentiment) in tweets.into_iter().zip(sentiments) {
-					let id = tweet.id;
-					let created = tweet.created_at.timestamp();
-					let text = tweet.text.to_lowercase();
-
-					for keyword in keywords.iter().filter(|keyword| text.contains(*keyword)) {
-						let entry = database::TweetSentiment::new(
-							id,
-							keyword.to_owned(),
-							created,
-							sentiment_to_float(&sentiment),
-						);
-
-						self.db.insert(entry).await?;
+			}
+		}
+
+		let duplicates = self.db.insert_batch(batch).await?.duplicates();
+		if duplicates > 0 {
+			debug!("{} entries were stored already.", duplicates);
+			self.status.duplicates_found(duplicates);
+		}
+		// The backfill searches the tweets after the newest streamed ones on the
+		// next start.
+		if self.backfiller.is_some() {
+			for (keyword, newest_id) in newest_ids {
+				self.db.save_newest_id(&keyword, newest_id).await?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Apply the retweet and quote policies to received tweets. Returns the
+	/// tweets to classify and store with the kind of their entries. Retweets
+	/// of stored tweets only add weight to the stored entries if configured.
+	async fn apply_policies(
+		&self,
+		tweets: Vec<SourceTweet>,
+	) -> Result<Vec<(SourceTweet, EntryKind)>> {
+		let mut prepared = Vec::with_capacity(tweets.len());
+		for mut tweet in tweets {
+			if let Some(retweeted_id) = tweet.meta.retweet_of {
+				match self.config.retweets {
+					RetweetPolicy::Drop => {}
+					RetweetPolicy::Separate => prepared.push((tweet, EntryKind::Retweet)),
+					RetweetPolicy::Weight => {
+						if self.db.add_weight(self.source.name(), retweeted_id).await? == 0 {
+							let text = tweet.text.clone();
+							let retweeted = Self::referenced_tweet(&tweet, retweeted_id, text);
+							prepared.push((retweeted, EntryKind::Retweeted));
+						}
 					}
 				}
-				Ok(())
-			})
-			.await?;
+				continue;
+			}
 
-		info!("Twitter stream listener stopped.");
+			if let (Some(quoted_id), Some(quoted_text)) =
+				(tweet.meta.quote_of, tweet.meta.quoted_text.clone())
+			{
+				match self.config.quotes {
+					QuotePolicy::Commentary => {}
+					QuotePolicy::Combined => {
+						tweet.text = format!("{}\n\n{}", tweet.text, quoted_text);
+					}
+					QuotePolicy::Separate => {
+						let quoted = Self::referenced_tweet(&tweet, quoted_id, quoted_text);
+						prepared.push((quoted, EntryKind::Quoted));
+					}
+				}
+			}
+			prepared.push((tweet, EntryKind::Original));
+		}
+		Ok(prepared)
+	}
+
+	/// Tweet referenced by a received tweet, with the data known from the
+	/// received tweet. The metadata is unknown, as it belongs to the received
+	/// tweet.
+	fn referenced_tweet(tweet: &SourceTweet, id: u64, text: String) -> SourceTweet {
+		SourceTweet {
+			id,
+			created_at: tweet.created_at,
+			text,
+			lang: tweet.lang.clone(),
+			keywords: tweet.keywords.clone(),
+			meta: TweetMeta::default(),
+		}
+	}
+
+	/// Get the keywords a tweet belongs to. Keywords the source attributed the
+	/// tweet to are trusted, as the source can match by more than the text,
+	/// e.g. by author or expanded links; only keywords that are still tracked
+	/// are kept. Otherwise the keyword rules are applied to the text, including
+	/// their languages.
+	fn tweet_keywords(matcher: &KeywordMatcher, tweet: &SourceTweet) -> Vec<String> {
+		match &tweet.keywords {
+			Some(attributed) => {
+				let mut keywords: Vec<String> = attributed
+					.iter()
+					.filter(|keyword| matcher.contains(keyword))
+					.map(|keyword| keyword.to_lowercase())
+					.collect();
+				keywords.sort();
+				keywords.dedup();
+				keywords
+			}
+			None => matcher.matches_lang(&tweet.text, tweet.lang.as_deref()),
+		}
+	}
+
+	/// Predict sentiment of some tweet texts with their languages
+	async fn predict_sentiment(
+		&self,
+		texts: Vec<(String, Option<String>)>,
+	) -> Result<Vec<Sentiment>> {
+		self.sentiment_classifier.predict_lang(texts).await
+	}
+}
+
+/// Kind of the entries stored for a classified tweet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+	/// A received tweet
+	Original,
+	/// A retweet, flagged as such
+	Retweet,
+	/// A retweeted tweet that was not stored yet, created from a retweet
+	Retweeted,
+	/// A quoted tweet, created from a quote tweet
+	Quoted,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use serde_json::{json, Value};
+	use tempfile::NamedTempFile;
+	use tokio::task::JoinHandle;
+
+	use super::*;
+	use crate::{
+		database::{EntryFilter, TweetSentiment},
+		settings::{KeywordRule, ReplaySettings, SpoolSettings},
+		source::ReplaySource,
+	};
+
+	fn tweet(id: u64, text: &str, keywords: Option<&[&str]>) -> SourceTweet {
+		SourceTweet {
+			id,
+			created_at: 1_666_000_000 + id as i64,
+			text: text.to_owned(),
+			lang: Some("en".to_owned()),
+			keywords: keywords
+				.map(|keywords| keywords.iter().map(|keyword| keyword.to_string()).collect()),
+			meta: TweetMeta::default(),
+		}
+	}
+
+	fn matcher(keywords: &[&str]) -> Result<Arc<KeywordMatcher>> {
+		let rules: Vec<_> =
+			keywords.iter().map(|keyword| KeywordRule::new(keyword.to_string())).collect();
+		Ok(Arc::new(KeywordMatcher::new(&rules)?))
+	}
+
+	fn spool_settings(max_attempts: u32) -> SpoolSettings {
+		SpoolSettings { path: String::new(), segment_size: 2, sync_ms: 100, max_attempts }
+	}
+
+	/// Runner replaying the tweets from the file over the memory backend,
+	/// optionally with a spool and with the settings overriding the defaults
+	fn spawn_runner(
+		tweets: &NamedTempFile,
+		spool: Option<Arc<Spool>>,
+		keywords: watch::Receiver<Arc<KeywordMatcher>>,
+		settings: Value,
+	) -> Result<(Arc<SentimentDB>, JoinHandle<Result<()>>)> {
+		let replay = ReplaySettings {
+			path: tweets.path().display().to_string(),
+			speed: 0.0,
+			source: "twitter".to_owned(),
+		};
+		let mut config = json!({
+			"track_tweets": ["rust"],
+			"concurrency": 2,
+			"chunk_size": 2,
+			"batch_latency_ms": 10,
+			"secs_reconnect": 1,
+		});
+		if let (Some(config), Value::Object(settings)) = (config.as_object_mut(), settings) {
+			config.extend(settings);
+		}
+		let db = Arc::new(SentimentDB::in_memory());
+
+		let mut runner = TwitterStreamRunner::builder();
+		if let Some(spool) = spool {
+			runner.spool(spool);
+		}
+		let runner = runner
+			.config(serde_json::from_value(config)?)
+			.keywords(keywords)
+			.source(Arc::new(ReplaySource::new(&replay)))
+			.status(StreamStatus::new("twitter"))
+			.sentiment_classifier(SentimentClassifier::fake())
+			.db(db.clone())
+			.build()?;
+		Ok((db, tokio::spawn(runner.run())))
+	}
+
+	/// File with the tweets to replay
+	fn tweets_file(tweets: &[SourceTweet]) -> Result<NamedTempFile> {
+		let mut file = NamedTempFile::new()?;
+		for tweet in tweets {
+			writeln!(file, "{}", serde_json::to_string(tweet)?)?;
+		}
+		Ok(file)
+	}
+
+	/// Saved entries of the keyword once there are as many as expected
+	async fn wait_for_entries(db: &SentimentDB, expected: usize) -> Result<Vec<TweetSentiment>> {
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let entries = db.get("rust", &EntryFilter::default()).await?;
+				if entries.len() >= expected {
+					return Ok(entries);
+				}
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await?
+	}
+
+	/// Replay the tweets through a runner over the memory backend, optionally
+	/// with a spool, and return the saved entries of the keyword once there
+	/// are as many as expected.
+	async fn replay(
+		tweets: &[SourceTweet],
+		spool: Option<Arc<Spool>>,
+		expected: usize,
+	) -> Result<Vec<TweetSentiment>> {
+		let file = tweets_file(tweets)?;
+		let (_keywords, receiver) = watch::channel(matcher(&["rust"])?);
+		let (db, handle) = spawn_runner(&file, spool, receiver, json!({}))?;
+		let entries = wait_for_entries(&db, expected).await;
+		handle.abort();
+		entries
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn classifies_and_saves_replayed_tweets() -> Result<()> {
+		let tweets = [
+			tweet(1, "Rust is good", None),
+			tweet(2, "Nothing to see here", None),
+			tweet(3, "Rust is bad", None),
+			// Attributed by the source, so the text is not matched
+			tweet(4, "A good language", Some(&["Rust"])),
+			tweet(5, "Rust is good", Some(&["golang"])),
+		];
+		let entries = replay(&tweets, None, 3).await?;
+
+		let saved: Vec<_> = entries.iter().map(|entry| (entry.id, entry.sentiment)).collect();
+		assert_eq!(saved, vec![(1, 0.9), (3, -0.9), (4, 0.9)]);
+		assert!(entries.iter().all(|entry| entry.source == "twitter"));
+		assert!(entries.iter().all(|entry| entry.lang.as_deref() == Some("en")));
 		Ok(())
 	}
 
-	/// Predict sentiment of some tweets
-	async fn predict_sentiment(&self, tweets: &[Tweet]) -> Result<Vec<Sentiment>> {
-		let texts = tweets.iter().map(|tweet| tweet.text.clone()).collect();
-		self.sentiment_classifier.predict(texts).await
+	#[tokio::test(flavor = "multi_thread")]
+	async fn processes_replayed_tweets_through_spool() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &spool_settings(3)).await?;
+		let tweets: Vec<_> = (1..=7).map(|id| tweet(id, "Rust is good", None)).collect();
+		let entries = replay(&tweets, Some(spool), tweets.len()).await?;
+
+		let ids: Vec<_> = entries.iter().map(|entry| entry.id).collect();
+		assert_eq!(ids, (1..=7).collect::<Vec<_>>());
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn flushes_the_partial_batch_when_keywords_change() -> Result<()> {
+		let tweets = [tweet(1, "Rust is good", None), tweet(2, "Rust is bad", None)];
+		let file = tweets_file(&tweets)?;
+		let (keywords, receiver) = watch::channel(matcher(&["rust"])?);
+		let settings = json!({ "chunk_size": 10, "batch_latency_ms": 3_600_000 });
+		let (db, handle) = spawn_runner(&file, None, receiver, settings)?;
+
+		// The batch waits for more tweets.
+		tokio::time::sleep(Duration::from_millis(200)).await;
+		assert!(db.get("rust", &EntryFilter::default()).await?.is_empty());
+
+		keywords.send(matcher(&["rust", "golang"])?)?;
+		let entries = wait_for_entries(&db, 2).await;
+		handle.abort();
+		let ids: Vec<_> = entries?.iter().map(|entry| entry.id).collect();
+		assert_eq!(ids, vec![1, 2]);
+		Ok(())
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn moves_failing_spooled_tweets_to_dead_letters() -> Result<()> {
+		let dir = tempfile::tempdir()?;
+		let spool = Spool::open(dir.path(), &spool_settings(2)).await?;
+		let tweets = [
+			tweet(1, "Rust is unclassifiable", None),
+			tweet(2, "Rust is good", None),
+			tweet(3, "Rust is bad", None),
+		];
+		let file = tweets_file(&tweets)?;
+		let (_keywords, receiver) = watch::channel(matcher(&["rust"])?);
+		let (db, handle) = spawn_runner(&file, Some(spool), receiver, json!({}))?;
+
+		let entries = wait_for_entries(&db, 2).await;
+		let dead_letters = tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let path = dir.path().join("dead_letter.jsonl");
+				if let Ok(content) = tokio::fs::read_to_string(path).await {
+					if content.ends_with('\n') {
+						return content;
+					}
+				}
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await;
+		handle.abort();
+
+		let ids: Vec<_> = entries?.iter().map(|entry| entry.id).collect();
+		assert_eq!(ids, vec![2, 3]);
+		let dead_letters: Vec<SourceEvent> =
+			dead_letters?.lines().map(serde_json::from_str).collect::<serde_json::Result<_>>()?;
+		assert_eq!(dead_letters, vec![SourceEvent::Tweet(tweets[0].clone())]);
+		Ok(())
 	}
 }